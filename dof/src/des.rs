@@ -10,7 +10,7 @@ use zerocopy::LayoutVerified;
 
 use crate::dof::DOF_MAGIC;
 use crate::dof_bindings::*;
-use crate::{Error, Ident, Probe, Provider, Section};
+use crate::{DataEncoding, Error, Ident, Probe, Provider, Section};
 
 // Extract one or more null-terminated strings from the given byte slice.
 fn extract_strings(buf: &[u8], count: Option<usize>) -> Vec<String> {
@@ -145,9 +145,23 @@ pub fn deserialize_raw_sections(buf: &[u8]) -> Result<(dof_hdr, Vec<(dof_sec, Ve
 }
 
 /// Deserialize a `Section` from a slice of DOF bytes
+///
+/// The DOF must be encoded in the byte order of the host. An error is returned if the section's
+/// identifier specifies any other encoding, or a DOF version newer than that supported by this
+/// crate, whose layout may differ from the one expected here.
 pub fn deserialize_section(buf: &[u8]) -> Result<Section, Error> {
     let (file_header, section_headers) = deserialize_raw_headers(buf)?;
     let ident = Ident::try_from(&file_header.dofh_ident[..])?;
+    if ident.encoding != DataEncoding::default() {
+        return Err(Error::InvalidIdentifier);
+    }
+    let supported = crate::dof_bindings::DOF_VERSION as u8;
+    if ident.version > supported {
+        return Err(Error::DofVersionMismatch {
+            found: ident.version,
+            supported,
+        });
+    }
     let providers_list = parse_providers(&section_headers, &buf);
     let providers = providers_list
         .into_iter()
@@ -208,3 +222,86 @@ pub fn extract_dof_sections<P: AsRef<Path>>(path: P) -> Result<Vec<Section>, Err
         .map(|sect| Section::from_bytes(&sect))
         .collect()
 }
+
+#[cfg(test)]
+mod test {
+    use super::deserialize_section;
+    use crate::{DataEncoding, Error, Probe, Provider, Section};
+    use std::collections::BTreeMap;
+
+    fn make_section() -> Section {
+        let probe = Probe {
+            name: String::from("probe"),
+            function: String::from("func"),
+            address: 0x1234,
+            offsets: vec![0x10, 0x2000],
+            enabled_offsets: vec![0x4],
+            arguments: vec![String::from("uint8_t"), String::from("char*")],
+        };
+        let mut probes = BTreeMap::new();
+        probes.insert(probe.name.clone(), probe);
+        let provider = Provider {
+            name: String::from("provider"),
+            probes,
+        };
+        let mut section = Section::default();
+        section.providers.insert(provider.name.clone(), provider);
+        section
+    }
+
+    #[test]
+    fn test_round_trip_native_encoding() {
+        let section = make_section();
+        assert_eq!(section.ident.encoding, DataEncoding::default());
+        let expected_encoding = if cfg!(target_endian = "big") {
+            DataEncoding::BigEndian
+        } else {
+            DataEncoding::LittleEndian
+        };
+        assert_eq!(section.ident.encoding, expected_encoding);
+
+        let deserialized = deserialize_section(&section.as_bytes()).unwrap();
+        assert_eq!(deserialized.ident.encoding, expected_encoding);
+        let probe = &deserialized.providers["provider"].probes["probe"];
+        assert_eq!(probe.function, "func");
+        assert_eq!(probe.address, 0x1234);
+        assert_eq!(probe.offsets, vec![0x10, 0x2000]);
+        assert_eq!(probe.enabled_offsets, vec![0x4]);
+        assert_eq!(probe.arguments, vec!["uint8_t", "char*"]);
+    }
+
+    #[test]
+    fn test_deserialize_foreign_encoding() {
+        let mut section = make_section();
+        section.ident.encoding = if cfg!(target_endian = "big") {
+            DataEncoding::LittleEndian
+        } else {
+            DataEncoding::BigEndian
+        };
+        assert!(matches!(
+            deserialize_section(&section.as_bytes()),
+            Err(Error::InvalidIdentifier)
+        ));
+    }
+
+    #[test]
+    fn test_deserialize_newer_version() {
+        let mut section = make_section();
+        let supported = section.ident.version;
+        section.ident.version += 1;
+        match deserialize_section(&section.as_bytes()) {
+            Err(Error::DofVersionMismatch {
+                found,
+                supported: s,
+            }) => {
+                assert_eq!(found, supported + 1);
+                assert_eq!(s, supported);
+            }
+            other => panic!("Expected a version mismatch, found {:?}", other),
+        }
+
+        // Older versions are still accepted.
+        section.ident.version = crate::dof_bindings::DOF_VERSION_1 as u8;
+        assert!(deserialize_section(&section.as_bytes()).is_ok());
+    }
+}
@@ -24,6 +24,10 @@ pub enum Error {
     #[error("invalid DOF identifier (magic bytes, endianness, or version)")]
     InvalidIdentifier,
 
+    /// The DOF section is of a newer version than this crate supports
+    #[error("unsupported DOF version {found}, expected at most {supported}")]
+    DofVersionMismatch { found: u8, supported: u8 },
+
     /// An error occurred parsing a type from an underlying byte slice
     #[error("data does not match expected struct layout or is misaligned")]
     ParseError,
@@ -43,7 +47,7 @@ pub enum Error {
 }
 
 /// Represents the DTrace data model, e.g. the pointer width of the platform
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[repr(u8)]
 pub enum DataModel {
     None = 0,
@@ -74,7 +78,7 @@ impl TryFrom<u8> for DataModel {
 }
 
 /// Represents the endianness of the platform
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[repr(u8)]
 pub enum DataEncoding {
     None = 0,
@@ -177,9 +181,25 @@ impl Section {
     }
 
     /// Serialize a section into DOF object file section.
+    ///
+    /// All fields are written in the byte order of the host, which is the order DTrace expects of
+    /// DOF loaded into a running process. The `ident` should therefore specify the default
+    /// encoding.
     pub fn as_bytes(&self) -> Vec<u8> {
         crate::ser::serialize_section(&self)
     }
+
+    /// Return the provider with the given name, if any.
+    pub fn provider(&self, name: &str) -> Option<&Provider> {
+        self.providers.get(name)
+    }
+
+    /// Return an iterator over every probe in the section, ordered by provider and probe name.
+    pub fn probes(&self) -> impl Iterator<Item = &Probe> {
+        self.providers
+            .values()
+            .flat_map(|provider| provider.probes.values())
+    }
 }
 
 impl Default for Section {
@@ -187,8 +207,8 @@ impl Default for Section {
         Self {
             ident: Ident {
                 magic: DOF_MAGIC,
-                model: DataModel::LP64,
-                encoding: DataEncoding::LittleEndian,
+                model: DataModel::default(),
+                encoding: DataEncoding::default(),
                 version: crate::dof_bindings::DOF_VERSION as u8,
                 dif_vers: crate::dof_bindings::DIF_VERSION as u8,
                 dif_ireg: crate::dof_bindings::DIF_DIR_NREGS as u8,
@@ -224,3 +244,56 @@ pub struct Provider {
     /// List of probes this provider exports
     pub probes: BTreeMap<String, Probe>,
 }
+
+impl Provider {
+    /// Return the probe with the given name, if any.
+    pub fn probe(&self, name: &str) -> Option<&Probe> {
+        self.probes.get(name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn provider(name: &str, probe_names: &[&str]) -> Provider {
+        let probes = probe_names
+            .iter()
+            .map(|probe| {
+                let probe = Probe {
+                    name: probe.to_string(),
+                    function: String::from("func"),
+                    address: 0,
+                    offsets: vec![0],
+                    enabled_offsets: vec![],
+                    arguments: vec![],
+                };
+                (probe.name.clone(), probe)
+            })
+            .collect();
+        Provider {
+            name: name.to_string(),
+            probes,
+        }
+    }
+
+    #[test]
+    fn test_section_accessors() {
+        let mut section = Section::default();
+        for provider in [provider("second", &["c"]), provider("first", &["b", "a"])] {
+            section.providers.insert(provider.name.clone(), provider);
+        }
+
+        let names = section
+            .probes()
+            .map(|probe| probe.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b", "c"]);
+
+        let first = section.provider("first").unwrap();
+        assert_eq!(first.name, "first");
+        assert_eq!(first.probe("b").unwrap().name, "b");
+        assert!(first.probe("c").is_none());
+        assert!(section.provider("third").is_none());
+    }
+}
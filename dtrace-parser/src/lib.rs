@@ -4,7 +4,7 @@
 use std::collections::HashSet;
 use std::convert::TryFrom;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use pest::iterators::{Pair, Pairs};
 use pest_derive::Parser;
@@ -25,6 +25,8 @@ pub enum DTraceError {
     InvalidProviderName(String),
     #[error("The probe name \"{0}\" is invalid")]
     InvalidProbeName(String),
+    #[error("The file {0:?} includes itself")]
+    IncludeCycle(PathBuf),
     #[error(transparent)]
     IO(#[from] std::io::Error),
     #[error("Input is not a valid DTrace provider definition:\n{0}")]
@@ -151,11 +153,53 @@ impl DataType {
     }
 }
 
+/// The optional name and documentation of a single probe argument.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Argument {
+    /// The name of the argument, if one is given in its declaration.
+    pub name: Option<String>,
+    /// The text of any documentation comments (`/** ... */`) following the argument.
+    pub docs: Option<String>,
+}
+
 /// Type representing a single D probe definition within a provider.
 #[derive(Clone, Debug, PartialEq)]
 pub struct Probe {
     pub name: String,
     pub types: Vec<DataType>,
+    /// The name and documentation of each argument, in the same order as `types`.
+    pub arguments: Vec<Argument>,
+    /// The text of any documentation comments (`/** ... */`) preceding the probe.
+    pub docs: Option<String>,
+}
+
+// Append the text of another documentation comment to any existing documentation.
+fn append_docs(docs: &mut Option<String>, text: String) {
+    *docs = Some(match docs.take() {
+        Some(docs) => format!("{}\n{}", docs, text),
+        None => text,
+    });
+}
+
+// Extract the text of a documentation comment, removing the delimiters and any leading `*` on each
+// line, while preserving the line structure.
+fn doc_comment_text(comment: &str) -> String {
+    let body = comment
+        .trim_start_matches("/**")
+        .trim_end_matches("*/")
+        .lines()
+        .map(|line| {
+            let line = line.trim();
+            let line = line.strip_prefix('*').unwrap_or(line);
+            line.strip_prefix(' ').unwrap_or(line).trim_end()
+        })
+        .collect::<Vec<_>>();
+    let start = body.iter().position(|line| !line.is_empty());
+    let end = body.iter().rposition(|line| !line.is_empty());
+    match (start, end) {
+        (Some(start), Some(end)) => body[start..=end].join("\n"),
+        _ => String::new(),
+    }
 }
 
 impl TryFrom<&Pair<'_, Rule>> for Probe {
@@ -163,7 +207,11 @@ impl TryFrom<&Pair<'_, Rule>> for Probe {
 
     fn try_from(pair: &Pair<'_, Rule>) -> Result<Self, Self::Error> {
         expect_token(pair, Rule::PROBE)?;
-        let mut inner = pair.clone().into_inner();
+        let mut inner = pair.clone().into_inner().peekable();
+        let mut docs: Option<String> = None;
+        while let Some(comment) = inner.next_if(|pair| pair.as_rule() == Rule::DOC_COMMENT) {
+            append_docs(&mut docs, doc_comment_text(comment.as_str()));
+        }
         expect_token(
             &inner.next().expect("Expected the literal 'probe'"),
             Rule::PROBE_KEY,
@@ -181,11 +229,24 @@ impl TryFrom<&Pair<'_, Rule>> for Probe {
             .next()
             .expect("Expected an argument list or literal ')'");
         let mut types = Vec::new();
+        let mut arguments: Vec<Argument> = Vec::new();
         if expect_token(&possibly_argument_list, Rule::ARGUMENT_LIST).is_ok() {
-            let arguments = possibly_argument_list.clone().into_inner();
-            for data_type in arguments {
-                expect_token(&data_type, Rule::DATA_TYPE)?;
-                types.push(DataType::try_from(&data_type)?);
+            for pair in possibly_argument_list.clone().into_inner() {
+                if pair.as_rule() == Rule::DOC_COMMENT {
+                    if let Some(argument) = arguments.last_mut() {
+                        append_docs(&mut argument.docs, doc_comment_text(pair.as_str()));
+                    }
+                    continue;
+                }
+                expect_token(&pair, Rule::ARGUMENT)?;
+                let mut argument = pair.into_inner();
+                types.push(DataType::try_from(
+                    &argument.next().expect("Expected an argument type"),
+                )?);
+                arguments.push(Argument {
+                    name: argument.next().map(|name| name.as_str().to_string()),
+                    docs: None,
+                });
             }
         }
         expect_token(
@@ -196,7 +257,51 @@ impl TryFrom<&Pair<'_, Rule>> for Probe {
             &inner.next().expect("Expected a literal ';'"),
             Rule::SEMICOLON,
         )?;
-        Ok(Probe { name, types })
+        Ok(Probe {
+            name,
+            types,
+            arguments,
+            docs,
+        })
+    }
+}
+
+impl Probe {
+    /// Return the representation of this probe in D source code, including any documentation.
+    pub fn to_d_source(&self) -> String {
+        let types = self
+            .types
+            .iter()
+            .zip(
+                self.arguments
+                    .iter()
+                    .chain(std::iter::repeat(&Argument::default())),
+            )
+            .map(|(typ, argument)| {
+                let mut decl = typ.to_c_type();
+                if let Some(name) = &argument.name {
+                    decl = format!("{} {}", decl, name);
+                }
+                if let Some(docs) = &argument.docs {
+                    decl = format!("{} /** {} */", decl, docs.replace('\n', "\n * "));
+                }
+                decl
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let probe = format!("probe {}({});", self.name, types);
+        match self.docs.as_deref() {
+            None => probe,
+            Some(docs) if !docs.contains('\n') => format!("/** {} */\n{}", docs, probe),
+            Some(docs) => {
+                let lines = docs
+                    .lines()
+                    .map(|line| format!(" * {}", line).trim_end().to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                format!("/**\n{}\n */\n{}", lines, probe)
+            }
+        }
     }
 }
 
@@ -238,13 +343,15 @@ impl TryFrom<&Pair<'_, Rule>> for Provider {
             Rule::LEFT_BRACE,
         )?;
         let mut probes = Vec::new();
-        let mut possibly_probe = inner
-            .next()
-            .expect("Expected at least one probe in the provider");
+        let mut possibly_probe = inner.next().expect("Expected a probe or a literal '}'");
         while expect_token(&possibly_probe, Rule::PROBE).is_ok() {
             probes.push(Probe::try_from(&possibly_probe)?);
             possibly_probe = inner.next().expect("Expected a token");
         }
+        // Skip any documentation comments which aren't followed by a probe.
+        while expect_token(&possibly_probe, Rule::DOC_COMMENT).is_ok() {
+            possibly_probe = inner.next().expect("Expected a token");
+        }
         expect_token(&possibly_probe, Rule::RIGHT_BRACE)?;
         expect_token(
             &inner.next().expect("Expected a literal ';'"),
@@ -254,6 +361,24 @@ impl TryFrom<&Pair<'_, Rule>> for Provider {
     }
 }
 
+impl Provider {
+    /// Return the representation of this provider in D source code.
+    pub fn to_d_source(&self) -> String {
+        let probes = self
+            .probes
+            .iter()
+            .map(|probe| {
+                probe
+                    .to_d_source()
+                    .lines()
+                    .map(|line| format!("\t{}\n", line))
+                    .collect::<String>()
+            })
+            .collect::<String>();
+        format!("provider {} {{\n{}}};", self.name, probes)
+    }
+}
+
 impl TryFrom<&Pairs<'_, Rule>> for Provider {
     type Error = DTraceError;
 
@@ -266,6 +391,8 @@ impl TryFrom<&Pairs<'_, Rule>> for Provider {
 #[derive(Debug, Clone, PartialEq)]
 pub struct File {
     name: String,
+    pragmas: Vec<String>,
+    translators: Vec<String>,
     providers: Vec<Provider>,
 }
 
@@ -274,10 +401,16 @@ impl TryFrom<&Pair<'_, Rule>> for File {
 
     fn try_from(pair: &Pair<'_, Rule>) -> Result<Self, Self::Error> {
         expect_token(&pair, Rule::FILE)?;
+        let mut pragmas = Vec::new();
+        let mut translators = Vec::new();
         let mut providers = Vec::new();
         let mut names = HashSet::new();
         for item in pair.clone().into_inner() {
-            if item.as_rule() == Rule::PROVIDER {
+            if item.as_rule() == Rule::PRAGMA {
+                pragmas.push(item.as_str().trim().to_string());
+            } else if item.as_rule() == Rule::TRANSLATOR {
+                translators.push(item.as_str().to_string());
+            } else if item.as_rule() == Rule::PROVIDER {
                 let provider = Provider::try_from(&item)?;
                 for probe in provider.probes.iter() {
                     let name = (provider.name.clone(), probe.name.clone());
@@ -292,6 +425,8 @@ impl TryFrom<&Pair<'_, Rule>> for File {
 
         Ok(File {
             name: "".to_string(),
+            pragmas,
+            translators,
             providers,
         })
     }
@@ -305,10 +440,58 @@ impl TryFrom<&Pairs<'_, Rule>> for File {
     }
 }
 
+/// Read the D file at the given path, replacing each `#include "file"` directive with the contents
+/// of the named file.
+///
+/// Included paths are resolved relative to the directory of the including file, and may
+/// themselves contain further includes. Other preprocessor directives are left untouched.
+///
+/// Returns the expanded source, along with the path of every file read to produce it, starting
+/// with `filename` itself. Build scripts can use the latter to rerun when any of them change.
+pub fn expand_includes(filename: &Path) -> Result<(String, Vec<PathBuf>), DTraceError> {
+    let mut files = Vec::new();
+    let source = expand_includes_impl(filename, &mut Vec::new(), &mut files)?;
+    Ok((source, files))
+}
+
+fn expand_includes_impl(
+    filename: &Path,
+    stack: &mut Vec<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> Result<String, DTraceError> {
+    let canonical = fs::canonicalize(filename)?;
+    if stack.contains(&canonical) {
+        return Err(DTraceError::IncludeCycle(filename.to_path_buf()));
+    }
+    let source = fs::read_to_string(filename)?;
+    stack.push(canonical);
+    files.push(filename.to_path_buf());
+    let dir = filename.parent().unwrap_or_else(|| Path::new(""));
+    let mut expanded = String::with_capacity(source.len());
+    for line in source.lines() {
+        match include_path(line) {
+            Some(path) => expanded.push_str(&expand_includes_impl(&dir.join(path), stack, files)?),
+            None => expanded.push_str(line),
+        }
+        expanded.push('\n');
+    }
+    stack.pop();
+    Ok(expanded)
+}
+
+// Return the quoted path named by a line of the form `#include "path"`, if any.
+fn include_path(line: &str) -> Option<&str> {
+    let rest = line.trim().strip_prefix('#')?.trim_start();
+    let rest = rest.strip_prefix("include")?.trim();
+    rest.strip_prefix('"')?.strip_suffix('"')
+}
+
 impl File {
     /// Load and parse a provider from a D file at the given path.
+    ///
+    /// Any `#include "file"` directives are resolved before parsing, see [`expand_includes`].
     pub fn from_file(filename: &Path) -> Result<Self, DTraceError> {
-        let mut f = File::try_from(fs::read_to_string(filename)?.as_str())?;
+        let mut f = File::try_from(expand_includes(filename)?.0.as_str())?;
         f.name = filename
             .file_stem()
             .unwrap()
@@ -327,6 +510,33 @@ impl File {
     pub fn providers(&self) -> &Vec<Provider> {
         &self.providers
     }
+
+    /// Return the `#pragma` directives in the file, each including the `#pragma` keyword.
+    pub fn pragmas(&self) -> &Vec<String> {
+        &self.pragmas
+    }
+
+    /// Return the `translator` definitions in the file, each exactly as written.
+    pub fn translators(&self) -> &Vec<String> {
+        &self.translators
+    }
+
+    /// Return the representation of this file in D source code.
+    ///
+    /// The source is canonically formatted, with all pragmas preceding any translators, which
+    /// precede the providers. Translators are reproduced exactly as written. The documentation
+    /// comments of probes are retained, but any other comments are not.
+    pub fn to_d_source(&self) -> String {
+        let mut items = Vec::new();
+        if !self.pragmas.is_empty() {
+            items.push(self.pragmas.join("\n"));
+        }
+        items.extend(self.translators.iter().cloned());
+        items.extend(self.providers.iter().map(Provider::to_d_source));
+        let mut source = items.join("\n\n");
+        source.push('\n');
+        source
+    }
 }
 
 impl TryFrom<&str> for File {
@@ -362,7 +572,10 @@ impl TryFrom<&str> for File {
 
 #[cfg(test)]
 mod tests {
-    use super::{DTraceParser, DataType, File, Probe, Provider, Rule, TryFrom};
+    use super::{
+        expand_includes, include_path, DTraceError, DTraceParser, DataType, File, Path, Probe,
+        Provider, Rule, TryFrom,
+    };
     use ::pest::Parser;
     use rstest::{fixture, rstest};
 
@@ -427,7 +640,11 @@ mod tests {
     #[test]
     fn test_null_provider() {
         let defn = "provider foo { };";
-        assert!(DTraceParser::parse(Rule::FILE, defn).is_err());
+        assert!(DTraceParser::parse(Rule::FILE, defn).is_ok());
+        let file = File::try_from(defn).unwrap();
+        assert_eq!(file.providers().len(), 1);
+        assert_eq!(file.providers()[0].name, "foo");
+        assert!(file.providers()[0].probes.is_empty());
     }
 
     #[test]
@@ -441,6 +658,110 @@ mod tests {
         assert!(DTraceParser::parse(Rule::FILE, defn).is_ok());
     }
 
+    #[test]
+    fn test_doc_comment_provider() {
+        let defn = r#"
+            /** A dangling doc comment */
+            provider foo {
+                /** Fired when work begins. */
+                probe bar();
+                /**
+                 * Fired when work ends.
+                 *
+                 * The argument is the result of the work.
+                 */
+                probe baz(char*);
+                /* Not a doc comment */
+                probe quux();
+                /**/
+                probe quack();
+                /** Another dangling doc comment */
+            };"#;
+        let file = File::try_from(defn).unwrap();
+        let probes = &file.providers()[0].probes;
+        assert_eq!(probes[0].docs.as_deref(), Some("Fired when work begins."));
+        assert_eq!(
+            probes[1].docs.as_deref(),
+            Some("Fired when work ends.\n\nThe argument is the result of the work.")
+        );
+        assert!(probes[2].docs.is_none());
+        assert!(probes[3].docs.is_none());
+    }
+
+    #[test]
+    fn test_unattached_doc_comments_are_comments() {
+        let defns = [
+            "provider /** x */ foo { probe bar(uint8_t); };",
+            "provider foo { probe /** mid */ bar(uint8_t); };",
+            "provider foo { probe bar(uint8_t) /** after */; };",
+            "provider foo /** x */ { probe bar(uint8_t); } /** y */;",
+        ];
+        for defn in defns.iter() {
+            let file = File::try_from(*defn).unwrap();
+            let probe = &file.providers()[0].probes[0];
+            assert_eq!(probe.name, "bar");
+            assert_eq!(probe.types, &[DataType::U8]);
+            assert!(probe.docs.is_none());
+            assert!(probe.arguments[0].docs.is_none());
+        }
+    }
+
+    #[test]
+    fn test_leading_argument_doc_comments() {
+        let defn = "provider foo { probe bar(/** first */ uint8_t /** arg */, uint16_t); };";
+        let file = File::try_from(defn).unwrap();
+        let probe = &file.providers()[0].probes[0];
+        assert_eq!(probe.types, &[DataType::U8, DataType::U16]);
+        assert_eq!(probe.arguments[0].docs.as_deref(), Some("arg"));
+        assert!(probe.arguments[1].docs.is_none());
+
+        let defn = "provider foo { probe bar(/** none */); };";
+        let file = File::try_from(defn).unwrap();
+        assert!(file.providers()[0].probes[0].types.is_empty());
+    }
+
+    #[test]
+    fn test_argument_names_and_docs() {
+        let defn = r#"
+            provider foo {
+                probe bar(
+                    uint64_t request_id, /** The ID of the request. */
+                    uint64_t bytes /** The number of bytes
+                                    * transferred. */,
+                    char *
+                );
+            };"#;
+        let file = File::try_from(defn).unwrap();
+        let probe = &file.providers()[0].probes[0];
+        assert_eq!(
+            probe.types,
+            &[DataType::U64, DataType::U64, DataType::String]
+        );
+        let names = probe
+            .arguments
+            .iter()
+            .map(|arg| arg.name.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(names, &[Some("request_id"), Some("bytes"), None]);
+        assert_eq!(
+            probe.arguments[0].docs.as_deref(),
+            Some("The ID of the request.")
+        );
+        assert_eq!(
+            probe.arguments[1].docs.as_deref(),
+            Some("The number of bytes\ntransferred.")
+        );
+        assert!(probe.arguments[2].docs.is_none());
+
+        let source = probe.to_d_source();
+        assert!(source.starts_with(
+            "probe bar(uint64_t request_id /** The ID of the request. */, uint64_t bytes"
+        ));
+        let reparsed =
+            Probe::try_from(&DTraceParser::parse(Rule::PROBE, &source).unwrap()).unwrap();
+        assert_eq!(&reparsed, probe);
+    }
+
     #[test]
     fn test_pragma_provider() {
         let defn = r#"
@@ -544,6 +865,42 @@ mod tests {
         assert_eq!(provider.probes[0].name, "baz");
     }
 
+    #[test]
+    fn test_file_with_includes() {
+        let file = File::from_file(Path::new(&data_file("includes.d"))).unwrap();
+        assert_eq!(file.name(), "includes");
+        assert_eq!(file.translators().len(), 1);
+        assert!(file.translators()[0].starts_with("translator conninfo_t"));
+        assert_eq!(file.providers().len(), 1);
+        assert_eq!(file.providers()[0].name, "conn");
+        assert_eq!(file.providers()[0].probes[0].name, "accept");
+    }
+
+    #[test]
+    fn test_expand_includes_files() {
+        let (_, files) = expand_includes(Path::new(&data_file("includes.d"))).unwrap();
+        let names = files
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["includes.d", "shared.d"]);
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let err = File::from_file(Path::new(&data_file("cycle.d"))).unwrap_err();
+        assert!(matches!(err, DTraceError::IncludeCycle(_)));
+    }
+
+    #[test]
+    fn test_include_path() {
+        assert_eq!(include_path(r#"#include "shared.d""#), Some("shared.d"));
+        assert_eq!(include_path(r#"  # include "a/b.d"  "#), Some("a/b.d"));
+        assert_eq!(include_path("#include <sys/types.h>"), None);
+        assert_eq!(include_path("#pragma D option quiet"), None);
+        assert_eq!(include_path("probe include();"), None);
+    }
+
     #[test]
     fn test_file_struct() {
         let defn = r#"
@@ -568,4 +925,81 @@ mod tests {
 
         assert!(File::try_from("this is not a D file").is_err());
     }
+
+    #[test]
+    fn test_file_to_d_source_round_trip() {
+        let defn = r#"
+            /* a comment */
+            #pragma D option quiet
+            provider foo {
+                /** Fired on entry. */
+                probe quux();
+                /**
+                 * Fired with
+                 *
+                 * some data.
+                 */
+                probe quack(char*, uint16_t, int8_t);
+            };
+            #pragma D attributes Evolving/Evolving/ISA provider foo provider
+            provider bar { probe baz(); };
+            "#;
+        let file = File::try_from(defn).unwrap();
+        assert_eq!(file.pragmas().len(), 2);
+        let source = file.to_d_source();
+        assert_eq!(
+            source,
+            concat!(
+                "#pragma D option quiet\n",
+                "#pragma D attributes Evolving/Evolving/ISA provider foo provider\n",
+                "\n",
+                "provider foo {\n",
+                "\t/** Fired on entry. */\n",
+                "\tprobe quux();\n",
+                "\t/**\n",
+                "\t * Fired with\n",
+                "\t *\n",
+                "\t * some data.\n",
+                "\t */\n",
+                "\tprobe quack(char*, uint16_t, int8_t);\n",
+                "};\n",
+                "\n",
+                "provider bar {\n",
+                "\tprobe baz();\n",
+                "};\n",
+            )
+        );
+        assert_eq!(File::try_from(source.as_str()).unwrap(), file);
+    }
+
+    #[test]
+    fn test_file_translator() {
+        let translator = concat!(
+            "translator conninfo_t < struct conn *c > {\n",
+            "\tci_port = c->port;\n",
+            "\tci_name = copyinstr((uintptr_t) c->name);\n",
+            "};"
+        );
+        let defn = format!(
+            "#pragma D option quiet\n{}\nprovider foo {{ probe bar(uint8_t); }};\n",
+            translator
+        );
+        let file = File::try_from(defn.as_str()).unwrap();
+        assert_eq!(file.translators(), &vec![translator.to_string()]);
+        assert_eq!(file.providers().len(), 1);
+
+        let source = file.to_d_source();
+        assert_eq!(
+            source,
+            format!(
+                "#pragma D option quiet\n\n{}\n\nprovider foo {{\n\tprobe bar(uint8_t);\n}};\n",
+                translator
+            )
+        );
+        assert_eq!(File::try_from(source.as_str()).unwrap(), file);
+
+        assert!(
+            File::try_from("translator conninfo_t < struct conn *c > { ci_port = 0; }").is_err()
+        );
+    }
 }
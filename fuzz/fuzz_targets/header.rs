@@ -0,0 +1,38 @@
+//! Fuzz the parsing of headers generated by `dtrace -h`, which must never panic.
+//!
+//! Run with `cargo +nightly fuzz run header` from the repository root.
+
+// Copyright 2021 Oxide Computer Company
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+// Lines resembling those in a real header, which the fuzzer splices into its input so that it
+// quickly reaches the interesting paths of the parser.
+const LINES: &[&str] = &[
+    "#define FOO_STABILITY \"___dtrace_stability$foo$v1$1_1_0_1_1_0_1_1_0_1_1_0_1_1_0\"",
+    "#define FOO_TYPEDEFS \"___dtrace_typedefs$foo$v2\"",
+    "extern void __dtrace_probe$foo$bar$v1$75696e74385f74(uint8_t);",
+    "extern int __dtrace_isenabled$foo$bar$v1(void);",
+];
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(text) = std::str::from_utf8(data) {
+        extract_providers(text);
+        let mut header = LINES.join("\n");
+        header.push_str(text);
+        extract_providers(&header);
+        for line in LINES.iter() {
+            extract_providers(&format!("{}{}", line, text));
+        }
+    }
+});
+
+// Extract the providers from a header, and decode the stability attributes of each.
+fn extract_providers(header: &str) {
+    if let Ok(providers) = usdt_impl::header::extract_providers(header) {
+        for info in providers.values() {
+            let _ = info.decode_stability();
+        }
+    }
+}
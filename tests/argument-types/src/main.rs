@@ -46,6 +46,47 @@ mod refs {
     /// Serializable types may also be taken by value or reference.
     fn serializable_as_value(_: crate::Arg) {}
     fn serializable_as_reference(_: &crate::Arg) {}
+
+    /// Probes may be fired from methods, with arguments derived from the receiver's fields.
+    fn method(_: u64, _: &str) {}
+
+    /// Arguments may be arbitrary expressions.
+    fn conditional(_: u8, _: u64) {}
+}
+
+/// Custom types may be passed to probes expecting an integer, by implementing `IntoProbeArg`.
+#[derive(Clone, Copy)]
+enum State {
+    Idle,
+    Busy,
+}
+
+impl usdt::IntoProbeArg<u8> for State {
+    fn to_probe_arg(&self) -> u8 {
+        match self {
+            State::Idle => 0,
+            State::Busy => 1,
+        }
+    }
+}
+
+fn expensive() -> u64 {
+    42
+}
+
+struct Worker {
+    id: u64,
+    name: String,
+}
+
+impl Worker {
+    /// The argument closure borrows `self`, rather than moving out of it, so fields may be passed
+    /// by value or reference from a method taking `&self`, and used again after the probe fires.
+    fn work(&self) -> usize {
+        refs::method!(|| (self.id, self.name.as_str()));
+        refs::method!(|| (&self.id, &self.name));
+        self.name.len()
+    }
 }
 
 fn main() {
@@ -62,6 +103,12 @@ fn main() {
     refs::u8_as_reference!(|| 0);
     refs::u8_as_reference!(|| &0);
 
+    // Types implementing `IntoProbeArg<u8>` may be passed as well, by value or reference.
+    let state = State::Idle;
+    refs::u8_as_value!(|| state);
+    refs::u8_as_value!(|| &state);
+    refs::u8_as_reference!(|| State::Busy);
+
     // This is true for string types as well. Probes accepting a string type may be called with
     // anything that implements `AsRef<str>`, which includes `&str`, owned `String`s, and
     // `&String` as well.
@@ -70,6 +117,12 @@ fn main() {
     refs::string_as_reference!(|| "&'static str");
     refs::string_as_reference!(|| String::from("owned"));
 
+    // C strings may also be passed to string probes, and are not copied, since they're already
+    // null-terminated.
+    let c_string = std::ffi::CString::new("C string").unwrap();
+    refs::string_as_value!(|| c_string.as_c_str());
+    refs::string_as_reference!(|| &c_string);
+
     // Vectors are supported as well. In this case, the probe argument behaves the way it might in
     // a "normal" function -- with a signature like `fn foo(_: Vec<T>)`, one can pass a `Vec<T>`.
     // (In this case a reference would also work, i.e., `&Vec<T>`.) However, with a _slice_ as the
@@ -113,4 +166,27 @@ fn main() {
 
     // This line will fail to compile, indicating that `arg` is borrowed after it's been moved.
     // println!("{:#?}", arg.x);
+
+    // Each argument returned by the closure may be any expression, including `if` or `match`
+    // expressions. This can be used to include a costly argument only when some cheaper condition
+    // holds.
+    let verbose = std::env::var_os("VERBOSE").is_some();
+    refs::conditional!(|| (0, if verbose { expensive() } else { 0 }));
+    let level: Option<u8> = Some(1);
+    refs::conditional!(|| (
+        match level {
+            Some(level) => level,
+            None => 0,
+        },
+        match level {
+            Some(level) if level > 0 => expensive(),
+            _ => 0,
+        }
+    ));
+
+    let worker = Worker {
+        id: 0,
+        name: String::from("worker"),
+    };
+    assert_eq!(worker.work(), worker.name.len());
 }
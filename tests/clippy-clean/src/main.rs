@@ -0,0 +1,54 @@
+//! Test that firing probes doesn't trigger lints in a crate with strict lints enabled.
+//!
+//! `trybuild` only runs the compiler, so this is checked by running `clippy` on this crate, e.g.,
+//! `cargo clippy -p clippy-clean -- -D warnings`.
+
+// Copyright 2021 Oxide Computer Company
+
+#![feature(asm)]
+#![deny(warnings)]
+#![deny(clippy::all, clippy::pedantic, clippy::nursery)]
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Arg {
+    x: u8,
+}
+
+#[usdt::provider]
+mod strict {
+    use crate::Arg;
+    fn zero() {}
+    fn one(x: u8) {}
+    fn many(a: u64, b: i32, s: &str) {}
+    fn serialized(arg: &Arg) {}
+    fn unique(id: &usdt::UniqueId) {}
+}
+
+fn main() {
+    usdt::register_probes().unwrap();
+    fire();
+}
+
+fn fire() {
+    let message = String::from("message");
+    let id = usdt::UniqueId::new();
+    strict::zero!();
+    strict::zero!(|| ());
+    strict::one!(|| 1);
+    strict::one!(x = 2);
+    strict::one!(raw & [3]);
+    strict::one!(signal_safe || 4);
+    strict::many!(|| (5, -6, &message));
+    strict::many!(s = "many", b = 7, a = 8);
+    strict::serialized!(|| Arg { x: 12 });
+    strict::unique!(|| &id);
+}
+
+#[cfg(test)]
+mod test {
+    // We just want to make sure that main builds and runs.
+    #[test]
+    fn test_main() {
+        super::main();
+    }
+}
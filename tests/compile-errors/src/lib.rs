@@ -13,5 +13,7 @@ mod tests {
         t.compile_fail("src/zero-arg-probe-type-check.rs");
         t.compile_fail("src/different-serializable-type.rs");
         t.compile_fail("src/relative-import.rs");
+        t.compile_fail("src/misspelled-argument-name.rs");
+        t.compile_fail("src/overflowing-literal.rs");
     }
 }
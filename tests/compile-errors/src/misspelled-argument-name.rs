@@ -0,0 +1,14 @@
+//! Test that a misspelled named argument is rejected
+
+// Copyright 2021 Oxide Computer Company
+
+#![feature(asm)]
+
+#[usdt::provider]
+mod my_provider {
+    fn transfer(request_id: u64, bytes: u64) {}
+}
+
+fn main() {
+    my_provider::transfer!(request_id = 1, bytse = 2);
+}
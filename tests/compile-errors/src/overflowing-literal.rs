@@ -0,0 +1,14 @@
+//! Test that an integer literal which overflows the type of a probe argument is rejected
+
+// Copyright 2021 Oxide Computer Company
+
+#![feature(asm)]
+
+#[usdt::provider]
+mod my_provider {
+    fn my_probe(_: u8) {}
+}
+
+fn main() {
+    my_provider::my_probe!(|| 256);
+}
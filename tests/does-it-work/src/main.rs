@@ -104,4 +104,69 @@ mod tests {
 
         thr.join().expect("Failed to join test runner thread");
     }
+
+    // Check that the probe fires, and that DTrace reads its arguments, on FreeBSD, where probes
+    // are registered differently than on illumos.
+    #[cfg(target_os = "freebsd")]
+    #[test]
+    fn test_does_it_fire() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        usdt::register_probes().unwrap();
+        let done = Arc::new(AtomicBool::new(false));
+        let thr = {
+            let done = done.clone();
+            thread::spawn(move || {
+                while !done.load(Ordering::SeqCst) {
+                    crate::doesit::work!(|| (7, "fired"));
+                    thread::sleep(Duration::from_millis(100));
+                }
+            })
+        };
+        let output = root_command("dtrace")
+            .arg("-q")
+            .arg("-n")
+            .arg(format!(
+                "doesit{}:::work {{ printf(\"%d %s\", arg0, copyinstr(arg1)); exit(0); }}",
+                std::process::id()
+            ))
+            .stdout(Stdio::piped())
+            .output()
+            .expect("Could not run DTrace");
+        done.store(true, Ordering::SeqCst);
+        thr.join().expect("Failed to join test runner thread");
+
+        assert!(output.status.success(), "DTrace failed");
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "7 fired");
+    }
+    // Check that `enabled_probes` reflects whether a DTrace consumer has enabled the probe.
+    #[cfg(any(target_os = "illumos", target_os = "macos", target_os = "freebsd"))]
+    #[test]
+    fn test_enabled_probes() {
+        use std::time::{Duration, Instant};
+
+        usdt::register_probes().unwrap();
+        assert!(crate::doesit::enabled_probes().is_empty());
+
+        let mut dtrace = root_command("dtrace")
+            .arg("-q")
+            .arg("-n")
+            .arg(format!(
+                "doesit{}:::work {{}} tick-10s {{ exit(0); }}",
+                std::process::id()
+            ))
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("Could not start DTrace");
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while crate::doesit::enabled_probes().is_empty() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(100));
+        }
+        let enabled = crate::doesit::enabled_probes();
+        let _ = dtrace.kill();
+        let _ = dtrace.wait();
+        assert_eq!(enabled, ["work"]);
+    }
 }
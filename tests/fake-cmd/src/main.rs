@@ -4,6 +4,11 @@
 fn main() {
     fake_lib::register_probes().unwrap();
     fake_lib::dummy();
+
+    // Fire probes defined in the library, through its re-export of their provider.
+    let item = fake_lib::Item { id: 1 };
+    fake_lib::trace::exported::item!(|| &item);
+    fake_lib::trace::exported::count!(|| 2);
 }
 
 #[cfg(test)]
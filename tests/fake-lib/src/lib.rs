@@ -5,7 +5,26 @@ pub use usdt::register_probes;
 
 include!(concat!(env!("OUT_DIR"), "/test.rs"));
 
+/// An item passed to the exported probes.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Item {
+    pub id: u64,
+}
+
+#[usdt::provider(export = true)]
+mod exported {
+    use crate::Item;
+    fn item(_: &Item) {}
+    fn count(_: u8) {}
+}
+
+/// The probes which this library exposes to its users.
+pub mod trace {
+    pub use crate::exported;
+}
+
 pub fn dummy() {
     test::here__i__am!();
     test::here__i__am!();
+    exported::count!(|| 1);
 }
@@ -0,0 +1,21 @@
+//! Test that probes with integer arguments can be fired from a `#![no_std]` crate.
+
+// Copyright 2021 Oxide Computer Company
+
+#![no_std]
+#![feature(asm)]
+#![deny(warnings)]
+
+#[usdt::provider(no_std = true)]
+mod no_std_provider {
+    fn work(_: u64, _: i32) {}
+    fn done() {}
+}
+
+/// Fire the provider's probes, returning the sum of the arguments.
+pub fn fire(count: u64, status: i32) -> i64 {
+    no_std_provider::work!(|| (count, status));
+    no_std_provider::work!(|| (&count, &status));
+    no_std_provider::done!();
+    count as i64 + status as i64
+}
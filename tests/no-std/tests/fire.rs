@@ -0,0 +1,9 @@
+//! Fire probes from the `no_std` crate, which is compiled without the standard library.
+
+// Copyright 2021 Oxide Computer Company
+
+#[test]
+fn test_fire_no_std_probes() {
+    usdt::register_probes().unwrap();
+    assert_eq!(no_std::fire(2, -1), 1);
+}
@@ -0,0 +1,5 @@
+fn main() {
+    usdt::build_providers_in_dir("providers").expect("Failed to build providers");
+    usdt::build_providers_from_source("shared", include_str!("embedded/shared.d"))
+        .expect("Failed to build embedded provider");
+}
@@ -0,0 +1,136 @@
+//! Test that a directory of provider files can be built in one call, and that a provider can be
+//! built from source embedded in the build script.
+
+// Copyright 2021 Oxide Computer Company
+
+#![cfg_attr(feature = "asm", feature(asm))]
+#![deny(warnings)]
+
+use usdt::register_probes;
+
+include!(concat!(env!("OUT_DIR"), "/first.rs"));
+include!(concat!(env!("OUT_DIR"), "/second.rs"));
+// The file name `my.provider.d` is sanitized when naming the generated file.
+include!(concat!(env!("OUT_DIR"), "/my_provider.rs"));
+// Built from a provider definition embedded in the build script.
+include!(concat!(env!("OUT_DIR"), "/shared.rs"));
+
+fn main() {
+    register_probes().unwrap();
+
+    first::begin!(|| (0u8));
+    second::work!(|| (1u64, "working"));
+    third::tick!();
+    shared::request!(|| (2u32, "GET"));
+    first::end!(|| "done");
+}
+
+#[cfg(test)]
+mod test {
+    // We just want to make sure that all providers were generated, and that main builds and runs.
+    #[test]
+    fn test_main() {
+        super::main();
+    }
+
+    #[test]
+    fn test_probe_table() {
+        assert_eq!(
+            super::first::PROBES,
+            &[
+                usdt::ProbeInfo {
+                    name: "begin",
+                    args: &["uint8_t"],
+                },
+                usdt::ProbeInfo {
+                    name: "end",
+                    args: &["char*"],
+                },
+            ]
+        );
+        assert_eq!(super::second::PROBES[0].args, &["uint64_t", "char*"]);
+        assert!(super::third::PROBES[0].args.is_empty());
+        assert_eq!(super::shared::PROBES[0].args, &["uint32_t", "char*"]);
+    }
+
+    #[test]
+    fn test_find_probe() {
+        const PROVIDERS: &[usdt::ProviderInfo] = &[super::first::PROVIDER, super::shared::PROVIDER];
+        assert_eq!(super::first::PROVIDER.name, "first");
+        let end = usdt::find_probe(PROVIDERS, "first", "end").unwrap();
+        assert_eq!(end.args, &["char*"]);
+        assert!(usdt::find_probe(PROVIDERS, "shared", "request").is_some());
+        assert!(usdt::find_probe(PROVIDERS, "first", "request").is_none());
+        // Only the given providers are searched, though `second` is also compiled in.
+        assert!(usdt::find_probe(PROVIDERS, "second", "work").is_none());
+    }
+
+    #[test]
+    fn test_validate_providers() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+        usdt::validate_providers(dir.join("providers/first.d")).unwrap();
+        assert!(matches!(
+            usdt::validate_providers(dir.join("invalid/duplicate-probe.d")),
+            Err(usdt::Error::ParseError(_))
+        ));
+        assert!(matches!(
+            usdt::validate_providers(dir.join("providers/missing.d")),
+            Err(usdt::Error::IO(_))
+        ));
+    }
+
+    #[test]
+    fn test_generate() {
+        let generated = usdt::Builder::from_source("shared", include_str!("../embedded/shared.d"))
+            .manifest(true)
+            .generate()
+            .unwrap();
+        assert_eq!(generated.out_file, std::path::Path::new("shared.rs"));
+        assert!(generated.source.contains("macro_rules ! request"));
+        assert_eq!(
+            generated.manifests.keys().collect::<Vec<_>>(),
+            vec!["shared"]
+        );
+        assert!(generated.manifests["shared"].contains("\"request\""));
+    }
+
+    // Write each of the named provider files to a new scratch directory, returning its path, or
+    // `None` if the names collide on this filesystem, e.g., `foo.d` and `Foo.d` on a
+    // case-insensitive one.
+    fn provider_dir(test: &str, names: &[&str]) -> Option<std::path::PathBuf> {
+        let dir =
+            std::env::temp_dir().join(format!("provider-dir-{}-{}", std::process::id(), test));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in names {
+            std::fs::write(dir.join(name), "provider foo { probe bar(); };").unwrap();
+        }
+        if std::fs::read_dir(&dir).unwrap().count() == names.len() {
+            Some(dir)
+        } else {
+            std::fs::remove_dir_all(&dir).unwrap();
+            None
+        }
+    }
+
+    #[test]
+    fn test_build_providers_in_dir_collisions() {
+        // The collisions are reported before anything is written to `OUT_DIR`.
+        let out_dir = std::env::temp_dir().join(format!("provider-dir-{}-out", std::process::id()));
+        std::fs::create_dir_all(&out_dir).unwrap();
+        std::env::set_var("OUT_DIR", &out_dir);
+
+        let sanitized = provider_dir("sanitized", &["foo-bar.d", "foo_bar.d"]);
+        let case = provider_dir("case", &["foo.d", "Foo.d"]);
+        for dir in sanitized.iter().chain(case.iter()) {
+            let err = usdt::build_providers_in_dir(dir).unwrap_err();
+            assert!(matches!(
+                err,
+                usdt::Error::IO(ref e) if e.kind() == std::io::ErrorKind::AlreadyExists
+            ));
+            assert_eq!(std::fs::read_dir(&out_dir).unwrap().count(), 0);
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+        assert!(sanitized.is_some());
+        std::fs::remove_dir_all(&out_dir).unwrap();
+    }
+}
@@ -0,0 +1,66 @@
+//! Test that providers can be emitted into, and registered from, separate linker sections.
+
+// Copyright 2021 Oxide Computer Company
+
+#![feature(asm)]
+#![deny(warnings)]
+
+#[usdt::provider(section_name = "probes_{provider}")]
+mod first {
+    fn begin(_: u8) {}
+}
+
+#[usdt::provider(section_name = "probes_{provider}")]
+mod second {
+    fn end(_: &str) {}
+}
+
+fn main() {
+    first::register_provider().unwrap();
+    second::register_provider().unwrap();
+
+    first::begin!(|| 0);
+    second::end!(|| "stopped");
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_main() {
+        super::main();
+    }
+
+    // Check that each provider's probe records are emitted only into its own section, so that
+    // registering one provider cannot register the probes of another.
+    #[cfg(target_os = "illumos")]
+    #[test]
+    fn test_sections_are_disjoint() {
+        extern "C" {
+            static __start_probes_first: usize;
+            static __stop_probes_first: usize;
+            static __start_probes_second: usize;
+            static __stop_probes_second: usize;
+        }
+
+        fn section(start: &usize, stop: &usize) -> &'static [u8] {
+            let start = start as *const usize as usize;
+            let stop = stop as *const usize as usize;
+            unsafe { std::slice::from_raw_parts(start as *const u8, stop - start) }
+        }
+
+        fn contains(data: &[u8], needle: &[u8]) -> bool {
+            data.windows(needle.len()).any(|window| window == needle)
+        }
+
+        let (first, second) = unsafe {
+            (
+                section(&__start_probes_first, &__stop_probes_first),
+                section(&__start_probes_second, &__stop_probes_second),
+            )
+        };
+        assert!(contains(first, b"first\0begin\0"));
+        assert!(!contains(first, b"second\0"));
+        assert!(contains(second, b"second\0end\0"));
+        assert!(!contains(second, b"first\0"));
+    }
+}
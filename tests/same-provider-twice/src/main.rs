@@ -0,0 +1,42 @@
+//! Test that one provider may be compiled more than once in the same crate, with different
+//! configurations, without the generated symbols clashing when the crate is linked.
+
+// Copyright 2021 Oxide Computer Company
+
+#![feature(asm)]
+#![deny(warnings)]
+
+usdt::dtrace_provider!("test.d", probe_path = "guarded::{provider}");
+usdt::dtrace_provider!(
+    "test.d",
+    probe_path = "unguarded::{provider}",
+    always_fire = true,
+    with_sequence = true
+);
+
+fn main() {
+    usdt::register_probes().unwrap();
+
+    guarded::twice::begin!(|| 0);
+    unguarded::twice::begin!(|| 1);
+    guarded::twice::end!(|| "guarded");
+    unguarded::twice::end!(|| "unguarded");
+}
+
+#[cfg(test)]
+mod test {
+    // Building and linking the crate is the real test; this checks that the probes also fire.
+    #[test]
+    fn test_main() {
+        super::main();
+    }
+
+    #[test]
+    fn test_distinct_providers() {
+        assert_eq!(super::guarded::twice::PROBES[0].args, &["uint8_t"]);
+        assert_eq!(
+            super::unguarded::twice::PROBES[0].args,
+            &["uint64_t", "uint8_t"]
+        );
+    }
+}
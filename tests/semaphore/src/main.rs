@@ -0,0 +1,54 @@
+//! Test that semaphore-guarded probes fire only when their semaphore is nonzero.
+
+// Copyright 2021 Oxide Computer Company
+
+#![feature(asm)]
+#![deny(warnings)]
+
+#[usdt::provider(semaphore = true)]
+mod guarded {
+    fn work(_: u8, _: &str) {}
+}
+
+fn main() {
+    guarded::work!(|| (0, "working"));
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_main() {
+        super::main();
+    }
+
+    // Tracers increment the semaphore when they attach to a probe. Simulate that directly, and
+    // check whether the argument closure is evaluated, which happens only if the probe fires.
+    #[cfg(all(target_os = "linux", feature = "stapsdt"))]
+    #[test]
+    fn test_semaphore_enables_probe() {
+        use std::cell::Cell;
+
+        let fired = Cell::new(0);
+        let fire = || {
+            crate::guarded::work!(|| {
+                fired.set(fired.get() + 1);
+                (0, "working")
+            })
+        };
+
+        fire();
+        assert_eq!(fired.get(), 0, "Probe fired with a zero semaphore");
+
+        unsafe { crate::guarded::__usdt_semaphore_guarded_work = 1 };
+        fire();
+        assert_eq!(
+            fired.get(),
+            1,
+            "Probe did not fire with a nonzero semaphore"
+        );
+
+        unsafe { crate::guarded::__usdt_semaphore_guarded_work = 0 };
+        fire();
+        assert_eq!(fired.get(), 1, "Probe fired after the semaphore was reset");
+    }
+}
@@ -0,0 +1,53 @@
+//! Test that several providers may import the same types, without unused or duplicate imports.
+
+// Copyright 2021 Oxide Computer Company
+
+#![feature(asm)]
+#![deny(warnings)]
+
+use serde::Serialize;
+
+#[derive(Clone, Serialize)]
+pub struct Request {
+    id: u64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Response {
+    status: u16,
+}
+
+#[usdt::provider]
+mod client {
+    use crate::Request;
+    use crate::Request;
+    use crate::Response;
+    fn send(_: &Request) {}
+    fn receive(_: &Response) {}
+}
+
+#[usdt::provider]
+mod server {
+    use crate::Request;
+    use crate::Response;
+    fn accept(_: &Request) {}
+    fn reply(_: &Response) {}
+}
+
+fn main() {
+    usdt::register_probes().unwrap();
+    let request = Request { id: 1 };
+    let response = Response { status: 200 };
+    client::send!(|| &request);
+    server::accept!(|| &request);
+    server::reply!(|| &response);
+    client::receive!(|| &response);
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_main() {
+        super::main();
+    }
+}
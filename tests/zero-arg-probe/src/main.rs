@@ -9,6 +9,7 @@ fn main() {
     register_probes().unwrap();
 
     zero::here__i__am!(|| ());
+    zero::here__i__am!(|| (),);
     zero::here__i__am!();
 }
 
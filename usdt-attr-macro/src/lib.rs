@@ -2,7 +2,7 @@
 // Copyright 2021 Oxide Computer Company
 
 use proc_macro2::TokenStream;
-use quote::quote;
+use quote::{quote, ToTokens};
 use serde_tokenstream::from_tokenstream;
 use syn::spanned::Spanned;
 use usdt_impl::{CompileProvidersConfig, DataType, Probe, Provider};
@@ -22,6 +22,185 @@ pub fn provider(
     }
 }
 
+/// Derive `usdt::IntoProbeArg` for a fieldless enum with an integer representation.
+///
+/// The enum must have a `#[repr]` attribute naming one of the fixed-width integer types, such as
+/// `#[repr(u32)]`. Each variant is passed to probes as its discriminant, in that type, and so the
+/// enum may be used as the argument to any probe taking that integer type.
+#[proc_macro_derive(ProbeArg)]
+pub fn derive_probe_arg(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    syn::parse::<syn::DeriveInput>(item)
+        .and_then(|input| generate_probe_arg_impl(&input))
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+// Generate the implementation of `IntoProbeArg` for a fieldless enum, converting each variant into
+// its discriminant.
+fn generate_probe_arg_impl(input: &syn::DeriveInput) -> Result<TokenStream, syn::Error> {
+    let data = match &input.data {
+        syn::Data::Enum(data) => data,
+        _ => {
+            return Err(syn::Error::new(
+                input.ident.span(),
+                "ProbeArg may only be derived for enums",
+            ))
+        }
+    };
+    if let Some(variant) = data
+        .variants
+        .iter()
+        .find(|variant| !matches!(variant.fields, syn::Fields::Unit))
+    {
+        return Err(syn::Error::new(
+            variant.span(),
+            "ProbeArg may only be derived for enums without fields",
+        ));
+    }
+    let repr = input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("repr"))
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(syn::Meta::List(list)) => Some(list.nested),
+            _ => None,
+        })
+        .flatten()
+        .find_map(|nested| match nested {
+            syn::NestedMeta::Meta(syn::Meta::Path(path)) => path
+                .get_ident()
+                .filter(|ident| {
+                    matches!(
+                        ident.to_string().as_str(),
+                        "u8" | "u16" | "u32" | "u64" | "i8" | "i16" | "i32" | "i64"
+                    )
+                })
+                .cloned(),
+            _ => None,
+        })
+        .ok_or_else(|| {
+            syn::Error::new(
+                input.ident.span(),
+                "ProbeArg requires a fixed-width integer representation, such as #[repr(u32)]",
+            )
+        })?;
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let arms = data.variants.iter().map(|variant| {
+        let variant = &variant.ident;
+        quote! { #name::#variant => #name::#variant as #repr, }
+    });
+    Ok(quote! {
+        impl #impl_generics ::usdt::IntoProbeArg<#repr> for #name #ty_generics #where_clause {
+            fn to_probe_arg(&self) -> #repr {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+    })
+}
+
+/// Derive `usdt::ProbeArgs` for a `#[repr(C)]` struct whose fields are all fixed-width integers,
+/// or fixed-size arrays of them.
+///
+/// The struct may then be passed by reference to any probe taking a `uint64_t` argument, which
+/// receives its address, and `ProbeArgs::D_STRUCT` holds the declaration of the same struct in D.
+#[proc_macro_derive(ProbeArgs)]
+pub fn derive_probe_args(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    syn::parse::<syn::DeriveInput>(item)
+        .and_then(|input| generate_probe_args_impl(&input))
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+// Generate the implementations of `ProbeArgs` and `IntoProbeArg<u64>` for a struct, declaring the
+// struct in D with the same field types, in the same order, so that it has the same layout.
+fn generate_probe_args_impl(input: &syn::DeriveInput) -> Result<TokenStream, syn::Error> {
+    let fields = match &input.data {
+        syn::Data::Struct(syn::DataStruct {
+            fields: syn::Fields::Named(fields),
+            ..
+        }) => fields,
+        _ => {
+            return Err(syn::Error::new(
+                input.ident.span(),
+                "ProbeArgs may only be derived for structs with named fields",
+            ))
+        }
+    };
+    let repr_c = input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("repr"))
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(syn::Meta::List(list)) => Some(list.nested),
+            _ => None,
+        })
+        .flatten()
+        .any(|nested| matches!(nested, syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident("C")));
+    if !repr_c {
+        return Err(syn::Error::new(
+            input.ident.span(),
+            "ProbeArgs requires the C representation, #[repr(C)]",
+        ));
+    }
+    let name = &input.ident;
+    let mut declaration = format!("struct {} {{\n", name);
+    for field in fields.named.iter() {
+        let name = field.ident.as_ref().unwrap();
+        // An array is declared with its length after the field's name, as in C.
+        let (c_type, len) = match &field.ty {
+            syn::Type::Array(syn::TypeArray {
+                elem,
+                len:
+                    syn::Expr::Lit(syn::ExprLit {
+                        lit: syn::Lit::Int(len),
+                        ..
+                    }),
+                ..
+            }) => (integer_c_type(elem), format!("[{}]", len.base10_digits())),
+            ty => (integer_c_type(ty), String::new()),
+        };
+        let c_type = c_type.ok_or_else(|| {
+            syn::Error::new(
+                field.ty.span(),
+                "ProbeArgs fields must be fixed-width integers, such as u32, or arrays of them \
+                with a literal length, such as [u8; 8]",
+            )
+        })?;
+        declaration.push_str(&format!("\t{} {}{};\n", c_type, name, len));
+    }
+    declaration.push_str("};\n");
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::usdt::ProbeArgs for #name #ty_generics #where_clause {
+            const D_STRUCT: &'static str = #declaration;
+        }
+
+        impl #impl_generics ::usdt::IntoProbeArg<u64> for #name #ty_generics #where_clause {
+            fn to_probe_arg(&self) -> u64 {
+                self as *const Self as usize as u64
+            }
+        }
+    })
+}
+
+// Return the D type of a fixed-width integer type, if it is one.
+fn integer_c_type(ty: &syn::Type) -> Option<String> {
+    let ident = match ty {
+        syn::Type::Path(path) => path.path.get_ident()?.to_string(),
+        _ => return None,
+    };
+    match ident.as_str() {
+        "u8" | "u16" | "u32" | "u64" | "i8" | "i16" | "i32" | "i64" => {
+            let unsigned = if ident.starts_with('u') { "u" } else { "" };
+            Some(format!("{}int{}_t", unsigned, &ident[1..]))
+        }
+        _ => None,
+    }
+}
+
 // Generate the actual provider implementation, include the type-checks and probe macros.
 fn generate_provider_item(
     item: TokenStream,
@@ -52,6 +231,7 @@ fn generate_provider_item(
                 let signature = check_probe_function_signature(&func.sig)?;
                 let mut item_check_fns = Vec::new();
                 let mut item_types = Vec::new();
+                let mut item_names = Vec::new();
                 for (arg_index, arg) in signature.inputs.iter().enumerate() {
                     match arg {
                         syn::FnArg::Receiver(item) => {
@@ -67,6 +247,10 @@ fn generate_provider_item(
                                 item_check_fns.push(check_fn);
                             }
                             item_types.push(item_type);
+                            item_names.push(match &*item.pat {
+                                syn::Pat::Ident(pat) => Some(pat.ident.to_string()),
+                                _ => None,
+                            });
                         }
                     }
                 }
@@ -74,11 +258,21 @@ fn generate_provider_item(
                 probes.push(Probe {
                     name: signature.ident.to_string(),
                     types: item_types,
+                    arg_names: item_names,
+                    docs: doc_comments(&func.attrs),
                 });
             }
             syn::Item::Use(ref use_statement) => {
                 verify_use_tree(&use_statement.tree)?;
-                use_statements.push(use_statement.clone());
+                // The use statements are repeated in the type check for each probe, where a
+                // duplicate would be an error rather than merely redundant.
+                let tokens = use_statement.to_token_stream().to_string();
+                if !use_statements
+                    .iter()
+                    .any(|u: &syn::ItemUse| u.to_token_stream().to_string() == tokens)
+                {
+                    use_statements.push(use_statement.clone());
+                }
             }
             _ => {
                 return Err(syn::Error::new(
@@ -93,13 +287,17 @@ fn generate_provider_item(
         probes,
         use_statements: use_statements.clone(),
     };
-    let compiled = usdt_impl::compile_provider(&provider, &config);
+    let compiled = usdt_impl::compile_provider(&provider, &config)
+        .map_err(|e| syn::Error::new(mod_.span(), e.to_string()))?;
     let type_checks = if check_fns.is_empty() {
         quote! { const _: fn() = || {}; }
     } else {
         quote! {
             const _: fn() = || {
-                #(#use_statements)*
+                #(
+                    #[allow(unused_imports)]
+                    #use_statements
+                )*
                 fn usdt_types_must_be_clone_and_serialize<T: ?Sized + Clone + ::serde::Serialize>() {}
                 #(#check_fns)*
             };
@@ -111,6 +309,29 @@ fn generate_provider_item(
     })
 }
 
+// Collect the text of any doc comments on a probe function, one line per comment.
+fn doc_comments(attrs: &[syn::Attribute]) -> Option<String> {
+    let lines = attrs
+        .iter()
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(syn::Meta::NameValue(syn::MetaNameValue {
+                ref path,
+                lit: syn::Lit::Str(ref doc),
+                ..
+            })) if path.is_ident("doc") => {
+                let doc = doc.value();
+                Some(doc.strip_prefix(' ').map(String::from).unwrap_or(doc))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
 fn check_probe_name(ident: &syn::Ident) -> syn::Result<()> {
     let check = |name| {
         if ident == name {
@@ -214,17 +435,19 @@ fn is_simple_type(ident: &syn::Ident) -> bool {
     let ident = format!("{}", ident);
     matches!(
         ident.as_str(),
-        "u8" | "u16" | "u32" | "u64" | "i8" | "i16" | "i32" | "i64" | "String" | "str"
+        "u8" | "u16" | "u32" | "u64" | "i8" | "i16" | "i32" | "i64" | "char" | "String" | "str"
     )
 }
 
 // Return the `dtrace_parser::DataType` corresponding to the given `path`
+//
+// A `char` is passed as its code point, a `uint32_t`.
 fn data_type_from_path(path: &syn::Path) -> DataType {
     if path.is_ident("u8") {
         DataType::Native(dtrace_parser::DataType::U8)
     } else if path.is_ident("u16") {
         DataType::Native(dtrace_parser::DataType::U16)
-    } else if path.is_ident("u32") {
+    } else if path.is_ident("u32") || path.is_ident("char") {
         DataType::Native(dtrace_parser::DataType::U32)
     } else if path.is_ident("u64") {
         DataType::Native(dtrace_parser::DataType::U64)
@@ -308,6 +531,7 @@ mod tests {
     #[rstest]
     #[case("u8", dtrace_parser::DataType::U8)]
     #[case("&u8", dtrace_parser::DataType::U8)]
+    #[case("char", dtrace_parser::DataType::U32)]
     #[case("&str", dtrace_parser::DataType::String)]
     #[case("String", dtrace_parser::DataType::String)]
     #[case("&&str", dtrace_parser::DataType::String)]
@@ -374,4 +598,85 @@ mod tests {
         let item: syn::ItemUse = syn::parse2(tokens).unwrap();
         assert!(verify_use_tree(&item.tree).is_err());
     }
+
+    #[test]
+    fn test_generate_probe_arg_impl() {
+        let input = syn::parse_str::<syn::DeriveInput>(
+            "#[derive(Clone)] #[repr(u16)] enum State { Idle = 1, Busy = 4 }",
+        )
+        .unwrap();
+        let expected = quote! {
+            impl ::usdt::IntoProbeArg<u16> for State {
+                fn to_probe_arg(&self) -> u16 {
+                    match self {
+                        State::Idle => State::Idle as u16,
+                        State::Busy => State::Busy as u16,
+                    }
+                }
+            }
+        };
+        assert_eq!(
+            generate_probe_arg_impl(&input).unwrap().to_string(),
+            expected.to_string()
+        );
+
+        for defn in [
+            "enum State { Idle, Busy }",
+            "#[repr(C)] enum State { Idle, Busy }",
+            "#[repr(u8)] enum State { Idle, Busy(u8) }",
+            "#[repr(u8)] struct State(u8);",
+        ] {
+            let input = syn::parse_str::<syn::DeriveInput>(defn).unwrap();
+            assert!(generate_probe_arg_impl(&input).is_err(), "{}", defn);
+        }
+    }
+
+    #[test]
+    fn test_generate_probe_args_impl() {
+        let input = syn::parse_str::<syn::DeriveInput>(
+            "#[derive(Clone)] #[repr(C)] struct Req { id: u64, len: u32, kind: i8 }",
+        )
+        .unwrap();
+        let declaration = "struct Req {\n\tuint64_t id;\n\tuint32_t len;\n\tint8_t kind;\n};\n";
+        let expected = quote! {
+            impl ::usdt::ProbeArgs for Req {
+                const D_STRUCT: &'static str = #declaration;
+            }
+
+            impl ::usdt::IntoProbeArg<u64> for Req {
+                fn to_probe_arg(&self) -> u64 {
+                    self as *const Self as usize as u64
+                }
+            }
+        };
+        assert_eq!(
+            generate_probe_args_impl(&input).unwrap().to_string(),
+            expected.to_string()
+        );
+
+        // Arrays are declared with their length after the field's name.
+        let input = syn::parse_str::<syn::DeriveInput>(
+            "#[repr(C)] struct Key { id: u32, bytes: [u8; 8], words: [i16; 2] }",
+        )
+        .unwrap();
+        let output = generate_probe_args_impl(&input).unwrap().to_string();
+        let declaration =
+            "struct Key {\n\tuint32_t id;\n\tuint8_t bytes[8];\n\tint16_t words[2];\n};\n";
+        assert!(output.contains(&quote! { #declaration }.to_string()));
+
+        for defn in [
+            "struct Req { id: u64 }",
+            "#[repr(u8)] struct Req { id: u64 }",
+            "#[repr(C)] struct Req(u64);",
+            "#[repr(C)] struct Req { name: String }",
+            "#[repr(C)] struct Req { id: usize }",
+            "#[repr(C)] struct Req { key: [String; 2] }",
+            "#[repr(C)] struct Req { key: [u8; N] }",
+            "#[repr(C)] struct Req { key: [[u8; 2]; 2] }",
+            "#[repr(C)] enum Req { A }",
+        ] {
+            let input = syn::parse_str::<syn::DeriveInput>(defn).unwrap();
+            assert!(generate_probe_args_impl(&input).is_err(), "{}", defn);
+        }
+    }
 }
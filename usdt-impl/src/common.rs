@@ -6,7 +6,8 @@ use proc_macro2::TokenStream;
 use quote::{format_ident, quote};
 
 // Construct function call that is used internally in the UDST-generated macros, to allow
-// compile-time type checking of the lambda arguments.
+// compile-time type checking of the lambda arguments. The lambda must already be bound to
+// `__usdt_private_args_lambda`.
 pub fn generate_type_check(
     provider_name: &str,
     use_statements: &[syn::ItemUse],
@@ -17,7 +18,6 @@ pub fn generate_type_check(
     // Note that there's no need to clone the closure here, since () is Copy.
     if types.is_empty() {
         return quote! {
-            let __usdt_private_args_lambda = $args_lambda;
             let _ = || {
                 let _: () = __usdt_private_args_lambda();
             };
@@ -30,12 +30,14 @@ pub fn generate_type_check(
     // support passing things by value or reference, and take some form of reference to that thing.
     // The mapping is generally:
     //
-    // T or &T -> Borrow<T>
-    // Strings -> AsRef<str>
+    // Integers T or &T -> IntoProbeArg<T>
+    // UniqueId or &UniqueId, and other T or &T -> Borrow<T>
+    // Strings -> ProbeStr<_>, i.e., AsRef<str> or AsRef<CStr>
     // [T; N] or &[T] -> AsRef<[T]>
     let type_check_args = types
         .iter()
-        .map(|typ| match typ {
+        .enumerate()
+        .map(|(i, typ)| match typ {
             DataType::Serializable(ty) => {
                 match ty {
                     syn::Type::Reference(reference) => {
@@ -43,7 +45,7 @@ pub fn generate_type_check(
                             quote! { _: impl AsRef<[#elem]> }
                         } else {
                             let elem = &*reference.elem;
-                            quote! { _: impl ::std::borrow::Borrow<#elem> }
+                            quote! { _: impl ::core::borrow::Borrow<#elem> }
                         }
                     }
                     syn::Type::Slice(slice) => {
@@ -55,7 +57,7 @@ pub fn generate_type_check(
                         quote! { _: impl AsRef<[#elem]> }
                     }
                     syn::Type::Path(_) => {
-                        quote! { _: impl ::std::borrow::Borrow<#ty> }
+                        quote! { _: impl ::core::borrow::Borrow<#ty> }
                     }
                     _ => {
                         // Any other type must be specified exactly as given in the probe parameter
@@ -63,10 +65,14 @@ pub fn generate_type_check(
                     }
                 }
             }
-            DataType::Native(dtrace_parser::DataType::String) => quote! { _: impl AsRef<str> },
+            DataType::Native(dtrace_parser::DataType::String) => {
+                let kind = format_ident!("__UsdtStrKind{}", i);
+                quote! { _: impl ::usdt::ProbeStr<#kind> }
+            }
+            DataType::UniqueId => quote! { _: impl ::core::borrow::Borrow<::usdt::UniqueId> },
             _ => {
                 let arg = typ.to_rust_type();
-                quote! { _: impl ::std::borrow::Borrow<#arg> }
+                quote! { _: impl ::usdt::IntoProbeArg<#arg> }
             }
         })
         .collect::<Vec<_>>();
@@ -79,15 +85,30 @@ pub fn generate_type_check(
         })
         .collect::<Vec<_>>();
 
+    // Whether a string argument is borrowed as a `str` or `CStr` is inferred, with a separate type
+    // parameter for each.
+    let str_kinds = types
+        .iter()
+        .enumerate()
+        .filter(|(_, typ)| matches!(typ, DataType::Native(dtrace_parser::DataType::String)))
+        .map(|(i, _)| format_ident!("__UsdtStrKind{}", i))
+        .collect::<Vec<_>>();
+    let generics = if str_kinds.is_empty() {
+        quote! {}
+    } else {
+        quote! { <#(#str_kinds),*> }
+    };
+
     let preamble = unpack_argument_lambda(&types, /* clone = */ true);
 
     let type_check_function =
         format_ident!("__usdt_private_{}_{}_type_check", provider_name, probe_name);
     quote! {
-        let __usdt_private_args_lambda = $args_lambda;
-        #[allow(unused_imports)]
-        #(#use_statements)*
-        fn #type_check_function (#(#type_check_args),*) { }
+        #(
+            #[allow(unused_imports)]
+            #use_statements
+        )*
+        fn #type_check_function #generics (#(#type_check_args),*) { }
         let _ = || {
             #preamble
             #type_check_function(#(#expanded_lambda_args),*);
@@ -103,32 +124,132 @@ fn shared_slice_elem_type(reference: &syn::TypeReference) -> Option<&syn::Type>
     }
 }
 
+// The registers in which probe arguments are passed on the given architecture, if it is supported.
+//
+// x86_64 passes the first 6 arguments in registers, with the rest on the stack.
+// We limit this to 6 arguments in all cases for now, as handling those stack
+// arguments would be challenging with the current `asm!` macro implementation.
+pub(crate) fn abi_regs(arch: &str) -> Option<[&'static str; 6]> {
+    match arch {
+        "x86_64" => Some(["rdi", "rsi", "rdx", "rcx", "r8", "r9"]),
+        "aarch64" => Some(["x0", "x1", "x2", "x3", "x4", "x5"]),
+        _ => None,
+    }
+}
+
+// The registers to which probe arguments may be pinned on the given architecture.
+//
+// This excludes the stack and frame pointers, registers reserved by LLVM or the platform, and the
+// link register clobbered by the probe's call instruction on macOS, none of which can be used as
+// `asm!` operands for the probe.
+fn pinnable_regs(arch: &str) -> &'static [&'static str] {
+    match arch {
+        "x86_64" => &[
+            "rax", "rcx", "rdx", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15",
+        ],
+        "aarch64" => &[
+            "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12", "x13",
+            "x14", "x15", "x16", "x17", "x20", "x21", "x22", "x23", "x24", "x25", "x26", "x27",
+            "x28",
+        ],
+        _ => &[],
+    }
+}
+
+// Return the register in which each of a probe's arguments is passed, or a message describing why
+// they cannot be assigned.
+//
+// Arguments are passed in the ABI registers of the target architecture, in order, except for the
+// leading arguments pinned to other registers with the `register_pins` option. Pinning an argument
+// to a register which is reserved or clobbered, or to one which holds another argument, is an
+// error.
+pub(crate) fn argument_regs(
+    probe_name: &str,
+    n_args: usize,
+    config: &crate::CompileProvidersConfig,
+) -> Result<Vec<&'static str>, String> {
+    let arch = config.target_arch();
+    let abi_regs = abi_regs(arch).ok_or_else(|| {
+        format!(
+            "USDT only supports x86_64 and ARM64 architectures, not \"{}\"",
+            arch
+        )
+    })?;
+    let pins = config
+        .register_pins
+        .get(probe_name)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    if pins.len() > n_args {
+        return Err(format!(
+            "Probe \"{}\" pins {} arguments to registers, but has only {}",
+            probe_name,
+            pins.len(),
+            n_args
+        ));
+    }
+    let mut regs: Vec<&'static str> = Vec::with_capacity(n_args);
+    for (i, default) in abi_regs.iter().take(n_args).enumerate() {
+        let reg = match pins.get(i) {
+            Some(pin) => *pinnable_regs(arch)
+                .iter()
+                .find(|reg| **reg == pin.as_str())
+                .ok_or_else(|| {
+                    format!(
+                        "Argument {} of probe \"{}\" cannot be pinned to \"{}\", which is not \
+                        a general-purpose register on {}, or is reserved or clobbered by the probe",
+                        i, probe_name, pin, arch
+                    )
+                })?,
+            None => default,
+        };
+        if let Some(other) = regs.iter().position(|r| *r == reg) {
+            return Err(format!(
+                "Arguments {} and {} of probe \"{}\" are both passed in register \"{}\"",
+                other, i, probe_name, reg
+            ));
+        }
+        regs.push(reg);
+    }
+    Ok(regs)
+}
+
 // Return code to destructure a probe arguments into identifiers, and to pass those to ASM
 // registers.
-pub fn construct_probe_args(types: &[DataType]) -> (TokenStream, TokenStream) {
-    // x86_64 passes the first 6 arguments in registers, with the rest on the stack.
-    // We limit this to 6 arguments in all cases for now, as handling those stack
-    // arguments would be challenging with the current `asm!` macro implementation.
-    #[cfg(target_arch = "x86_64")]
-    let abi_regs = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
-    #[cfg(target_arch = "aarch64")]
-    let abi_regs = ["x0", "x1", "x2", "x3", "x4", "x5"];
-    #[cfg(not(any(target_arch = "aarch64", target_arch = "x86_64")))]
-    compile_error!("USDT only supports x86_64 and ARM64 architectures");
-
+//
+// The argument closure is called and each argument converted in `let` bindings, all of which are
+// evaluated before the `asm!` block. The register operands only cast those bindings, so however
+// complex the argument expressions are, none of their code is inlined into the operands.
+//
+// The registers are those of the configured target architecture, unless the probe's arguments are
+// pinned to others (see `argument_regs`). If the registers cannot be assigned, the returned code
+// is a compile error. Probes without arguments need neither the closure nor any registers, so both
+// blocks are empty, and the probe is fired with only its `nop`.
+pub fn construct_probe_args(
+    probe_name: &str,
+    types: &[DataType],
+    config: &crate::CompileProvidersConfig,
+) -> (TokenStream, TokenStream) {
     assert!(
-        types.len() <= abi_regs.len(),
+        types.len() <= 6,
         "Up to 6 probe arguments are currently supported"
     );
+    let regs = match argument_regs(probe_name, types.len(), config) {
+        Ok(regs) => regs,
+        Err(msg) => return (quote! { compile_error!(#msg); }, TokenStream::new()),
+    };
+    if types.is_empty() {
+        return (TokenStream::new(), TokenStream::new());
+    }
     let (unpacked_args, in_regs): (Vec<_>, Vec<_>) = types
         .iter()
-        .zip(&abi_regs)
+        .zip(&regs)
         .enumerate()
         .map(|(i, (typ, reg))| {
             let arg = format_ident!("arg_{}", i);
             let index = syn::Index::from(i);
             let input = quote! { args.#index };
-            let (value, at_use) = asm_type_convert(typ, input);
+            let (value, at_use) = asm_type_convert(typ, input, config);
 
             // These values must refer to the actual traced data and prevent it
             // from being dropped until after we've completed the probe
@@ -151,7 +272,7 @@ pub fn construct_probe_args(types: &[DataType]) -> (TokenStream, TokenStream) {
     (unpacked_args, in_regs)
 }
 
-fn unpack_argument_lambda(types: &[DataType], clone: bool) -> TokenStream {
+pub(crate) fn unpack_argument_lambda(types: &[DataType], clone: bool) -> TokenStream {
     let maybe_clone = if clone {
         quote! { .clone() }
     } else {
@@ -170,7 +291,11 @@ fn unpack_argument_lambda(types: &[DataType], clone: bool) -> TokenStream {
 // Convert a supported data type to 1. a type to store for the duration of the
 // probe invocation and 2. a transformation for compatibility with an asm
 // register.
-fn asm_type_convert(typ: &DataType, input: TokenStream) -> (TokenStream, TokenStream) {
+fn asm_type_convert(
+    typ: &DataType,
+    input: TokenStream,
+    config: &crate::CompileProvidersConfig,
+) -> (TokenStream, TokenStream) {
     match typ {
         DataType::Serializable(_) => (
             // Convert the input to JSON. This is a fallible operation, however, so we wrap the
@@ -187,16 +312,21 @@ fn asm_type_convert(typ: &DataType, input: TokenStream) -> (TokenStream, TokenSt
             },
             quote! { .as_ptr() as i64 },
         ),
-        DataType::Native(dtrace_parser::DataType::String) => (
-            quote! {
-                [(#input.as_ref() as &str).as_bytes(), &[0_u8]].concat()
-            },
-            quote! { .as_ptr() as i64 },
-        ),
+        // A `CStr` is borrowed rather than copied, unless it must be truncated, so the pointer is
+        // valid until the binding is dropped, after the probe fires.
+        DataType::Native(dtrace_parser::DataType::String) => {
+            let max_len = config.max_string_len();
+            (
+                quote! {
+                    ::usdt::truncate_probe_str(::usdt::ProbeStr::to_probe_str(&#input), #max_len)
+                },
+                quote! { .as_ptr() as i64 },
+            )
+        }
         DataType::Native(_) => {
             let ty = typ.to_rust_type();
             (
-                quote! { (*<_ as ::std::borrow::Borrow<#ty>>::borrow(&#input) as i64) },
+                quote! { (<_ as ::usdt::IntoProbeArg<#ty>>::to_probe_arg(&#input) as i64) },
                 quote! {},
             )
         }
@@ -204,6 +334,529 @@ fn asm_type_convert(typ: &DataType, input: TokenStream) -> (TokenStream, TokenSt
     }
 }
 
+// Construct a `tracing` event mirroring a probe, if configured, or an empty token stream.
+//
+// The event's target is `provider::probe`, its message is the probe name, and the arguments are
+// recorded as fields named `arg0`, `arg1`, etc. The generated code expects the probe arguments to
+// have already been unpacked into the tuple `args`. It refers to `tracing` through its re-export
+// from `usdt`, which requires the `tracing` feature of `usdt`.
+pub(crate) fn build_tracing_event(
+    config: &crate::CompileProvidersConfig,
+    provider_name: &str,
+    probe_name: &str,
+    types: &[DataType],
+) -> TokenStream {
+    let level = match config.mirror_tracing.as_deref() {
+        None => return quote! {},
+        Some(level) => match level.to_lowercase().as_str() {
+            "trace" => quote! { ::usdt::tracing::Level::TRACE },
+            "debug" => quote! { ::usdt::tracing::Level::DEBUG },
+            "info" => quote! { ::usdt::tracing::Level::INFO },
+            "warn" => quote! { ::usdt::tracing::Level::WARN },
+            "error" => quote! { ::usdt::tracing::Level::ERROR },
+            _ => {
+                let message = format!(
+                    "Invalid tracing level \"{}\", expected one of \
+                    \"trace\", \"debug\", \"info\", \"warn\", or \"error\"",
+                    level
+                );
+                return quote! { compile_error!(#message); };
+            }
+        },
+    };
+    let target = format!("{}::{}", provider_name, probe_name);
+    let (bindings, fields): (Vec<_>, Vec<_>) = types
+        .iter()
+        .enumerate()
+        .map(|(i, typ)| {
+            let index = syn::Index::from(i);
+            let binding = format_ident!("__usdt_private_arg_{}", i);
+            let field = format_ident!("arg{}", i);
+            let (value, at_use) = match typ {
+                DataType::Serializable(_) => (
+                    quote! {
+                        match ::usdt::to_json(&args.#index) {
+                            Ok(json) => json,
+                            Err(e) => e.to_string(),
+                        }
+                    },
+                    quote! { .as_str() },
+                ),
+                DataType::Native(dtrace_parser::DataType::String) => (
+                    quote! { ::usdt::ProbeStr::to_probe_string(&args.#index) },
+                    quote! { .as_ref() },
+                ),
+                DataType::Native(_) => {
+                    let ty = typ.to_rust_type();
+                    (
+                        quote! { <_ as ::usdt::IntoProbeArg<#ty>>::to_probe_arg(&args.#index) },
+                        quote! {},
+                    )
+                }
+                DataType::UniqueId => (quote! { args.#index.as_u64() }, quote! {}),
+            };
+            (
+                quote! { let #binding = #value; },
+                quote! { #field = #binding #at_use },
+            )
+        })
+        .unzip();
+    quote! {
+        {
+            #(#bindings)*
+            ::usdt::tracing::event!(target: #target, #level, #(#fields,)* #probe_name);
+        }
+    }
+}
+
+// Construct the `register_provider` function emitted for providers with a custom section name,
+// with the given body registering that section's probes.
+pub(crate) fn build_register_provider_fn(
+    config: &crate::CompileProvidersConfig,
+    provider: &Provider,
+    body: TokenStream,
+) -> TokenStream {
+    if config.format_section(&provider.name).is_none() {
+        return quote! {};
+    }
+    let vis = config.visibility();
+    quote! {
+        /// Register this provider's probes with DTrace.
+        #[allow(dead_code)]
+        #vis fn register_provider() -> ::core::result::Result<(), ::usdt::Error> {
+            #body
+        }
+    }
+}
+
+// Construct the `enabled_probes` function, which returns the names of the provider's probes which
+// are currently enabled, using the given expression to check whether each probe is enabled.
+//
+// As for the `PROBES` constant, the function is omitted if the probes are not placed in any
+// module, and for providers without probes. It's also omitted from `no_std` crates, since it
+// allocates.
+pub(crate) fn build_enabled_probes_fn(
+    config: &crate::CompileProvidersConfig,
+    provider: &Provider,
+    is_enabled: impl Fn(&crate::Probe) -> TokenStream,
+) -> TokenStream {
+    if provider.probes.is_empty()
+        || config.provider_modules(&provider.name).is_empty()
+        || config.no_std
+    {
+        return quote! {};
+    }
+    let names = provider.probes.iter().map(|probe| &probe.name);
+    let checks = provider.probes.iter().map(is_enabled);
+    let allow_lints = allow_generated_lints();
+    let vis = config.visibility();
+    quote! {
+        /// Return the names of this provider's probes which are currently enabled.
+        #[allow(dead_code)]
+        #allow_lints
+        #vis fn enabled_probes() -> ::std::vec::Vec<&'static str> {
+            let mut enabled = ::std::vec::Vec::new();
+            if !::usdt::probes_globally_enabled() {
+                return enabled;
+            }
+            #(
+                if #checks {
+                    enabled.push(#names);
+                }
+            )*
+            enabled
+        }
+    }
+}
+
+// Construct the macro arm accepting `raw &[u64]`, a slice with one already-prepared value for each
+// argument, which is cast to the argument's type and passed to the probe as if from a closure.
+//
+// Only probes whose arguments are all integers accept raw values. For any other probe, the arm is a
+// compile error.
+fn build_raw_args_match(
+    macro_path: &TokenStream,
+    probe_name: &str,
+    types: &[DataType],
+) -> TokenStream {
+    if !all_integers(types) {
+        let message = format!(
+            "Probe \"{}\" does not accept raw arguments, which are only supported for probes \
+            whose arguments are all integers",
+            probe_name
+        );
+        return quote! {
+            (raw $values:expr) => {
+                compile_error!(#message);
+            };
+        };
+    }
+    let n_args = types.len();
+    let message = format!("Probe \"{}\" takes {} raw argument(s)", probe_name, n_args);
+    let values = types
+        .iter()
+        .enumerate()
+        .map(|(i, typ)| {
+            let ty = typ.to_rust_type();
+            quote! { __usdt_private_raw_args[#i] as #ty }
+        })
+        .collect::<Vec<_>>();
+    // The closure for a probe with one argument returns it directly, rather than in a tuple.
+    let values = if values.len() == 1 {
+        quote! { #(#values)* }
+    } else {
+        quote! { (#(#values),*) }
+    };
+    let allow_lints = allow_generated_lints();
+    quote! {
+        (raw $values:expr) => {
+            {
+                let __usdt_private_raw_args: &[u64] = $values;
+                #allow_lints
+                {
+                    debug_assert_eq!(__usdt_private_raw_args.len(), #n_args, #message);
+                    $crate::#macro_path!(|| #values)
+                }
+            }
+        };
+    }
+}
+
+// Return true if every argument is an integer, and so is passed to the probe without allocating.
+fn all_integers(types: &[DataType]) -> bool {
+    types.iter().all(|typ| match typ {
+        DataType::Native(dtrace_parser::DataType::String) => false,
+        DataType::Native(_) => true,
+        _ => false,
+    })
+}
+
+// Construct the macro arm accepting `signal_safe $args_lambda`, which fires the probe without
+// allocating or taking any locks, so that it may be called from a signal handler.
+//
+// This differs from the general arm in that probes are never registered automatically, which
+// requires a lock, and in that it is a compile error if any argument must be lowered into an
+// allocated buffer, or if the probe is mirrored to `tracing`, whose subscribers may do either.
+fn build_signal_safe_match(
+    config: &crate::CompileProvidersConfig,
+    probe_name: &str,
+    types: &[DataType],
+    body: TokenStream,
+) -> TokenStream {
+    let reason = if !all_integers(types) {
+        Some("whose arguments are all integers")
+    } else if config.mirror_tracing.is_some() {
+        Some("which are not mirrored to `tracing`")
+    } else {
+        None
+    };
+    if let Some(reason) = reason {
+        let message = format!(
+            "Probe \"{}\" cannot be fired from a signal handler, which is only supported for \
+            probes {}",
+            probe_name, reason
+        );
+        return quote! {
+            (signal_safe $args_lambda:expr) => {
+                compile_error!(#message);
+            };
+        };
+    }
+    let allow_lints = allow_generated_lints();
+    quote! {
+        (signal_safe $args_lambda:expr) => {
+            {
+                let __usdt_private_args_lambda = $args_lambda;
+                #allow_lints
+                {
+                    #body
+                }
+            }
+        };
+    }
+}
+
+// Construct the macro arm accepting `if $predicate, $args_lambda`, which fires the probe only if
+// it is enabled and the predicate is true. The predicate is only evaluated if the probe is
+// enabled, as checked by the given expression, so that a disabled probe costs no more than usual.
+// The probe is then fired by the body, which checks whether it is enabled again.
+fn build_predicate_match(
+    config: &crate::CompileProvidersConfig,
+    is_enabled: &TokenStream,
+    body: TokenStream,
+) -> TokenStream {
+    let register = if config.auto_register {
+        quote! { ::usdt::register_probes_once(); }
+    } else {
+        quote! {}
+    };
+    let (_, skipped) = probe_status(config);
+    let allow_lints = allow_generated_lints();
+    quote! {
+        (if $predicate:expr, $args_lambda:expr) => {
+            {
+                let __usdt_private_args_lambda = $args_lambda;
+                let __usdt_private_is_enabled: bool;
+                #allow_lints
+                {
+                    #register
+                    __usdt_private_is_enabled = #is_enabled;
+                }
+                if __usdt_private_is_enabled && $predicate {
+                    #allow_lints
+                    {
+                        #body
+                    }
+                } else {
+                    #skipped
+                }
+            }
+        };
+    }
+}
+
+// Return the values to which a probe macro evaluates when the probe fires, and when it is skipped
+// because it isn't enabled. If the macro doesn't return its status, these are empty, so that it
+// evaluates to `()`.
+pub(crate) fn probe_status(config: &crate::CompileProvidersConfig) -> (TokenStream, TokenStream) {
+    if config.return_status {
+        (quote! { true }, quote! { false })
+    } else {
+        (quote! {}, quote! {})
+    }
+}
+
+// Return the attribute allowing lints in the code generated at each probe site, so that firing
+// probes in a crate which enables stricter lints, such as `clippy::pedantic`, doesn't pollute its
+// output with lints about code it didn't write. The closure passed to the probe macro is bound
+// outside the scope of this attribute, so that it is linted as usual.
+fn allow_generated_lints() -> TokenStream {
+    quote! {
+        #[allow(
+            unused_unsafe,
+            clippy::all,
+            clippy::pedantic,
+            clippy::nursery,
+            clippy::restriction
+        )]
+    }
+}
+
+// Construct the macro arm accepting arguments by name, as in `request_id = a, bytes = b`, in any
+// order. The values are assigned to the fields of a struct named for the probe, whose fields are
+// then passed to the probe in the order of its arguments, as if from a closure. A misspelled or
+// missing name is reported by the compiler as an unknown or missing field of that struct.
+//
+// Only probes whose arguments are all named, with distinct names, accept named arguments. For any
+// other probe with arguments, the arm is a compile error.
+fn build_named_args_match(
+    macro_path: &TokenStream,
+    probe_name: &str,
+    names: &[Option<&str>],
+) -> TokenStream {
+    if names.is_empty() {
+        return quote! {};
+    }
+    let mut fields = Vec::with_capacity(names.len());
+    for name in names.iter() {
+        match name {
+            Some(name) if !fields.contains(name) => fields.push(*name),
+            _ => {
+                let message = format!(
+                    "Probe \"{}\" does not accept named arguments, which are only supported for \
+                    probes whose arguments all have distinct names",
+                    probe_name
+                );
+                return quote! {
+                    ($($name:ident = $value:expr),+ $(,)?) => {
+                        compile_error!(#message);
+                    };
+                };
+            }
+        }
+    }
+    let fields = fields
+        .iter()
+        .map(|name| format_ident!("{}", name))
+        .collect::<Vec<_>>();
+    let generics = (0..fields.len())
+        .map(|i| format_ident!("T{}", i))
+        .collect::<Vec<_>>();
+    let struct_name = format_ident!("__usdt_{}_arguments", probe_name);
+    let args = if fields.len() == 1 {
+        quote! { args.#(#fields)* }
+    } else {
+        quote! { (#(args.#fields),*) }
+    };
+    quote! {
+        ($($name:ident = $value:expr),+ $(,)?) => {
+            $crate::#macro_path!(|| {
+                #[allow(non_camel_case_types)]
+                struct #struct_name<#(#generics),*> {
+                    #(#fields: #generics,)*
+                }
+                let args = #struct_name { $($name: $value),+ };
+                #args
+            })
+        };
+    }
+}
+
+// Return the values of the arguments supplied by the probe macro itself, before the declared
+// arguments: a sequence number, if the `with_sequence` option is set, then the ID of the firing
+// thread, if the `with_tid` option is set. Each is a `u64`.
+pub(crate) fn implicit_arguments(config: &crate::CompileProvidersConfig) -> Vec<TokenStream> {
+    let mut args = Vec::new();
+    if config.with_sequence {
+        args.push(quote! { ::usdt::next_sequence() });
+    }
+    if config.with_tid {
+        args.push(quote! { ::usdt::thread_id() });
+    }
+    args
+}
+
+// Return the provider with the implicit arguments added to the start of each probe. These are
+// supplied by the probe macro itself, and are not part of the arguments returned by the closure.
+pub(crate) fn add_implicit_arguments(
+    provider: &Provider,
+    config: &crate::CompileProvidersConfig,
+) -> Provider {
+    let mut provider = provider.clone();
+    for _ in implicit_arguments(config) {
+        for probe in provider.probes.iter_mut() {
+            probe
+                .types
+                .insert(0, DataType::Native(dtrace_parser::DataType::U64));
+            if !probe.arg_names.is_empty() {
+                probe.arg_names.insert(0, None);
+            }
+        }
+    }
+    provider
+}
+
+// Return code rebinding the argument closure to one which also returns the implicit arguments, as
+// its first elements, before the declared arguments. The closure is only called when the probe
+// fires, so the implicit arguments are only computed then.
+fn prepend_implicit_arguments(implicit: &[TokenStream], declared: &[DataType]) -> TokenStream {
+    let call = quote! { __usdt_private_user_args_lambda() };
+    let args = match declared.len() {
+        // A closure for a single argument returns it bare, rather than in a tuple.
+        0 if implicit.len() == 1 => quote! { { #call; #(#implicit)* } },
+        0 => quote! { { #call; (#(#implicit),*) } },
+        1 => quote! { (#(#implicit,)* #call) },
+        n => {
+            let indices = (0..n).map(syn::Index::from);
+            quote! {
+                {
+                    let args = #call;
+                    (#(#implicit,)* #(args.#indices,)*)
+                }
+            }
+        }
+    };
+    quote! {
+        let __usdt_private_args_lambda = {
+            let __usdt_private_user_args_lambda = __usdt_private_args_lambda;
+            move || #args
+        };
+    }
+}
+
+// Return an error message if an argument has a type which is lowered in a way the `strict` option
+// rejects: a primitive Rust type with no D analog, such as `u128` or `f64`, or a zero-sized type
+// such as `()`. These are accepted as serializable types, and so are passed to the probe as JSON
+// strings, which is rarely what was intended.
+fn check_strict_argument(probe_name: &str, typ: &DataType) -> Option<String> {
+    let ty = match typ {
+        DataType::Serializable(ty) => ty,
+        _ => return None,
+    };
+    let mut inner = ty;
+    while let syn::Type::Reference(reference) = inner {
+        inner = &reference.elem;
+    }
+    let reason = match inner {
+        syn::Type::Tuple(tuple) if tuple.elems.is_empty() => "is zero-sized",
+        syn::Type::Array(syn::TypeArray {
+            len:
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Int(len),
+                    ..
+                }),
+            ..
+        }) if len.base10_digits() == "0" => "is zero-sized",
+        syn::Type::Path(path)
+            if matches!(
+                path.path.get_ident().map(ToString::to_string).as_deref(),
+                Some("u128" | "i128" | "usize" | "isize" | "f32" | "f64" | "bool")
+            ) =>
+        {
+            "has no fixed-width integer type in D"
+        }
+        _ => return None,
+    };
+    Some(format!(
+        "Probe \"{}\" has an argument of type `{}`, which {}, and so would be passed to the \
+        probe as JSON. This is not allowed with the `strict` option",
+        probe_name,
+        quote! { #ty }.to_string().replace(' ', ""),
+        reason,
+    ))
+}
+
+// Return the width in bytes of a D argument type, if known.
+fn c_type_width(c_type: &str) -> Option<usize> {
+    match c_type {
+        "uint8_t" | "int8_t" => Some(1),
+        "uint16_t" | "int16_t" => Some(2),
+        "uint32_t" | "int32_t" => Some(4),
+        "uint64_t" | "int64_t" => Some(8),
+        _ if c_type.ends_with('*') => Some(8),
+        _ => None,
+    }
+}
+
+// Return the width in bytes of the Rust type used to pass an argument across the FFI boundary, if
+// known. Only 64-bit targets are supported, so pointers are always 8 bytes.
+fn rust_ffi_type_width(ffi_type: &syn::Type) -> Option<usize> {
+    match ffi_type {
+        syn::Type::Ptr(_) => Some(8),
+        syn::Type::Path(path) => match path.path.segments.last()?.ident.to_string().as_str() {
+            "c_uchar" | "c_schar" | "c_char" => Some(1),
+            "c_ushort" | "c_short" => Some(2),
+            "c_uint" | "c_int" => Some(4),
+            "c_ulonglong" | "c_longlong" => Some(8),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Check that the D type and the Rust FFI type of an argument have the same width. If they do not,
+// DTrace reads a different number of bytes than the probe passes, and so reports garbage.
+fn check_argument_width(
+    probe_name: &str,
+    index: usize,
+    c_type: &str,
+    ffi_type: &syn::Type,
+) -> Result<(), String> {
+    match (c_type_width(c_type), rust_ffi_type_width(ffi_type)) {
+        (Some(c_width), Some(ffi_width)) if c_width == ffi_width => Ok(()),
+        (c_width, ffi_width) => Err(format!(
+            "Argument {} of probe \"{}\" is declared as `{}` ({} bytes), but is passed as `{}` \
+            ({} bytes)",
+            index,
+            probe_name,
+            c_type,
+            c_width.map_or_else(|| String::from("unknown"), |w| w.to_string()),
+            quote! { #ffi_type }.to_string().replace(' ', ""),
+            ffi_width.map_or_else(|| String::from("unknown"), |w| w.to_string()),
+        )),
+    }
+}
+
 pub(crate) fn build_probe_macro(
     config: &crate::CompileProvidersConfig,
     provider: &Provider,
@@ -211,33 +864,189 @@ pub(crate) fn build_probe_macro(
     types: &[DataType],
     pre_macro_block: TokenStream,
     impl_block: TokenStream,
+    is_enabled: TokenStream,
 ) -> TokenStream {
+    if config.no_std {
+        // Strings and serializable types are lowered into an allocated, null-terminated buffer.
+        let needs_std = types.iter().find(|typ| {
+            matches!(
+                typ,
+                DataType::Native(dtrace_parser::DataType::String) | DataType::Serializable(_)
+            )
+        });
+        if let Some(typ) = needs_std {
+            let ty = typ.to_rust_type();
+            let message = format!(
+                "Probe \"{}\" has an argument of type `{}`, which is not supported with the \
+                `no_std` option",
+                probe_name,
+                quote! { #ty }.to_string().replace(' ', ""),
+            );
+            return quote! { compile_error!(#message); };
+        }
+    }
+    for (i, typ) in types.iter().enumerate() {
+        if let Err(message) =
+            check_argument_width(probe_name, i, &typ.to_c_type(), &typ.to_rust_ffi_type())
+        {
+            return quote! { compile_error!(#message); };
+        }
+    }
+    if config.strict {
+        if let Some(message) = types
+            .iter()
+            .find_map(|typ| check_strict_argument(probe_name, typ))
+        {
+            return quote! { compile_error!(#message); };
+        }
+    }
+    // The closure returns only the declared arguments, without any implicit arguments.
+    let implicit = implicit_arguments(config);
+    let declared = &types[implicit.len()..];
+    let implicit = if implicit.is_empty() {
+        quote! {}
+    } else {
+        prepend_implicit_arguments(&implicit, declared)
+    };
     let macro_path = config.macro_path(&provider.name, probe_name);
     let macro_name = config.probe_ident(&provider.name, probe_name);
-    let type_check_block =
-        generate_type_check(&provider.name, &provider.use_statements, probe_name, types);
-    let no_args_match = if types.is_empty() {
-        quote! { () => { crate::#macro_path!(|| ()) }; }
+    // While probes are disabled process-wide, every probe is skipped without checking whether it is
+    // enabled, or evaluating its arguments.
+    let (_, skipped) = probe_status(config);
+    let impl_block = quote! {
+        if ::usdt::probes_globally_enabled() {
+            #impl_block
+        } else {
+            #skipped
+        }
+    };
+    let is_enabled = quote! { ::usdt::probes_globally_enabled() && #is_enabled };
+    let type_check_block = if config.export {
+        let use_statements = provider.use_statements.iter().map(export_use_statement);
+        let type_check_block = generate_type_check(&provider.name, &[], probe_name, declared);
+        quote! {
+            #(
+                #[allow(unused_imports)]
+                #use_statements
+            )*
+            #type_check_block
+        }
+    } else {
+        generate_type_check(
+            &provider.name,
+            &provider.use_statements,
+            probe_name,
+            declared,
+        )
+    };
+    let register = if config.auto_register {
+        quote! { ::usdt::register_probes_once(); }
     } else {
         quote! {}
     };
+    // A probe without arguments may be fired without a closure, or with a trailing comma after it.
+    let (no_args_match, trailing_comma) = if declared.is_empty() {
+        (
+            quote! { () => { $crate::#macro_path!(|| ()) }; },
+            quote! { $(,)? },
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
+    let raw_args_match = build_raw_args_match(&macro_path, probe_name, declared);
+    let probe = provider
+        .probes
+        .iter()
+        .find(|probe| probe.name == probe_name);
+    let names = (types.len() - declared.len()..types.len())
+        .map(|i| probe.and_then(|probe| probe.arg_name(i)))
+        .collect::<Vec<_>>();
+    let named_args_match = build_named_args_match(&macro_path, probe_name, &names);
+    let predicate_match = build_predicate_match(
+        config,
+        &is_enabled,
+        quote! {
+            #type_check_block
+            #implicit
+            #impl_block
+        },
+    );
+    let signal_safe_match = build_signal_safe_match(
+        config,
+        probe_name,
+        declared,
+        quote! {
+            #type_check_block
+            #implicit
+            #impl_block
+        },
+    );
+    let docs = crate::doc_lines(probe.and_then(|probe| probe.docs.as_deref()));
+    let allow_lints = allow_generated_lints();
+    // A macro may only be used outside of its crate if it's exported, which places it at the root
+    // of the crate. It's given a private name there, and re-exported from the provider's module.
+    let (export, definition_name, reexport) = if config.export {
+        let modules = config
+            .provider_modules(&provider.name)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let exported = format_ident!("__usdt_private_export_{}_{}", modules.join("_"), macro_name);
+        (
+            quote! { #[doc(hidden)] #[macro_export] },
+            exported.clone(),
+            quote! { pub use #exported as #macro_name; },
+        )
+    } else {
+        (
+            quote! {},
+            macro_name.clone(),
+            quote! { pub(crate) use #macro_name; },
+        )
+    };
     quote! {
         #pre_macro_block
+        #(#[doc = #docs])*
+        #export
         #[allow(unused_macros)]
-        macro_rules! #macro_name {
+        macro_rules! #definition_name {
+            #predicate_match
             #no_args_match
+            #raw_args_match
+            #signal_safe_match
+            #named_args_match
             ($tree:tt) => {
                 compile_error!("USDT probe macros should be invoked with a closure returning the arguments");
             };
-            ($args_lambda:expr) => {
+            ($args_lambda:expr #trailing_comma) => {
                 {
-                    #type_check_block
-                    #impl_block
+                    let __usdt_private_args_lambda = $args_lambda;
+                    #allow_lints
+                    {
+                        #type_check_block
+                        #register
+                        #implicit
+                        #impl_block
+                    }
                 }
             };
         }
         #[allow(unused_imports)]
-        pub(crate) use #macro_name;
+        #reexport
+    }
+}
+
+// Return a use statement which may be repeated in an exported probe macro. A path relative to the
+// root of the crate defining the provider must be made relative to `$crate`, since the macro may
+// be invoked from another crate.
+fn export_use_statement(use_statement: &syn::ItemUse) -> TokenStream {
+    match &use_statement.tree {
+        syn::UseTree::Path(path) if path.ident == "crate" => {
+            let attrs = &use_statement.attrs;
+            let tree = &path.tree;
+            quote! { #(#attrs)* use $crate::#tree; }
+        }
+        _ => quote! { #use_statement },
     }
 }
 
@@ -245,12 +1054,12 @@ pub(crate) fn build_probe_macro(
 mod tests {
 
     use super::*;
+    use crate::Probe;
 
     #[test]
     fn test_generate_type_check_empty() {
         let types = &[];
         let expected = quote! {
-            let __usdt_private_args_lambda = $args_lambda;
             let _ = || {
                 let _: () = __usdt_private_args_lambda();
             };
@@ -268,11 +1077,9 @@ mod tests {
             DataType::Native(dtrace_parser::DataType::I64),
         ];
         let expected = quote! {
-            let __usdt_private_args_lambda = $args_lambda;
-            #[allow(unused_imports)]
             fn __usdt_private_provider_probe_type_check(
-                _: impl ::std::borrow::Borrow<u8>,
-                _: impl ::std::borrow::Borrow<i64>
+                _: impl ::usdt::IntoProbeArg<u8>,
+                _: impl ::usdt::IntoProbeArg<i64>
             ) { }
             let _ = || {
                 let args = __usdt_private_args_lambda.clone()();
@@ -290,9 +1097,9 @@ mod tests {
         let types = &[DataType::Native(dtrace_parser::DataType::String)];
         let use_statements = vec![];
         let expected = quote! {
-            let __usdt_private_args_lambda = $args_lambda;
-            #[allow(unused_imports)]
-            fn __usdt_private_provider_probe_type_check(_: impl AsRef<str>) { }
+            fn __usdt_private_provider_probe_type_check<__UsdtStrKind0>(
+                _: impl ::usdt::ProbeStr<__UsdtStrKind0>
+            ) { }
             let _ = || {
                 let args = (__usdt_private_args_lambda.clone()(),);
                 __usdt_private_provider_probe_type_check(args.0);
@@ -309,8 +1116,6 @@ mod tests {
         let types = &[DataType::Serializable(syn::parse_str("&[u8]").unwrap())];
         let use_statements = vec![];
         let expected = quote! {
-            let __usdt_private_args_lambda = $args_lambda;
-            #[allow(unused_imports)]
             fn __usdt_private_provider_probe_type_check(_: impl AsRef<[u8]>) { }
             let _ = || {
                 let args = (__usdt_private_args_lambda.clone()(),);
@@ -328,10 +1133,9 @@ mod tests {
         let types = &[DataType::Serializable(syn::parse_str("MyType").unwrap())];
         let use_statements = vec![syn::parse2(quote! { use my_module::MyType; }).unwrap()];
         let expected = quote! {
-            let __usdt_private_args_lambda = $args_lambda;
             #[allow(unused_imports)]
             use my_module::MyType;
-            fn __usdt_private_provider_probe_type_check(_: impl ::std::borrow::Borrow<MyType>) { }
+            fn __usdt_private_provider_probe_type_check(_: impl ::core::borrow::Borrow<MyType>) { }
             let _ = || {
                 let args = (__usdt_private_args_lambda.clone()(),);
                 __usdt_private_provider_probe_type_check(args.0);
@@ -341,6 +1145,51 @@ mod tests {
         assert_eq!(block.to_string(), expected.to_string());
     }
 
+    #[test]
+    fn test_generate_type_check_allows_each_unused_import() {
+        let types = &[DataType::Serializable(syn::parse_str("First").unwrap())];
+        let use_statements = vec![
+            syn::parse2(quote! { use my_module::First; }).unwrap(),
+            syn::parse2(quote! { use my_module::Second; }).unwrap(),
+        ];
+        let block = generate_type_check("provider", &use_statements, "probe", types).to_string();
+        let allow = quote! { #[allow(unused_imports)] }.to_string();
+        for use_statement in use_statements.iter() {
+            let expected = format!("{} {}", allow, quote! { #use_statement });
+            assert!(block.contains(&expected));
+        }
+    }
+
+    #[test]
+    fn test_construct_probe_args_binds_before_asm() {
+        let types = &[
+            DataType::Native(dtrace_parser::DataType::U64),
+            DataType::Native(dtrace_parser::DataType::String),
+            DataType::Serializable(syn::parse_str("Arg").unwrap()),
+            DataType::UniqueId,
+        ];
+        let (args, regs) =
+            construct_probe_args("probe", types, &crate::CompileProvidersConfig::default());
+        let args = args.to_string();
+        for (i, reg) in regs.to_string().split(',').enumerate() {
+            let reg = reg.replace(" ", "");
+            if reg.is_empty() {
+                continue;
+            }
+            let binding = format!("arg_{}", i);
+            assert!(args.contains(&format!("let {} =", binding)));
+            let operand = reg
+                .split_once(&format!("({}", binding))
+                .expect("Expected register operand to use the argument binding")
+                .1;
+            assert!(
+                operand == ")" || operand == ".as_ptr()asi64)",
+                "Unexpected register operand: {}",
+                reg
+            );
+        }
+    }
+
     #[test]
     fn test_construct_probe_args() {
         let types = &[
@@ -348,11 +1197,15 @@ mod tests {
             DataType::Native(dtrace_parser::DataType::String),
         ];
         let registers = &["rdi", "rsi"];
-        let (args, regs) = construct_probe_args(types);
+        let config = crate::CompileProvidersConfig {
+            target_arch: Some(String::from("x86_64")),
+            ..Default::default()
+        };
+        let (args, regs) = construct_probe_args("probe", types, &config);
         let expected = quote! {
             let args = __usdt_private_args_lambda();
-            let arg_0 = (*<_ as ::std::borrow::Borrow<u8>>::borrow(&args.0) as i64);
-            let arg_1 = [(args.1.as_ref() as &str).as_bytes(), &[0_u8]].concat();
+            let arg_0 = (<_ as ::usdt::IntoProbeArg<u8>>::to_probe_arg(&args.0) as i64);
+            let arg_1 = ::usdt::truncate_probe_str(::usdt::ProbeStr::to_probe_str(&args.1), 256usize);
         };
         assert_eq!(args.to_string(), expected.to_string());
 
@@ -367,27 +1220,620 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_construct_probe_args_target_arch() {
+        let types = &[
+            DataType::Native(dtrace_parser::DataType::U8),
+            DataType::Native(dtrace_parser::DataType::I64),
+        ];
+        let config = crate::CompileProvidersConfig {
+            target_arch: Some(String::from("aarch64")),
+            ..Default::default()
+        };
+        let (_, regs) = construct_probe_args("probe", types, &config);
+        let regs = regs.to_string().replace(" ", "");
+        assert!(regs.starts_with("in(\"x0\")(arg_0),in(\"x1\")(arg_1)"));
+
+        let config = crate::CompileProvidersConfig {
+            target_arch: Some(String::from("riscv64")),
+            ..Default::default()
+        };
+        let (args, regs) = construct_probe_args("probe", types, &config);
+        assert!(args.to_string().starts_with("compile_error !"));
+        assert!(regs.is_empty());
+    }
+
+    #[test]
+    fn test_construct_probe_args_register_pins() {
+        let types = &[
+            DataType::Native(dtrace_parser::DataType::U8),
+            DataType::Native(dtrace_parser::DataType::I64),
+            DataType::Native(dtrace_parser::DataType::U32),
+        ];
+        let mut config = crate::CompileProvidersConfig {
+            target_arch: Some(String::from("x86_64")),
+            ..Default::default()
+        };
+        config.register_pins.insert(
+            String::from("probe"),
+            vec![String::from("r12"), String::from("rax")],
+        );
+        let (_, regs) = construct_probe_args("probe", types, &config);
+        let regs = regs.to_string().replace(" ", "");
+        assert!(regs.starts_with("in(\"r12\")(arg_0),in(\"rax\")(arg_1),in(\"rdx\")(arg_2)"));
+
+        // Pins only apply to the named probe.
+        let (_, regs) = construct_probe_args("other", types, &config);
+        let regs = regs.to_string().replace(" ", "");
+        assert!(regs.starts_with("in(\"rdi\")(arg_0),in(\"rsi\")(arg_1),in(\"rdx\")(arg_2)"));
+    }
+
+    #[test]
+    fn test_construct_probe_args_invalid_register_pins() {
+        let types = &[
+            DataType::Native(dtrace_parser::DataType::U8),
+            DataType::Native(dtrace_parser::DataType::I64),
+            DataType::Native(dtrace_parser::DataType::U32),
+        ];
+        let cases: &[(&[&str], &str)] = &[
+            (
+                &["rdx"],
+                "Arguments 0 and 2 of probe \"probe\" are both passed in register \"rdx\"",
+            ),
+            (&["r12", "r12"], "Arguments 0 and 1"),
+            (&["rbx"], "cannot be pinned to \"rbx\""),
+            (&["rsp"], "cannot be pinned to \"rsp\""),
+            (&["x0"], "cannot be pinned to \"x0\""),
+            (
+                &["rax", "rcx", "rdx", "rsi"],
+                "pins 4 arguments to registers, but has only 3",
+            ),
+        ];
+        for (pins, message) in cases.iter() {
+            let mut config = crate::CompileProvidersConfig {
+                target_arch: Some(String::from("x86_64")),
+                ..Default::default()
+            };
+            config.register_pins.insert(
+                String::from("probe"),
+                pins.iter().map(|pin| pin.to_string()).collect(),
+            );
+            let (args, regs) = construct_probe_args("probe", types, &config);
+            let args = args.to_string();
+            assert!(args.starts_with("compile_error !"));
+            assert!(args.contains(&message.replace('"', "\\\"")), "{}", args);
+            assert!(regs.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_build_probe_macro_auto_register() {
+        let provider = Provider {
+            name: String::from("foo"),
+            probes: vec![],
+            use_statements: vec![],
+        };
+        let impl_block = quote! { fire_probe(); };
+        let config = crate::CompileProvidersConfig::default();
+        let output = build_probe_macro(
+            &config,
+            &provider,
+            "bar",
+            &[],
+            quote! {},
+            impl_block.clone(),
+            quote! { true },
+        );
+        assert!(!output.to_string().contains("register_probes_once"));
+
+        let config = crate::CompileProvidersConfig {
+            auto_register: true,
+            ..Default::default()
+        };
+        let output = build_probe_macro(
+            &config,
+            &provider,
+            "bar",
+            &[],
+            quote! {},
+            impl_block,
+            quote! { true },
+        );
+        let output = output.to_string();
+        let register = output
+            .find(":: usdt :: register_probes_once () ;")
+            .expect("Expected probe to register probes");
+        assert!(register < output.rfind("fire_probe").unwrap());
+    }
+
+    #[test]
+    fn test_build_probe_macro_trailing_comma() {
+        let provider = Provider {
+            name: String::from("foo"),
+            probes: vec![],
+            use_statements: vec![],
+        };
+        let config = crate::CompileProvidersConfig::default();
+        let build = |types: &[DataType]| {
+            build_probe_macro(
+                &config,
+                &provider,
+                "bar",
+                types,
+                quote! {},
+                quote! { fire_probe(); },
+                quote! { true },
+            )
+            .to_string()
+        };
+
+        // Only probes without arguments accept a trailing comma after the closure.
+        assert!(build(&[]).contains("($ args_lambda : expr $ (,) ?)"));
+        let output = build(&[DataType::Native(dtrace_parser::DataType::U8)]);
+        assert!(output.contains("($ args_lambda : expr)"));
+        assert!(!output.contains("($ args_lambda : expr $ (,) ?)"));
+    }
+
+    #[test]
+    fn test_build_probe_macro_export() {
+        let provider = Provider {
+            name: String::from("foo"),
+            probes: vec![],
+            use_statements: vec![
+                syn::parse2(quote! { use crate::Arg; }).unwrap(),
+                syn::parse2(quote! { use std::net::Ipv6Addr; }).unwrap(),
+            ],
+        };
+        let types = [DataType::Native(dtrace_parser::DataType::U8)];
+        let output = build_probe_macro(
+            &crate::CompileProvidersConfig::default(),
+            &provider,
+            "bar",
+            &types,
+            quote! {},
+            quote! {},
+            quote! { true },
+        )
+        .to_string();
+        assert!(!output.contains("macro_export"));
+        assert!(output.contains("macro_rules ! bar"));
+        assert!(output.contains(&quote! { pub(crate) use bar; }.to_string()));
+        assert!(output.contains(&quote! { use crate::Arg; }.to_string()));
+
+        // The macro is exported under a private name, and re-exported from the module, with paths
+        // to items in the provider's crate relative to `$crate`.
+        let config = crate::CompileProvidersConfig {
+            export: true,
+            ..Default::default()
+        };
+        let output = build_probe_macro(
+            &config,
+            &provider,
+            "bar",
+            &types,
+            quote! {},
+            quote! {},
+            quote! { true },
+        )
+        .to_string();
+        let expected = quote! {
+            #[doc(hidden)]
+            #[macro_export]
+            #[allow(unused_macros)]
+            macro_rules! __usdt_private_export_foo_bar
+        };
+        assert!(output.contains(&expected.to_string()));
+        assert!(
+            output.contains(&quote! { pub use __usdt_private_export_foo_bar as bar; }.to_string())
+        );
+        assert!(output.contains(&quote! { use $crate::Arg; }.to_string()));
+        assert!(output.contains(&quote! { use std::net::Ipv6Addr; }.to_string()));
+        assert!(!output.contains(&quote! { use crate::Arg; }.to_string()));
+    }
+
+    #[test]
+    fn test_build_probe_macro_no_std() {
+        let provider = Provider {
+            name: String::from("foo"),
+            probes: vec![],
+            use_statements: vec![],
+        };
+        let config = crate::CompileProvidersConfig {
+            no_std: true,
+            ..Default::default()
+        };
+        let types = &[
+            DataType::Native(dtrace_parser::DataType::U8),
+            DataType::UniqueId,
+        ];
+        let output = build_probe_macro(
+            &config,
+            &provider,
+            "bar",
+            types,
+            quote! {},
+            quote! {},
+            quote! { true },
+        );
+        let output = output.to_string();
+        assert!(output.contains("macro_rules ! bar"));
+        assert!(!output.contains("::std"));
+
+        let types = &[
+            DataType::Native(dtrace_parser::DataType::U8),
+            DataType::Native(dtrace_parser::DataType::String),
+        ];
+        let output = build_probe_macro(
+            &config,
+            &provider,
+            "bar",
+            types,
+            quote! {},
+            quote! {},
+            quote! { true },
+        );
+        let output = output.to_string();
+        assert!(output.starts_with("compile_error !"));
+        assert!(output.contains("`&str`"));
+    }
+
+    #[test]
+    fn test_build_enabled_probes_fn() {
+        let provider = Provider {
+            name: String::from("foo"),
+            probes: vec![
+                Probe {
+                    name: String::from("bar"),
+                    types: vec![],
+                    arg_names: vec![],
+                    docs: None,
+                },
+                Probe {
+                    name: String::from("baz"),
+                    types: vec![],
+                    arg_names: vec![],
+                    docs: None,
+                },
+            ],
+            use_statements: vec![],
+        };
+        let config = crate::CompileProvidersConfig::default();
+        let output = build_enabled_probes_fn(&config, &provider, |probe| {
+            let check = format_ident!("{}_enabled", probe.name);
+            quote! { #check() }
+        })
+        .to_string();
+        assert!(output.contains("fn enabled_probes"));
+        assert!(output.contains(&quote! { if bar_enabled() { enabled.push("bar"); } }.to_string()));
+        assert!(output.contains(&quote! { if baz_enabled() { enabled.push("baz"); } }.to_string()));
+
+        for config in [
+            crate::CompileProvidersConfig {
+                probe_path: Some(String::from("")),
+                ..Default::default()
+            },
+            crate::CompileProvidersConfig {
+                no_std: true,
+                ..Default::default()
+            },
+        ] {
+            let output = build_enabled_probes_fn(&config, &provider, |_| quote! { true });
+            assert!(output.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_build_probe_macro_strict() {
+        let provider = Provider {
+            name: String::from("foo"),
+            probes: vec![],
+            use_statements: vec![],
+        };
+        let strict = crate::CompileProvidersConfig {
+            strict: true,
+            ..Default::default()
+        };
+        let lenient = crate::CompileProvidersConfig::default();
+        for (ty, reason) in [
+            ("u128", "no fixed-width integer type"),
+            ("&f64", "no fixed-width integer type"),
+            ("()", "zero-sized"),
+            ("[u8; 0]", "zero-sized"),
+        ] {
+            let types = &[
+                DataType::Native(dtrace_parser::DataType::U8),
+                DataType::Serializable(syn::parse_str(ty).unwrap()),
+            ];
+            let output = build_probe_macro(
+                &lenient,
+                &provider,
+                "bar",
+                types,
+                quote! {},
+                quote! {},
+                quote! { true },
+            );
+            assert!(output.to_string().contains("macro_rules ! bar"), "{}", ty);
+
+            let output = build_probe_macro(
+                &strict,
+                &provider,
+                "bar",
+                types,
+                quote! {},
+                quote! {},
+                quote! { true },
+            );
+            let output = output.to_string();
+            assert!(output.starts_with("compile_error !"), "{}", ty);
+            assert!(output.contains("\\\"bar\\\""), "{}", ty);
+            assert!(
+                output.contains(&format!("`{}`", ty.replace(' ', ""))),
+                "{}",
+                ty
+            );
+            assert!(output.contains(reason), "{}", ty);
+        }
+
+        // Natively supported and structured serializable types are allowed.
+        let types = &[
+            DataType::Native(dtrace_parser::DataType::String),
+            DataType::Serializable(syn::parse_str("&[u8]").unwrap()),
+            DataType::Serializable(syn::parse_str("MyType").unwrap()),
+            DataType::UniqueId,
+        ];
+        let output = build_probe_macro(
+            &strict,
+            &provider,
+            "bar",
+            types,
+            quote! {},
+            quote! {},
+            quote! { true },
+        );
+        assert!(output.to_string().contains("macro_rules ! bar"));
+    }
+
+    #[test]
+    fn test_build_probe_macro_docs() {
+        let provider = Provider {
+            name: String::from("foo"),
+            probes: vec![Probe {
+                name: String::from("bar"),
+                types: vec![],
+                arg_names: vec![],
+                docs: Some(String::from("Fired when work begins.\n\nWith details.")),
+            }],
+            use_statements: vec![],
+        };
+        let config = crate::CompileProvidersConfig::default();
+        let output = build_probe_macro(
+            &config,
+            &provider,
+            "bar",
+            &[],
+            quote! {},
+            quote! {},
+            quote! { true },
+        );
+        let expected = quote! {
+            #[doc = " Fired when work begins."]
+            #[doc = ""]
+            #[doc = " With details."]
+            #[allow(unused_macros)]
+            macro_rules! bar
+        };
+        assert!(output.to_string().starts_with(&expected.to_string()));
+    }
+
+    #[test]
+    fn test_build_tracing_event() {
+        let types = &[
+            DataType::Native(dtrace_parser::DataType::U8),
+            DataType::Native(dtrace_parser::DataType::String),
+        ];
+        let config = crate::CompileProvidersConfig::default();
+        assert!(build_tracing_event(&config, "foo", "bar", types).is_empty());
+
+        let config = crate::CompileProvidersConfig {
+            mirror_tracing: Some(String::from("info")),
+            ..Default::default()
+        };
+        let expected = quote! {
+            {
+                let __usdt_private_arg_0 = <_ as ::usdt::IntoProbeArg<u8>>::to_probe_arg(&args.0);
+                let __usdt_private_arg_1 = ::usdt::ProbeStr::to_probe_string(&args.1);
+                ::usdt::tracing::event!(
+                    target: "foo::bar",
+                    ::usdt::tracing::Level::INFO,
+                    arg0 = __usdt_private_arg_0,
+                    arg1 = __usdt_private_arg_1.as_ref(),
+                    "bar"
+                );
+            }
+        };
+        assert_eq!(
+            build_tracing_event(&config, "foo", "bar", types).to_string(),
+            expected.to_string()
+        );
+
+        let config = crate::CompileProvidersConfig {
+            mirror_tracing: Some(String::from("loud")),
+            ..Default::default()
+        };
+        let output = build_tracing_event(&config, "foo", "bar", types).to_string();
+        assert!(output.starts_with("compile_error !"));
+    }
+
     #[test]
     fn test_asm_type_convert() {
         use std::str::FromStr;
+        let config = crate::CompileProvidersConfig {
+            max_string_len: Some(16),
+            ..Default::default()
+        };
         let (out, post) = asm_type_convert(
             &DataType::Native(dtrace_parser::DataType::U8),
             TokenStream::from_str("foo").unwrap(),
+            &config,
         );
         assert_eq!(
             out.to_string(),
-            quote! {(*<_ as ::std::borrow::Borrow<u8>>::borrow(&foo) as i64)}.to_string()
+            quote! {(<_ as ::usdt::IntoProbeArg<u8>>::to_probe_arg(&foo) as i64)}.to_string()
         );
         assert_eq!(post.to_string(), quote! {}.to_string());
 
         let (out, post) = asm_type_convert(
             &DataType::Native(dtrace_parser::DataType::String),
             TokenStream::from_str("foo").unwrap(),
+            &config,
         );
         assert_eq!(
             out.to_string(),
-            quote! { [(foo.as_ref() as &str).as_bytes(), &[0_u8]].concat() }.to_string()
+            quote! { ::usdt::truncate_probe_str(::usdt::ProbeStr::to_probe_str(&foo), 16usize) }
+                .to_string()
         );
         assert_eq!(post.to_string(), quote! { .as_ptr() as i64 }.to_string());
     }
+
+    #[test]
+    fn test_build_raw_args_match() {
+        let macro_path = quote! { prov::probe };
+        let types = [
+            DataType::Native(dtrace_parser::DataType::U8),
+            DataType::Native(dtrace_parser::DataType::I64),
+        ];
+        let output = build_raw_args_match(&macro_path, "probe", &types).to_string();
+        let expected = quote! {
+            $crate::prov::probe!(|| (
+                __usdt_private_raw_args[0usize] as u8,
+                __usdt_private_raw_args[1usize] as i64
+            ))
+        };
+        assert!(output.contains(&expected.to_string()));
+        assert!(output.contains("debug_assert_eq !"));
+
+        // A single argument is not wrapped in a tuple.
+        let types = [DataType::Native(dtrace_parser::DataType::U32)];
+        let output = build_raw_args_match(&macro_path, "probe", &types).to_string();
+        let expected = quote! {
+            $crate::prov::probe!(|| __usdt_private_raw_args[0usize] as u32)
+        };
+        assert!(output.contains(&expected.to_string()));
+
+        let types = [DataType::Native(dtrace_parser::DataType::String)];
+        let output = build_raw_args_match(&macro_path, "probe", &types).to_string();
+        assert!(output.contains("compile_error !"));
+        assert!(!output.contains("__usdt_private_raw_args"));
+    }
+
+    #[test]
+    fn test_build_signal_safe_match() {
+        let types = [DataType::Native(dtrace_parser::DataType::U8)];
+        let body = quote! { fire_probe(); };
+        let config = crate::CompileProvidersConfig::default();
+        let output = build_signal_safe_match(&config, "probe", &types, body.clone()).to_string();
+        assert!(output.starts_with("(signal_safe $ args_lambda : expr)"));
+        assert!(output.contains("fire_probe"));
+
+        let config = crate::CompileProvidersConfig {
+            mirror_tracing: Some(String::from("info")),
+            ..Default::default()
+        };
+        let output = build_signal_safe_match(&config, "probe", &types, body.clone()).to_string();
+        assert!(output.contains("compile_error !"));
+        assert!(!output.contains("fire_probe"));
+
+        let types = [DataType::Native(dtrace_parser::DataType::String)];
+        let config = crate::CompileProvidersConfig::default();
+        let output = build_signal_safe_match(&config, "probe", &types, body).to_string();
+        assert!(output.contains("compile_error !"));
+        assert!(!output.contains("fire_probe"));
+    }
+
+    #[test]
+    fn test_build_predicate_match() {
+        let is_enabled = quote! { probe_enabled() };
+        let body = quote! { fire_probe(); };
+        let config = crate::CompileProvidersConfig::default();
+        let output = build_predicate_match(&config, &is_enabled, body.clone()).to_string();
+        assert!(output.starts_with("(if $ predicate : expr , $ args_lambda : expr)"));
+        // The predicate is only evaluated after the probe is found to be enabled.
+        let enabled = output.find("probe_enabled ()").unwrap();
+        let predicate = output.find("&& $ predicate").unwrap();
+        assert!(enabled < predicate);
+        assert!(predicate < output.find("fire_probe").unwrap());
+        assert!(!output.contains("register_probes_once"));
+
+        let config = crate::CompileProvidersConfig {
+            auto_register: true,
+            return_status: true,
+            ..Default::default()
+        };
+        let output = build_predicate_match(&config, &is_enabled, body).to_string();
+        assert!(
+            output.find("register_probes_once").unwrap() < output.find("probe_enabled").unwrap()
+        );
+        assert!(output.contains("else { false }"));
+    }
+
+    #[test]
+    fn test_build_named_args_match() {
+        let macro_path = quote! { provider::probe };
+        let output = build_named_args_match(&macro_path, "probe", &[]).to_string();
+        assert!(output.is_empty());
+
+        let output =
+            build_named_args_match(&macro_path, "probe", &[Some("request_id"), Some("bytes")])
+                .to_string();
+        assert!(output.starts_with("($ ($ name : ident = $ value : expr) , + $ (,) ?)"));
+        assert!(output.contains("struct __usdt_probe_arguments < T0 , T1 >"));
+        assert!(output.contains("request_id : T0 , bytes : T1 ,"));
+        assert!(output.contains("(args . request_id , args . bytes)"));
+        assert!(!output.contains("compile_error !"));
+
+        let output = build_named_args_match(&macro_path, "probe", &[Some("bytes")]).to_string();
+        assert!(output.contains("args . bytes })"));
+
+        for names in [&[Some("a"), None][..], &[Some("a"), Some("a")][..]].iter() {
+            let output = build_named_args_match(&macro_path, "probe", names).to_string();
+            assert!(output.contains("compile_error !"));
+            assert!(!output.contains("struct"));
+        }
+    }
+
+    #[test]
+    fn test_check_argument_width() {
+        let types = [
+            DataType::Native(dtrace_parser::DataType::U8),
+            DataType::Native(dtrace_parser::DataType::I16),
+            DataType::Native(dtrace_parser::DataType::U32),
+            DataType::Native(dtrace_parser::DataType::I64),
+            DataType::Native(dtrace_parser::DataType::String),
+            DataType::UniqueId,
+            DataType::Serializable(syn::parse_str("Foo").unwrap()),
+        ];
+        for (i, typ) in types.iter().enumerate() {
+            check_argument_width("probe", i, &typ.to_c_type(), &typ.to_rust_ffi_type()).unwrap();
+        }
+
+        let ffi_type = syn::parse_str("::std::os::raw::c_ulonglong").unwrap();
+        let message = check_argument_width("probe", 1, "uint32_t", &ffi_type).unwrap_err();
+        assert_eq!(
+            message,
+            "Argument 1 of probe \"probe\" is declared as `uint32_t` (4 bytes), but is passed as \
+            `::std::os::raw::c_ulonglong` (8 bytes)"
+        );
+
+        let ffi_type = syn::parse_str("u128").unwrap();
+        assert!(check_argument_width("probe", 0, "uint64_t", &ffi_type).is_err());
+    }
+
+    #[test]
+    fn test_construct_probe_args_zero_args() {
+        let (args, regs) =
+            construct_probe_args("probe", &[], &crate::CompileProvidersConfig::default());
+        assert!(args.is_empty());
+        assert!(regs.is_empty());
+    }
 }
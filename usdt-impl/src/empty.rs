@@ -11,38 +11,48 @@ use proc_macro2::TokenStream;
 use quote::quote;
 use std::convert::TryFrom;
 
-pub fn compile_provider_source(
+pub fn compile_providers_source(
     source: &str,
     config: &crate::CompileProvidersConfig,
-) -> Result<TokenStream, crate::Error> {
+) -> Result<Vec<(String, TokenStream)>, crate::Error> {
     let dfile = dtrace_parser::File::try_from(source)?;
     let providers = dfile
         .providers()
         .into_iter()
         .map(|provider| {
             let provider = Provider::from(provider);
-            compile_provider(&provider, config)
+            let tokens = compile_provider(&provider, config);
+            (provider.name, tokens)
         })
         .collect::<Vec<_>>();
-    Ok(quote! {
-        #(#providers)*
-    })
+    Ok(providers)
 }
 
 pub fn compile_provider_from_definition(
     provider: &Provider,
     config: &crate::CompileProvidersConfig,
-) -> TokenStream {
-    compile_provider(provider, config)
+) -> Result<TokenStream, crate::Error> {
+    Ok(compile_provider(provider, config))
 }
 
 fn compile_provider(provider: &Provider, config: &crate::CompileProvidersConfig) -> TokenStream {
+    let provider = &common::add_implicit_arguments(provider, config);
     let probe_impls = provider
         .probes
         .iter()
         .map(|probe| compile_probe(&provider, probe, config))
         .collect::<Vec<_>>();
-    wrap_probes_in_modules(config, provider, quote! { #(#probe_impls)* })
+    let register_fn = common::build_register_provider_fn(config, provider, quote! { Ok(()) });
+    let enabled_fn = common::build_enabled_probes_fn(config, provider, |_| quote! { false });
+    wrap_probes_in_modules(
+        config,
+        provider,
+        quote! {
+            #register_fn
+            #enabled_fn
+            #(#probe_impls)*
+        },
+    )
 }
 
 fn compile_probe(
@@ -50,7 +60,27 @@ fn compile_probe(
     probe: &Probe,
     config: &crate::CompileProvidersConfig,
 ) -> TokenStream {
-    let impl_block = quote! { let _ = || ($args_lambda); };
+    // Probes are never enabled, so they never fire.
+    let (_, skipped) = common::probe_status(config);
+    let impl_block = if config.mirror_tracing.is_some() {
+        // There's no way to tell whether the probe is enabled, so the arguments are always
+        // evaluated when mirroring probes to `tracing`.
+        let preamble = common::unpack_argument_lambda(&probe.types, /* clone = */ false);
+        let tracing_event =
+            common::build_tracing_event(config, &provider.name, &probe.name, &probe.types);
+        quote! {
+            #preamble
+            #tracing_event
+            #skipped
+        }
+    } else {
+        // Refer to the closure bound by the type-check block, rather than expanding `$args_lambda`
+        // a second time, which would move any captured variables twice.
+        quote! {
+            let _ = __usdt_private_args_lambda;
+            #skipped
+        }
+    };
     common::build_probe_macro(
         config,
         provider,
@@ -58,9 +88,26 @@ fn compile_probe(
         &probe.types,
         quote! {},
         impl_block,
+        quote! { false },
     )
 }
 
 pub fn register_probes() -> Result<(), crate::Error> {
     Ok(())
 }
+
+pub fn register_section(_: &[u8]) -> Result<(), crate::Error> {
+    Ok(())
+}
+
+pub fn register_probes_scoped() -> Result<Vec<i32>, crate::Error> {
+    register_probes().map(|_| Vec::new())
+}
+
+pub fn dof_section_size() -> Result<Option<usize>, crate::Error> {
+    Ok(None)
+}
+
+pub fn deregister_probes(_: i32) -> Result<(), crate::Error> {
+    Ok(())
+}
@@ -0,0 +1,632 @@
+//! Parsing of the C header generated by `dtrace -h`, used on macOS.
+//!
+//! Only two kinds of line in the header are of interest: the `#define`s of the quoted stability
+//! and typedefs symbols for each provider, and the `extern` declarations of the probe and
+//! is-enabled functions for each probe. For example:
+//!
+//! ```ignore
+//! #define FOO_STABILITY "___dtrace_stability$foo$v1$1_1_0_1_1_0_1_1_0_1_1_0_1_1_0"
+//! #define FOO_TYPEDEFS "___dtrace_typedefs$foo$v2"
+//! extern void __dtrace_probe$foo$bar$v1$75696e74385f74(uint8_t);
+//! extern int __dtrace_isenabled$foo$bar$v1(void);
+//! ```
+//!
+//! Every other line, including the macros which refer to these symbols, is ignored. A line which
+//! declares one of these symbols but doesn't have the expected shape is an error, rather than
+//! being skipped or partially parsed, so that a change to the format of the header is noticed.
+//!
+//! This module doesn't depend on DTrace, and is compiled on all platforms so that it can be tested
+//! and fuzzed anywhere. It is public for the latter, and so that tools may decode the stability
+//! attributes of a provider from its stability symbol, with [`decode_stability`].
+
+// Copyright 2021 Oxide Computer Company
+
+use std::collections::BTreeMap;
+
+/// The symbols declared in the header for a single provider.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ProviderInfo {
+    pub stability: String,
+    pub typedefs: String,
+    pub is_enabled: BTreeMap<String, String>,
+    pub probes: BTreeMap<String, String>,
+}
+
+/// Extract the symbols declared for each provider in a header generated by `dtrace -h`.
+pub fn extract_providers(header: &str) -> Result<BTreeMap<String, ProviderInfo>, crate::Error> {
+    let mut providers = BTreeMap::new();
+    // `lines` removes a trailing `\r`, but not any other trailing whitespace, which would otherwise
+    // end up in the extracted symbols.
+    for line in header.lines().map(str::trim_end) {
+        if let Some((provider_name, stability)) = is_stability_line(line)? {
+            let info = ProviderInfo {
+                stability: stability.to_string(),
+                ..Default::default()
+            };
+            providers.insert(provider_name.to_string(), info);
+        } else if let Some((provider_name, typedefs)) = is_typedefs_line(line)? {
+            provider_info(&mut providers, provider_name, line)?.typedefs = typedefs.to_string();
+        } else if let Some((provider_name, probe_name, enabled)) = is_enabled_line(line)? {
+            provider_info(&mut providers, provider_name, line)?
+                .is_enabled
+                .insert(probe_name.to_string(), enabled.to_string());
+        } else if let Some((provider_name, probe_name, probe)) = is_probe_line(line)? {
+            provider_info(&mut providers, provider_name, line)?
+                .probes
+                .insert(probe_name.to_string(), probe.to_string());
+        }
+    }
+    Ok(providers)
+}
+
+/// Compute the symbols which `dtrace -h` declares for a provider, without running DTrace.
+///
+/// The symbols are derived from the provider definition alone: the provider and probe names, the
+/// stability attributes of the provider, and the names of the argument types, encoded in
+/// hexadecimal. The providers supported here never use any typedefs other than those built in to
+/// D, and so declare none. Providers must have the default stability attributes, which is the case
+/// unless they are set with `#pragma D attributes`.
+///
+/// DTrace translates `__` in provider and probe names to `-`, which is encoded differently, so
+/// `None` is returned for providers with such names.
+pub fn compute_provider_info(provider: &crate::Provider) -> Option<ProviderInfo> {
+    let names = std::iter::once(&provider.name).chain(provider.probes.iter().map(|p| &p.name));
+    for name in names {
+        if name.contains("__") {
+            return None;
+        }
+    }
+    let mut info = ProviderInfo {
+        stability: format!(
+            "__dtrace_stability${}$v1${}",
+            provider.name, DEFAULT_STABILITY
+        ),
+        typedefs: format!("__dtrace_typedefs${}$v2", provider.name),
+        ..Default::default()
+    };
+    for probe in provider.probes.iter() {
+        let mut symbol = format!("__dtrace_probe${}${}$v1", provider.name, probe.name);
+        for typ in probe.types.iter() {
+            symbol.push('$');
+            symbol.push_str(&encode_type_name(typ));
+        }
+        info.probes.insert(probe.name.clone(), symbol);
+        info.is_enabled.insert(
+            probe.name.clone(),
+            format!("__dtrace_isenabled${}${}$v1", provider.name, probe.name),
+        );
+    }
+    Some(info)
+}
+
+// The default stability attributes of a provider, as the (name, data, class) of each of the
+// provider, module, function, name and arguments: Private, Private, Unknown.
+const DEFAULT_STABILITY: &str = "1_1_0_1_1_0_1_1_0_1_1_0_1_1_0";
+
+/// The stability of a name or of data, from least to most stable, as defined by DTrace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StabilityLevel {
+    Internal,
+    Private,
+    Obsolete,
+    External,
+    Unstable,
+    Evolving,
+    Stable,
+    Standard,
+}
+
+/// The class of systems on which an interface is available, as defined by DTrace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DependencyClass {
+    Unknown,
+    Cpu,
+    Platform,
+    Group,
+    Isa,
+    Common,
+}
+
+/// A set of DTrace stability attributes, as written `Name/Data/Class` in `#pragma D attributes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stability {
+    pub name_state: StabilityLevel,
+    pub data_state: StabilityLevel,
+    pub dependency_class: DependencyClass,
+}
+
+/// The stability attributes of each part of the probes of a provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProviderStability {
+    pub provider: Stability,
+    pub module: Stability,
+    pub function: Stability,
+    pub name: Stability,
+    pub args: Stability,
+}
+
+impl ProviderInfo {
+    /// Decode the stability attributes of the provider from its stability symbol.
+    pub fn decode_stability(&self) -> Result<ProviderStability, crate::Error> {
+        decode_stability(&self.stability)
+    }
+}
+
+/// Decode the stability attributes encoded in a stability symbol, such as
+/// `__dtrace_stability$foo$v1$1_1_0_1_1_0_1_1_0_1_1_0_1_1_0`.
+///
+/// The symbol ends with the numeric name stability, data stability, and dependency class of each
+/// of the provider, module, function, name and arguments, in that order.
+pub fn decode_stability(symbol: &str) -> Result<ProviderStability, crate::Error> {
+    let codes = symbol
+        .rsplit('$')
+        .next()
+        .unwrap_or_default()
+        .split('_')
+        .map(str::parse::<u8>)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| header_parse_error(symbol))?;
+    if codes.len() != 15 {
+        return Err(header_parse_error(symbol));
+    }
+    let attrs = codes
+        .chunks(3)
+        .map(|chunk| {
+            Some(Stability {
+                name_state: stability_level(chunk[0])?,
+                data_state: stability_level(chunk[1])?,
+                dependency_class: dependency_class(chunk[2])?,
+            })
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| header_parse_error(symbol))?;
+    Ok(ProviderStability {
+        provider: attrs[0],
+        module: attrs[1],
+        function: attrs[2],
+        name: attrs[3],
+        args: attrs[4],
+    })
+}
+
+fn stability_level(code: u8) -> Option<StabilityLevel> {
+    use StabilityLevel::*;
+    [
+        Internal, Private, Obsolete, External, Unstable, Evolving, Stable, Standard,
+    ]
+    .get(usize::from(code))
+    .copied()
+}
+
+fn dependency_class(code: u8) -> Option<DependencyClass> {
+    use DependencyClass::*;
+    [Unknown, Cpu, Platform, Group, Isa, Common]
+        .get(usize::from(code))
+        .copied()
+}
+
+// Return the name DTrace gives the type of a probe argument, encoded in hexadecimal.
+fn encode_type_name(typ: &crate::DataType) -> String {
+    let name = match typ.to_c_type().as_str() {
+        "char*" => String::from("char *"),
+        name => String::from(name),
+    };
+    name.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Return the information for a provider referred to by a line of the header. The provider must
+// have been declared by a previous stability line.
+fn provider_info<'a>(
+    providers: &'a mut BTreeMap<String, ProviderInfo>,
+    provider_name: &str,
+    line: &str,
+) -> Result<&'a mut ProviderInfo, crate::Error> {
+    providers
+        .get_mut(provider_name)
+        .ok_or_else(|| header_parse_error(line))
+}
+
+fn header_parse_error(line: &str) -> crate::Error {
+    crate::Error::HeaderParse {
+        line: line.to_string(),
+    }
+}
+
+// Return true if the character may appear in a symbol generated by DTrace.
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '$'
+}
+
+// Split a symbol into its `$`-separated components, returning `None` if there are fewer than
+// `count` of them or any is empty.
+fn symbol_components(symbol: &str, count: usize) -> Option<Vec<&str>> {
+    let components = symbol.split('$').collect::<Vec<_>>();
+    if components.len() < count || components.iter().any(|c| c.is_empty()) {
+        None
+    } else {
+        Some(components)
+    }
+}
+
+// Return the (provider_name, stability) from a line, if it looks like the appropriate #define'd
+// line from the autogenerated header file.
+fn is_stability_line(line: &str) -> Result<Option<(&str, &str)>, crate::Error> {
+    quoted_symbol(line, "___dtrace_stability$")
+}
+
+// Return the (provider_name, typedefs) from a line, if it looks like the appropriate #define'd
+// line from the autogenerated header file.
+fn is_typedefs_line(line: &str) -> Result<Option<(&str, &str)>, crate::Error> {
+    quoted_symbol(line, "___dtrace_typedefs$")
+}
+
+// Return the provider name and symbol from a line containing a string literal which is a symbol
+// starting with `prefix`, of the form `prefix<provider>$<version>...`.
+fn quoted_symbol<'a>(
+    line: &'a str,
+    prefix: &str,
+) -> Result<Option<(&'a str, &'a str)>, crate::Error> {
+    let index = match line.find(prefix) {
+        Some(index) => index,
+        None => return Ok(None),
+    };
+    let err = || header_parse_error(line);
+    // The symbol must be the entire contents of the string literal.
+    if !line[..index].ends_with('"') {
+        return Err(err());
+    }
+    let rest = &line[index..];
+    let symbol = &rest[..rest.find('"').ok_or_else(err)?];
+    if !symbol.chars().all(is_symbol_char) {
+        return Err(err());
+    }
+    let components = symbol_components(symbol, 3).ok_or_else(err)?;
+    // NOTE: The symbol name really needs to be `___dtrace_stability$...`. But that symbol name
+    // will have a "_" prefixed to it during compilation, so we remove the leading one here,
+    // knowing it will be added back.
+    Ok(Some((components[1], &symbol[1..])))
+}
+
+// Return the (provider, probe, enabled) from a line, if it looks like the appropriate extern
+// function declaration from the autogenerated header file.
+fn is_enabled_line(line: &str) -> Result<Option<(&str, &str, &str)>, crate::Error> {
+    extern_symbol(line, "int", "__dtrace_isenabled$")
+}
+
+// Return the (provider, probe, probe) from a line, if it looks like the appropriate extern
+// function declaration from the autogenerated header file.
+fn is_probe_line(line: &str) -> Result<Option<(&str, &str, &str)>, crate::Error> {
+    extern_symbol(line, "void", "__dtrace_probe$")
+}
+
+// Return the provider name, probe name and symbol from a line declaring an extern function with
+// the given return type, whose name is a symbol starting with `prefix`, of the form
+// `prefix<provider>$<probe>$<version>...`. The keywords and symbol may be separated by any
+// whitespace.
+fn extern_symbol<'a>(
+    line: &'a str,
+    return_type: &str,
+    prefix: &str,
+) -> Result<Option<(&'a str, &'a str, &'a str)>, crate::Error> {
+    let mut rest = line.trim_start();
+    for keyword in &["extern", return_type] {
+        rest = match rest.strip_prefix(keyword) {
+            Some(r) if r.starts_with(char::is_whitespace) => r.trim_start(),
+            _ => return Ok(None),
+        };
+    }
+    if !rest.starts_with(prefix) {
+        return Ok(None);
+    }
+    let err = || header_parse_error(line);
+    let end = rest.find(|c| !is_symbol_char(c)).unwrap_or(rest.len());
+    let (symbol, rest) = rest.split_at(end);
+    if !rest.trim_start().starts_with('(') {
+        return Err(err());
+    }
+    let components = symbol_components(symbol, 4).ok_or_else(err)?;
+    Ok(Some((components[1], components[2], symbol)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_is_stability_line() {
+        let line = "this line is ok \"___dtrace_stability$foo$bar\"";
+        let result = is_stability_line(line).unwrap();
+        assert!(result.is_some());
+        assert_eq!(result.unwrap().0, "foo");
+        assert_eq!(result.unwrap().1, "__dtrace_stability$foo$bar");
+        assert!(is_stability_line("bad").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_is_typedefs_line() {
+        let line = "this line is ok \"___dtrace_typedefs$foo$bar\"";
+        let result = is_typedefs_line(line).unwrap();
+        assert!(result.is_some());
+        assert_eq!(result.unwrap().0, "foo");
+        assert_eq!(result.unwrap().1, "__dtrace_typedefs$foo$bar");
+        assert!(is_typedefs_line("bad").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_is_enabled_line() {
+        let line = "extern int __dtrace_isenabled$foo$bar$xxx(void);";
+        let result = is_enabled_line(line).unwrap();
+        assert!(result.is_some());
+        assert_eq!(result.unwrap().0, "foo");
+        assert_eq!(result.unwrap().1, "bar");
+        assert_eq!(result.unwrap().2, "__dtrace_isenabled$foo$bar$xxx");
+        assert!(is_enabled_line("bad").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_is_probe_line() {
+        let line = "extern void __dtrace_probe$foo$bar$xxx(whatever);";
+        let result = is_probe_line(line).unwrap();
+        assert!(result.is_some());
+        assert_eq!(result.unwrap().0, "foo");
+        assert_eq!(result.unwrap().1, "bar");
+        assert_eq!(result.unwrap().2, "__dtrace_probe$foo$bar$xxx");
+        assert!(is_probe_line("bad").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_decode_stability() {
+        let private = Stability {
+            name_state: StabilityLevel::Private,
+            data_state: StabilityLevel::Private,
+            dependency_class: DependencyClass::Unknown,
+        };
+        let info = ProviderInfo {
+            stability: format!("__dtrace_stability$foo$v1${}", DEFAULT_STABILITY),
+            ..Default::default()
+        };
+        assert_eq!(
+            info.decode_stability().unwrap(),
+            ProviderStability {
+                provider: private,
+                module: private,
+                function: private,
+                name: private,
+                args: private,
+            }
+        );
+
+        // `#pragma D attributes Evolving/Stable/ISA provider foo provider`, and
+        // `Standard/Internal/Common` for the arguments.
+        let stability =
+            decode_stability("__dtrace_stability$foo$v1$5_6_4_1_1_0_1_1_0_1_1_0_7_0_5").unwrap();
+        assert_eq!(
+            stability.provider,
+            Stability {
+                name_state: StabilityLevel::Evolving,
+                data_state: StabilityLevel::Stable,
+                dependency_class: DependencyClass::Isa,
+            }
+        );
+        assert_eq!(stability.module, private);
+        assert_eq!(
+            stability.args,
+            Stability {
+                name_state: StabilityLevel::Standard,
+                data_state: StabilityLevel::Internal,
+                dependency_class: DependencyClass::Common,
+            }
+        );
+
+        for symbol in [
+            "__dtrace_stability$foo$v1$1_1_0",
+            "__dtrace_stability$foo$v1$1_1_0_1_1_0_1_1_0_1_1_0_1_1_0_1",
+            "__dtrace_stability$foo$v1$8_1_0_1_1_0_1_1_0_1_1_0_1_1_0",
+            "__dtrace_stability$foo$v1$1_1_6_1_1_0_1_1_0_1_1_0_1_1_0",
+            "__dtrace_stability$foo$v1$1_1_0_1_1_0_1_1_0_1_1_0_1_1_x",
+        ] {
+            assert!(matches!(
+                decode_stability(symbol),
+                Err(crate::Error::HeaderParse { line }) if line == symbol
+            ));
+        }
+    }
+
+    #[test]
+    fn test_extract_providers_crlf() {
+        let header = [
+            "#define FOO_STABILITY \"___dtrace_stability$foo$v1$1_1_0\"",
+            "#define FOO_TYPEDEFS \"___dtrace_typedefs$foo$v2\" \t",
+            "extern int __dtrace_isenabled$foo$bar$v1(void);",
+            "extern void __dtrace_probe$foo$bar$v1(uint8_t);",
+            "",
+        ]
+        .join("\r\n");
+        let providers = extract_providers(&header).unwrap();
+        let info = &providers["foo"];
+        assert_eq!(info.stability, "__dtrace_stability$foo$v1$1_1_0");
+        assert_eq!(info.typedefs, "__dtrace_typedefs$foo$v2");
+        assert_eq!(info.is_enabled["bar"], "__dtrace_isenabled$foo$bar$v1");
+        assert_eq!(info.probes["bar"], "__dtrace_probe$foo$bar$v1");
+    }
+
+    // A complete header, as generated by `dtrace -h`. The macros refer to the same symbols as the
+    // declarations, but must not be mistaken for them.
+    const HEADER: &str = r#"/*
+ * Generated by dtrace(1M).
+ */
+
+#ifndef	_FOO_H
+#define	_FOO_H
+
+#include <unistd.h>
+
+#ifdef	__cplusplus
+extern "C" {
+#endif
+
+#define FOO_STABILITY "___dtrace_stability$foo$v1$1_1_0_1_1_0_1_1_0_1_1_0_1_1_0"
+
+#define FOO_TYPEDEFS "___dtrace_typedefs$foo$v2"
+
+#if !defined(DTRACE_PROBES_DISABLED) || !DTRACE_PROBES_DISABLED
+
+#define	FOO_BAR(arg0, arg1) \
+do { \
+	__asm__ volatile(".reference " FOO_TYPEDEFS); \
+	__dtrace_probe$foo$bar$v1$75696e74385f74$63686172202a(arg0, arg1); \
+	__asm__ volatile(".reference " FOO_STABILITY); \
+} while (0)
+#define	FOO_BAR_ENABLED() \
+	({ int _r = __dtrace_isenabled$foo$bar$v1(); \
+		__asm__ volatile(""); \
+		_r; })
+#define	FOO_BAZ() \
+do { \
+	__asm__ volatile(".reference " FOO_TYPEDEFS); \
+	__dtrace_probe$foo$baz$v1(); \
+	__asm__ volatile(".reference " FOO_STABILITY); \
+} while (0)
+#define	FOO_BAZ_ENABLED() \
+	({ int _r = __dtrace_isenabled$foo$baz$v1(); \
+		__asm__ volatile(""); \
+		_r; })
+
+
+extern void __dtrace_probe$foo$bar$v1$75696e74385f74$63686172202a(uint8_t, char *);
+extern int __dtrace_isenabled$foo$bar$v1(void);
+extern void __dtrace_probe$foo$baz$v1(void);
+extern int __dtrace_isenabled$foo$baz$v1(void);
+
+#else
+
+#define	FOO_BAR(arg0, arg1) \
+do { \
+	} while (0)
+#define	FOO_BAR_ENABLED() (0)
+#define	FOO_BAZ() \
+do { \
+	} while (0)
+#define	FOO_BAZ_ENABLED() (0)
+
+#endif /* !defined(DTRACE_PROBES_DISABLED) || !DTRACE_PROBES_DISABLED */
+
+
+#ifdef	__cplusplus
+}
+#endif
+
+#endif	/* _FOO_H */
+"#;
+
+    fn expected_foo() -> ProviderInfo {
+        let mut info = ProviderInfo {
+            stability: String::from("__dtrace_stability$foo$v1$1_1_0_1_1_0_1_1_0_1_1_0_1_1_0"),
+            typedefs: String::from("__dtrace_typedefs$foo$v2"),
+            ..Default::default()
+        };
+        for probe in ["bar", "baz"].iter() {
+            info.is_enabled.insert(
+                probe.to_string(),
+                format!("__dtrace_isenabled$foo${}$v1", probe),
+            );
+        }
+        info.probes.insert(
+            String::from("bar"),
+            String::from("__dtrace_probe$foo$bar$v1$75696e74385f74$63686172202a"),
+        );
+        info.probes.insert(
+            String::from("baz"),
+            String::from("__dtrace_probe$foo$baz$v1"),
+        );
+        info
+    }
+
+    #[test]
+    fn test_extract_providers_full_header() {
+        let providers = extract_providers(HEADER).unwrap();
+        assert_eq!(providers.len(), 1);
+        assert_eq!(providers["foo"], expected_foo());
+    }
+
+    #[test]
+    fn test_compute_provider_info() {
+        let source = "provider foo { probe bar(uint8_t, char*); probe baz(); };";
+        let dfile = dtrace_parser::File::try_from(source).unwrap();
+        let provider = crate::Provider::from(&dfile.providers()[0]);
+        assert_eq!(compute_provider_info(&provider).unwrap(), expected_foo());
+        assert_eq!(
+            compute_provider_info(&provider),
+            extract_providers(HEADER).unwrap().remove("foo")
+        );
+
+        for source in [
+            "provider foo { probe bar__baz(); };",
+            "provider foo__bar { probe baz(); };",
+        ]
+        .iter()
+        {
+            let dfile = dtrace_parser::File::try_from(*source).unwrap();
+            let provider = crate::Provider::from(&dfile.providers()[0]);
+            assert!(compute_provider_info(&provider).is_none());
+        }
+    }
+
+    #[test]
+    fn test_extract_providers_whitespace_variants() {
+        // Tabs or runs of spaces between the tokens of a declaration, and a space before the
+        // argument list, are all accepted, as is trailing text after the quoted symbols.
+        let header = HEADER
+            .replace("#define FOO_STABILITY ", "#define\tFOO_STABILITY\t")
+            .replace(
+                "\"___dtrace_typedefs$foo$v2\"",
+                "\"___dtrace_typedefs$foo$v2\" /* */",
+            )
+            .replace("extern void ", "extern\tvoid\t")
+            .replace("extern int ", "  extern  int  ")
+            .replace("$v1(void);", "$v1 (void);");
+        assert_ne!(header, HEADER);
+        let providers = extract_providers(&header).unwrap();
+        assert_eq!(providers["foo"], expected_foo());
+    }
+
+    #[test]
+    fn test_malformed_header_lines() {
+        let lines = [
+            // No trailing `$` after the provider name
+            "#define FOO_STABILITY \"___dtrace_stability$foo",
+            // Symbol not enclosed in quotes
+            "#define FOO_STABILITY ___dtrace_stability$foo$v1",
+            // Symbol not the entire string literal
+            "#define FOO_STABILITY \"___dtrace_stability$foo$v1 and more\"",
+            // Empty provider name
+            "#define FOO_STABILITY \"___dtrace_stability$$v1\"",
+            // No `$` after the probe name
+            "extern int __dtrace_isenabled$foo$bar(void);",
+            // No argument list
+            "extern void __dtrace_probe$foo$bar$v1;",
+        ];
+        for line in lines.iter() {
+            match extract_providers(line) {
+                Err(crate::Error::HeaderParse { line: l }) => assert_eq!(&l, line),
+                other => panic!("Expected a header parse error, found {:?}", other),
+            }
+        }
+
+        // A probe for a provider which has not been declared.
+        let line = "extern void __dtrace_probe$foo$bar$v1(void);";
+        assert!(matches!(
+            extract_providers(line),
+            Err(crate::Error::HeaderParse { .. })
+        ));
+    }
+
+    #[test]
+    fn test_extract_providers_truncated_header() {
+        // Truncating the header anywhere may produce an error, but must not panic.
+        for (end, _) in HEADER.char_indices() {
+            let _ = extract_providers(&HEADER[..end]);
+        }
+    }
+}
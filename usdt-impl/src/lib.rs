@@ -4,8 +4,18 @@
 
 #![cfg_attr(feature = "asm", feature(asm))]
 
-use serde::Deserialize;
+// Code generated for probes defined in unit tests refers to this crate as `usdt`.
+#[cfg(test)]
+extern crate self as usdt;
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::ffi::CStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Once;
 use thiserror::Error;
 
 #[cfg(all(
@@ -17,21 +27,59 @@ use thiserror::Error;
 ))]
 pub mod record;
 
-#[cfg_attr(any(target_os = "linux", not(feature = "asm")), allow(dead_code))]
+#[doc(hidden)]
+pub mod header;
+
+#[cfg_attr(
+    any(
+        all(target_os = "linux", not(feature = "stapsdt")),
+        not(feature = "asm"),
+        feature = "mock-probes"
+    ),
+    allow(dead_code)
+)]
 mod common;
 
 #[cfg_attr(
-    feature = "asm",
-    cfg_attr(target_os = "linux", path = "empty.rs"),
+    all(feature = "asm", not(feature = "mock-probes")),
+    cfg_attr(all(target_os = "linux", not(feature = "stapsdt")), path = "empty.rs"),
+    cfg_attr(all(target_os = "linux", feature = "stapsdt"), path = "stapsdt.rs"),
     cfg_attr(target_os = "macos", path = "linker.rs"),
     cfg_attr(
         all(not(target_os = "linux"), not(target_os = "macos")),
         path = "no-linker.rs"
     )
 )]
-#[cfg_attr(not(feature = "asm"), path = "empty.rs")]
+#[cfg_attr(
+    all(not(feature = "asm"), not(feature = "mock-probes")),
+    path = "empty.rs"
+)]
+#[cfg_attr(feature = "mock-probes", path = "mock.rs")]
 mod internal;
 
+// A provider whose probe registers the probes in the test binary when fired, used to test
+// registration with the DTrace helper device. Generated probe macros refer to their provider's
+// module from the crate root, so it's defined here rather than in the backend's tests.
+#[cfg(all(
+    test,
+    feature = "asm",
+    not(feature = "mock-probes"),
+    not(target_os = "linux"),
+    not(target_os = "macos")
+))]
+#[usdt_attr_macro::provider(auto_register = true)]
+mod auto_registered {
+    fn fire() {}
+}
+
+/// Inspect the probes fired by the mock implementation, used with the `mock-probes` feature.
+#[cfg(feature = "mock-probes")]
+pub mod mock {
+    #[doc(hidden)]
+    pub use crate::internal::record_probe;
+    pub use crate::internal::{take_fired_probes, FiredProbe};
+}
+
 /// Register an application's probe points with DTrace.
 ///
 /// This function collects information about the probe points defined in an application and ensures
@@ -48,6 +96,119 @@ pub fn register_probes() -> Result<(), Error> {
     crate::internal::register_probes()
 }
 
+/// Register an application's probe points with DTrace, until the returned guard is dropped.
+///
+/// This behaves like [`register_probes`], but the probes are removed from DTrace when the returned
+/// [`ProbeRegistration`] is dropped, which may be useful for scoping probes to a test or a plugin.
+/// Note that probes are only ever registered once, so the probes will not be registered again by
+/// a later call to this function or `register_probes`.
+///
+/// Probes can only be removed on systems where they are registered by this crate, such as illumos.
+/// Elsewhere, dropping the guard has no effect.
+pub fn register_probes_scoped() -> Result<ProbeRegistration, Error> {
+    crate::internal::register_probes_scoped().map(|generations| ProbeRegistration { generations })
+}
+
+/// Return the size in bytes of the DOF which [`register_probes`] would register with DTrace.
+///
+/// The probe records are extracted and serialized exactly as when registering them, but nothing is
+/// registered, and a later call to `register_probes` still registers the same probes. Probes
+/// which have already been registered are not included. This may be used to plan for the memory
+/// DTrace will use for the probes.
+///
+/// `None` is returned on systems where the DOF is not constructed by this crate, such as macOS,
+/// where it is generated by the linker.
+pub fn dof_section_size() -> Result<Option<usize>, Error> {
+    crate::internal::dof_section_size()
+}
+
+/// A registration of probes with DTrace, returned by [`register_probes_scoped`].
+///
+/// The probes are removed from DTrace when this is dropped. It is safe to leak the registration,
+/// e.g., with `std::mem::forget`, in which case the probes simply remain registered.
+#[derive(Debug)]
+#[must_use = "probes are removed when the registration is dropped"]
+pub struct ProbeRegistration {
+    // The generations DTrace assigned to the registered probes, one for each object file
+    // containing probes.
+    generations: Vec<i32>,
+}
+
+impl Drop for ProbeRegistration {
+    fn drop(&mut self) {
+        for generation in self.generations.drain(..) {
+            // There's no way to report an error from `drop`.
+            let _ = crate::internal::deregister_probes(generation);
+        }
+    }
+}
+
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// Return the next sequence number passed to probes generated with the `with_sequence` option.
+///
+/// This is called by those probes each time they fire, and should not be called directly.
+#[doc(hidden)]
+pub fn next_sequence() -> u64 {
+    SEQUENCE.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Return the ID of the current thread, passed to probes generated with the `with_tid` option.
+///
+/// This is the ID used by the OS, and so by DTrace and other tracers, e.g., `gettid()` on Linux.
+/// It is called by those probes each time they fire, and should not be called directly.
+#[doc(hidden)]
+pub fn thread_id() -> u64 {
+    #[cfg(target_os = "linux")]
+    let id = unsafe { libc::gettid() } as u64;
+    #[cfg(not(target_os = "linux"))]
+    let id = thread_id::get() as u64;
+    id
+}
+
+static PROBES_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Set whether any probe in the process may fire.
+///
+/// While disabled, every probe behaves as if it were not enabled, regardless of whether DTrace
+/// has enabled it: its arguments are not evaluated, and it does not fire. This is intended as a
+/// switch to quickly mitigate a probe misbehaving in production. Probes are enabled by default.
+pub fn set_probes_enabled(enabled: bool) {
+    PROBES_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Return whether probes may fire, as set by [`set_probes_enabled`].
+///
+/// This is checked by each probe before it checks whether DTrace has enabled it.
+#[inline]
+pub fn probes_globally_enabled() -> bool {
+    PROBES_ENABLED.load(Ordering::Relaxed)
+}
+
+static REGISTER_PROBES: Once = Once::new();
+
+/// Register an application's probes with DTrace, if this has not already been done.
+///
+/// This is called by probes generated with the `auto_register` option, each time they are reached.
+/// Registration is attempted only once, and any error is ignored, since there is no way to report
+/// it from a probe site. Note that this function may block while another thread is registering
+/// probes, and so is not async-signal-safe.
+#[doc(hidden)]
+pub fn register_probes_once() {
+    REGISTER_PROBES.call_once(|| {
+        let _ = register_probes();
+    });
+}
+
+/// Register the probes whose records are contained in the given linker section data.
+///
+/// This is used by the `register_provider` function generated for providers configured with a
+/// custom section name, and should not be called directly.
+#[doc(hidden)]
+pub fn register_section(data: &[u8]) -> Result<(), Error> {
+    crate::internal::register_section(data)
+}
+
 /// Errors related to building DTrace probes into Rust code
 #[derive(Error, Debug)]
 pub enum Error {
@@ -66,15 +227,118 @@ pub enum Error {
     /// Error related to calling out to DTrace itself
     #[error("Failed to call DTrace subprocess")]
     DTraceError,
+    /// The header file generated by DTrace contained an unexpected line
+    #[error("Failed to parse DTrace-generated header at line: {line}")]
+    HeaderParse { line: String },
+    /// DTrace ran, but failed to process the provider definition
+    #[error("DTrace failed to process the provider definition: {0}")]
+    DTraceBuildError(String),
+    /// Two probes would generate macros at the same path
+    #[error("Probes \"{first}\" and \"{second}\" would both generate the macro `{path}`")]
+    DuplicateMacro {
+        path: String,
+        first: String,
+        second: String,
+    },
+    /// Providers could not be merged, because their names or the signatures of their probes differ
+    #[error("Cannot merge providers: {0}")]
+    MergeConflict(String),
     /// Error converting input to JSON
     #[error(transparent)]
     Json(#[from] serde_json::Error),
+    /// The DTrace helper device refused to register probes from a non-global zone
+    #[error(
+        "Registering probes with DTrace is not permitted in the non-global zone \"{zone}\". \
+        The zone's configuration must make DTrace available to it, e.g., by adding the \
+        `dtrace_proc` and `dtrace_user` privileges to its `limitpriv` property."
+    )]
+    DTraceZoneUnsupported { zone: String },
+    /// The code generated for a provider is invalid
+    #[error("The generated code is invalid: {0}")]
+    GeneratedCode(String),
+    /// The probe records include some written by a newer, incompatible version of this crate
+    #[error(
+        "The probes were defined with incompatible versions of usdt: found probe records of \
+        version {version}, but this version of usdt reads records up to version {supported}. \
+        Probes must be registered by the newest version of usdt in the program."
+    )]
+    MixedRecordVersions { version: u8, supported: u8 },
 }
 
 #[derive(Default, Debug, Deserialize)]
 pub struct CompileProvidersConfig {
     pub probe_path: Option<String>,
     pub probe_name: Option<String>,
+    /// If true, probes fire unconditionally, skipping the check for whether they are enabled. On
+    /// macOS, this also omits the is-enabled symbol for each probe. This may also be set with the
+    /// name `skip_is_enabled`.
+    #[serde(default, alias = "skip_is_enabled")]
+    pub always_fire: bool,
+    /// The name of the linker section into which probe records are emitted, if not the default.
+    pub section_name: Option<String>,
+    /// If true, check whether probes are enabled using a per-probe semaphore, which tracers
+    /// increment when they attach. This is only supported by the `stapsdt` backend.
+    #[serde(default)]
+    pub semaphore: bool,
+    /// If set, also emit a `tracing` event at this level (e.g., `"info"`) whenever a probe fires.
+    /// This requires the `tracing` feature of the `usdt` crate.
+    pub mirror_tracing: Option<String>,
+    /// If true, probes register all probes with DTrace the first time any of them is reached,
+    /// rather than requiring an explicit call to `register_probes`.
+    #[serde(default)]
+    pub auto_register: bool,
+    /// Documentation for each generated provider module. This may include the token
+    /// `{provider}`, which is substituted with the name of the provider.
+    pub module_docs: Option<String>,
+    /// If true, the generated code refers only to `core`, so that it may be used in a `#![no_std]`
+    /// crate. Probes with string or serializable arguments, which require allocation, are rejected.
+    #[serde(default)]
+    pub no_std: bool,
+    /// The architecture for which probes are generated, as in `CARGO_CFG_TARGET_ARCH`. This
+    /// determines the registers in which probe arguments are passed. If unset, the architecture
+    /// for which this crate was compiled is used, which is wrong when cross-compiling.
+    pub target_arch: Option<String>,
+    /// If true, each probe takes an additional first argument, a `u64` sequence number which is
+    /// incremented each time any probe fires. This orders probes fired from different threads.
+    #[serde(default)]
+    pub with_sequence: bool,
+    /// If true, each probe takes an additional leading argument, a `u64` ID of the thread which
+    /// fired it, as returned by the OS. This follows the sequence number, if there is one.
+    #[serde(default)]
+    pub with_tid: bool,
+    /// The path to a header previously generated by `dtrace -h` for the provider definition. If
+    /// set, the macOS implementation reads probe symbols from this header, rather than running
+    /// `dtrace`, which allows building on hosts without DTrace. A relative path is relative to
+    /// the directory of the package being built.
+    pub dtrace_header: Option<String>,
+    /// If true, the macOS implementation computes the probe symbols directly from the provider
+    /// definition, rather than running `dtrace -h`, for providers which allow this. Others still
+    /// run `dtrace`.
+    #[serde(default)]
+    pub compute_symbols: bool,
+    /// If true, each probe macro evaluates to a `bool`, which is true if the probe fired, rather
+    /// than to `()`.
+    #[serde(default)]
+    pub return_status: bool,
+    /// If true, probe arguments whose types would be passed to the probe as JSON, though they
+    /// look like primitive or zero-sized types, such as `u128` or `()`, are a compile error.
+    #[serde(default)]
+    pub strict: bool,
+    /// The maximum length in bytes of each string argument. Longer strings are truncated, at a
+    /// UTF-8 character boundary, before being passed to the probe. If unset, this is
+    /// [`DEFAULT_MAX_STRING_LEN`].
+    pub max_string_len: Option<usize>,
+    /// If true, the provider's modules and probe macros are public, so that they may be
+    /// re-exported for use by other crates, e.g., with `pub use crate::my_provider;`.
+    #[serde(default)]
+    pub export: bool,
+    /// Registers to which the arguments of particular probes are pinned, overriding the default
+    /// ABI sequence. Each entry maps a probe name to the registers of its leading arguments, in
+    /// order, counting any sequence number or thread ID argument. Other arguments are passed in
+    /// their default registers. Tracers still read probe arguments from the default registers, so
+    /// this is only useful to match existing scripts which read specific registers directly.
+    #[serde(default)]
+    pub register_pins: BTreeMap<String, Vec<String>>,
 }
 
 impl CompileProvidersConfig {
@@ -111,6 +375,41 @@ impl CompileProvidersConfig {
             .collect()
     }
 
+    /// Return the formatted name of the linker section for a provider's probe records, if any.
+    pub fn format_section(&self, provider_name: &str) -> Option<String> {
+        self.section_name
+            .as_ref()
+            .map(|fmt| fmt.replace("{provider}", provider_name))
+    }
+
+    /// Return the formatted documentation for a provider's module, if any.
+    pub fn format_module_docs(&self, provider_name: &str) -> Option<String> {
+        self.module_docs
+            .as_ref()
+            .map(|fmt| fmt.replace("{provider}", provider_name))
+    }
+
+    /// Return the maximum length in bytes of each string argument.
+    pub fn max_string_len(&self) -> usize {
+        self.max_string_len.unwrap_or(DEFAULT_MAX_STRING_LEN)
+    }
+
+    /// Return the architecture for which probes are generated.
+    pub fn target_arch(&self) -> &str {
+        self.target_arch
+            .as_deref()
+            .unwrap_or(std::env::consts::ARCH)
+    }
+
+    /// Return the visibility of the generated modules, and of the items in them.
+    pub fn visibility(&self) -> proc_macro2::TokenStream {
+        if self.export {
+            quote::quote! { pub }
+        } else {
+            quote::quote! { pub(crate) }
+        }
+    }
+
     pub fn provider_module(&self, provider_name: &str) -> proc_macro2::TokenStream {
         let mods = self.provider_modules(provider_name).into_iter().rev();
         quote::quote! { #(#mods)::* }
@@ -127,22 +426,74 @@ impl CompileProvidersConfig {
 }
 
 /// Generate a possibly-nested list of modules, containing the given probe macros.
+///
+/// The innermost module also contains the constants `PROBES`, describing each of the provider's
+/// probes, and `PROVIDER`, describing the provider itself. These are omitted if the probes are not
+/// placed in any module, since multiple providers would then define the same constants. The
+/// innermost module is documented with the configured module docs, if any.
 fn wrap_probes_in_modules(
     config: &CompileProvidersConfig,
     provider: &Provider,
     macros: proc_macro2::TokenStream,
 ) -> proc_macro2::TokenStream {
-    config
-        .provider_modules(&provider.name)
-        .into_iter()
-        .rev()
-        .fold(macros, |inner, module| {
+    let modules = config.provider_modules(&provider.name);
+    let (docs, probes) = if modules.is_empty() {
+        (quote::quote! {}, quote::quote! {})
+    } else {
+        let docs = doc_lines(config.format_module_docs(&provider.name).as_deref());
+        let name = &provider.name;
+        let infos = provider.probes.iter().map(|probe| {
+            let name = &probe.name;
+            let args = probe.types.iter().map(DataType::to_c_type);
             quote::quote! {
-                pub(crate) mod #module {
-                    #inner
+                ::usdt::ProbeInfo {
+                    name: #name,
+                    args: &[#(#args),*],
                 }
             }
+        });
+        (
+            quote::quote! { #(#![doc = #docs])* },
+            quote::quote! {
+                /// The probes defined in this provider.
+                #[allow(dead_code)]
+                pub const PROBES: &[::usdt::ProbeInfo] = &[#(#infos),*];
+                /// A description of this provider.
+                #[allow(dead_code)]
+                pub const PROVIDER: ::usdt::ProviderInfo = ::usdt::ProviderInfo {
+                    name: #name,
+                    probes: PROBES,
+                };
+            },
+        )
+    };
+    let inner = quote::quote! {
+        #docs
+        #probes
+        #macros
+    };
+    let vis = config.visibility();
+    modules.into_iter().rev().fold(inner, |inner, module| {
+        quote::quote! {
+            #vis mod #module {
+                #inner
+            }
+        }
+    })
+}
+
+// Split documentation into the lines of `#[doc]` attributes, as would be generated by `///`.
+pub(crate) fn doc_lines(docs: Option<&str>) -> Vec<String> {
+    docs.into_iter()
+        .flat_map(str::lines)
+        .map(|line| {
+            if line.is_empty() {
+                String::new()
+            } else {
+                format!(" {}", line)
+            }
         })
+        .collect()
 }
 
 // Compile DTrace provider source code into Rust.
@@ -156,17 +507,100 @@ pub fn compile_provider_source(
     source: &str,
     config: &CompileProvidersConfig,
 ) -> Result<proc_macro2::TokenStream, Error> {
-    crate::internal::compile_provider_source(source, config)
+    let providers = compile_providers_source(source, config)?
+        .into_iter()
+        .map(|(_, tokens)| tokens);
+    Ok(quote::quote! { #(#providers)* })
+}
+
+// Compile DTrace provider source code into Rust, separately for each provider.
+//
+// This is the same as `compile_provider_source`, but returns the name and generated code of each
+// provider in the order they are defined, for example so that they may be written to separate
+// files.
+//
+// An error is returned if two probes would generate the same macro, which may happen when the
+// configured path or name of the macros omits the provider name.
+pub fn compile_providers_source(
+    source: &str,
+    config: &CompileProvidersConfig,
+) -> Result<Vec<(String, proc_macro2::TokenStream)>, Error> {
+    check_macro_paths(&parse_providers(source)?, config)?;
+    crate::internal::compile_providers_source(source, config)
+}
+
+// Check that each probe generates a macro at a distinct path.
+fn check_macro_paths(providers: &[Provider], config: &CompileProvidersConfig) -> Result<(), Error> {
+    let mut paths = BTreeMap::new();
+    for provider in providers.iter() {
+        for probe in provider.probes.iter() {
+            let path = config.macro_path(&provider.name, &probe.name).to_string();
+            let name = format!("{}:::{}", provider.name, probe.name);
+            if let Some(first) = paths.insert(path.clone(), name.clone()) {
+                return Err(Error::DuplicateMacro {
+                    path: path.replace(' ', ""),
+                    first,
+                    second: name,
+                });
+            }
+        }
+    }
+    Ok(())
 }
 
 // Compile a DTrace provider from its representation in the USDT crate.
+//
+// An error is returned if the provider cannot be compiled, for example because the DTrace command
+// needed to generate the probes fails.
 pub fn compile_provider(
     provider: &Provider,
     config: &CompileProvidersConfig,
-) -> proc_macro2::TokenStream {
+) -> Result<proc_macro2::TokenStream, Error> {
+    check_macro_paths(std::slice::from_ref(provider), config)?;
     crate::internal::compile_provider_from_definition(provider, config)
 }
 
+/// Check that DTrace provider source compiles into valid Rust code.
+///
+/// This runs the same pipeline as the `dtrace_provider!` macro and the `Builder`, with the default
+/// configuration, and checks that the generated code parses as a Rust file and does not contain
+/// any `compile_error!` item, such as one reporting a probe argument that cannot be passed to
+/// DTrace. It is intended for unit-testing provider files, e.g.,
+/// `check_provider_source(include_str!("provider.d")).unwrap()`.
+pub fn check_provider_source(source: &str) -> Result<(), Error> {
+    check_generated_code(compile_provider_source(
+        source,
+        &CompileProvidersConfig::default(),
+    )?)
+}
+
+/// Check that the code generated for DTrace providers, e.g., by `compile_provider_source`, is
+/// valid, as for [`check_provider_source`].
+pub fn check_generated_code(tokens: proc_macro2::TokenStream) -> Result<(), Error> {
+    let file: syn::File = syn::parse2(tokens).map_err(|e| Error::GeneratedCode(e.to_string()))?;
+    match find_compile_error(&file.items) {
+        Some(message) => Err(Error::GeneratedCode(message)),
+        None => Ok(()),
+    }
+}
+
+// Return the message of the first `compile_error!` item, searching nested modules.
+fn find_compile_error(items: &[syn::Item]) -> Option<String> {
+    items.iter().find_map(|item| match item {
+        syn::Item::Macro(item) if item.mac.path.is_ident("compile_error") => Some(
+            item.mac
+                .parse_body::<syn::LitStr>()
+                .map(|message| message.value())
+                .unwrap_or_else(|_| item.mac.tokens.to_string()),
+        ),
+        syn::Item::Mod(module) => module
+            .content
+            .as_ref()
+            .and_then(|(_, items)| find_compile_error(items)),
+        _ => None,
+    })
+}
+
 /// A data type supported by the `usdt` crate.
 #[derive(Debug, Clone, PartialEq)]
 pub enum DataType {
@@ -216,23 +650,114 @@ impl From<&syn::Type> for DataType {
     }
 }
 
+/// A description of a probe, available at runtime in the constant `PROBES` generated in each
+/// provider's module.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProbeInfo {
+    /// The name of the probe.
+    pub name: &'static str,
+    /// The D type of each of the probe's arguments, e.g., `"uint8_t"` or `"char*"`.
+    pub args: &'static [&'static str],
+}
+
+/// A description of a provider, available at runtime in the constant `PROVIDER` generated in each
+/// provider's module.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProviderInfo {
+    /// The name of the provider.
+    pub name: &'static str,
+    /// The provider's probes.
+    pub probes: &'static [ProbeInfo],
+}
+
+impl ProviderInfo {
+    /// Return the probe with the given name, if the provider has one.
+    pub fn probe(&self, name: &str) -> Option<&'static ProbeInfo> {
+        self.probes.iter().find(|probe| probe.name == name)
+    }
+}
+
+/// Look up the probe named `probe` in the provider named `provider`, among the given `providers`.
+///
+/// This only searches the list it's given, not the probes compiled into the program. Probes are
+/// compiled into each crate separately, so there is no list of every provider in a program.
+/// Instead, a program which decides which probes to fire at runtime can list the providers it
+/// knows of, via the `PROVIDER` constant generated in each provider's module:
+///
+/// ```ignore
+/// const PROVIDERS: &[usdt::ProviderInfo] = &[my_provider::PROVIDER, other_provider::PROVIDER];
+///
+/// assert!(usdt::find_probe(PROVIDERS, "my_provider", "start_work").is_some());
+/// ```
+pub fn find_probe(
+    providers: &[ProviderInfo],
+    provider: &str,
+    probe: &str,
+) -> Option<&'static ProbeInfo> {
+    providers
+        .iter()
+        .filter(|info| info.name == provider)
+        .find_map(|info| info.probe(probe))
+}
+
 /// A single DTrace probe function
 #[derive(Debug, Clone)]
 pub struct Probe {
     pub name: String,
     pub types: Vec<DataType>,
+    /// The name of each argument, in the same order as `types`. Arguments without a name, or
+    /// beyond the end of this list, are unnamed.
+    pub arg_names: Vec<Option<String>>,
+    /// Documentation for the probe, emitted on the generated probe macro.
+    pub docs: Option<String>,
 }
 
 impl From<dtrace_parser::Probe> for Probe {
     fn from(p: dtrace_parser::Probe) -> Self {
+        let docs = probe_docs(&p);
         Self {
             name: p.name,
             types: p.types.into_iter().map(DataType::from).collect(),
+            arg_names: p.arguments.into_iter().map(|arg| arg.name).collect(),
+            docs,
         }
     }
 }
 
+// Return the documentation for a probe, followed by a list of its arguments if any of them are
+// documented. Arguments without a name are named as in D scripts, e.g., `arg0`.
+fn probe_docs(probe: &dtrace_parser::Probe) -> Option<String> {
+    if probe.arguments.iter().all(|arg| arg.docs.is_none()) {
+        return probe.docs.clone();
+    }
+    let arguments = probe
+        .types
+        .iter()
+        .zip(probe.arguments.iter())
+        .enumerate()
+        .map(|(i, (typ, arg))| {
+            let name = arg.name.clone().unwrap_or_else(|| format!("arg{}", i));
+            let line = format!("- `{}` (`{}`)", name, typ.to_c_type());
+            match &arg.docs {
+                Some(docs) => format!("{}: {}", line, docs.replace('\n', "\n  ")),
+                None => line,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let section = format!("# Arguments\n\n{}", arguments);
+    Some(match &probe.docs {
+        Some(docs) => format!("{}\n\n{}", docs, section),
+        None => section,
+    })
+}
+
 impl Probe {
+    /// Return the name of the argument at `index`, if it has one.
+    pub fn arg_name(&self, index: usize) -> Option<&str> {
+        self.arg_names.get(index).and_then(Option::as_deref)
+    }
+
     /// Return the representation of this probe in D source code.
     pub fn to_d_source(&self) -> String {
         let types = self
@@ -254,6 +779,45 @@ pub struct Provider {
 }
 
 impl Provider {
+    /// Merge the probes and use statements of another provider of the same name into this one.
+    ///
+    /// Probes defined in both providers must have the same argument types, and are included once.
+    /// An error is returned if the providers' names differ, or if the same probe is defined with
+    /// different arguments.
+    pub fn merge(mut self, other: Provider) -> Result<Provider, Error> {
+        if self.name != other.name {
+            return Err(Error::MergeConflict(format!(
+                "provider \"{}\" cannot be merged with provider \"{}\"",
+                self.name, other.name
+            )));
+        }
+        for probe in other.probes {
+            match self.probes.iter().find(|p| p.name == probe.name) {
+                Some(existing) if existing.types != probe.types => {
+                    return Err(Error::MergeConflict(format!(
+                        "probe \"{}\" is defined as both `{}` and `{}`",
+                        probe.name,
+                        existing.to_d_source(),
+                        probe.to_d_source(),
+                    )));
+                }
+                Some(_) => {}
+                None => self.probes.push(probe),
+            }
+        }
+        for use_statement in other.use_statements {
+            let tokens = quote::quote! { #use_statement }.to_string();
+            if !self
+                .use_statements
+                .iter()
+                .any(|u| quote::quote! { #u }.to_string() == tokens)
+            {
+                self.use_statements.push(use_statement);
+            }
+        }
+        Ok(self)
+    }
+
     /// Return the representation of this provider in D source code.
     pub fn to_d_source(&self) -> String {
         let probes = self
@@ -268,6 +832,56 @@ impl Provider {
             probes = probes
         )
     }
+
+    /// Return a JSON manifest describing this provider and its probes.
+    ///
+    /// The manifest is an object with the provider's `name` and a list of `probes`. Each probe is
+    /// an object with its `name` and a list of `arguments`, given as their D (C) type names.
+    pub fn to_manifest(&self) -> Result<String, Error> {
+        #[derive(Serialize)]
+        struct ProbeManifest<'a> {
+            name: &'a str,
+            arguments: Vec<String>,
+        }
+
+        #[derive(Serialize)]
+        struct ProviderManifest<'a> {
+            name: &'a str,
+            probes: Vec<ProbeManifest<'a>>,
+        }
+
+        let manifest = ProviderManifest {
+            name: &self.name,
+            probes: self
+                .probes
+                .iter()
+                .map(|probe| ProbeManifest {
+                    name: &probe.name,
+                    arguments: probe.types.iter().map(DataType::to_c_type).collect(),
+                })
+                .collect(),
+        };
+        serde_json::to_string_pretty(&manifest).map_err(Error::from)
+    }
+}
+
+/// Read the DTrace provider source code in the file at `path`, resolving any `#include "file"`
+/// directives relative to the including file.
+///
+/// The path of every file read is returned along with the source, starting with `path` itself.
+pub fn read_provider_file(
+    path: &std::path::Path,
+) -> Result<(String, Vec<std::path::PathBuf>), Error> {
+    dtrace_parser::expand_includes(path).map_err(|e| match e {
+        dtrace_parser::DTraceError::IO(e) => Error::IO(e),
+        e => Error::ParseError(e),
+    })
+}
+
+/// Parse the providers defined in DTrace provider source code.
+pub fn parse_providers(source: &str) -> Result<Vec<Provider>, Error> {
+    let dfile = dtrace_parser::File::try_from(source)?;
+    Ok(dfile.providers().iter().map(Provider::from).collect())
 }
 
 impl From<dtrace_parser::Provider> for Provider {
@@ -297,6 +911,306 @@ where
     ::serde_json::to_string(x).map_err(Error::from)
 }
 
+/// A trait for types which may be passed to a probe with an integer argument.
+///
+/// The type parameter `T` is the integer type declared for the probe argument. This trait is
+/// implemented for `T` itself and for references to any implementor. Implementing it for your own
+/// types, such as newtypes or fieldless enums, allows passing them to probes directly:
+///
+/// ```ignore
+/// enum State { Idle, Busy }
+///
+/// impl usdt::IntoProbeArg<u8> for State {
+///     fn to_probe_arg(&self) -> u8 {
+///         match self {
+///             State::Idle => 0,
+///             State::Busy => 1,
+///         }
+///     }
+/// }
+///
+/// // Given a probe `transition(uint8_t)`
+/// my_provider::transition!(|| State::Busy);
+/// ```
+pub trait IntoProbeArg<T> {
+    /// Convert `self` into the value passed to the probe.
+    fn to_probe_arg(&self) -> T;
+}
+
+impl<T, U: IntoProbeArg<T> + ?Sized> IntoProbeArg<T> for &U {
+    fn to_probe_arg(&self) -> T {
+        (**self).to_probe_arg()
+    }
+}
+
+impl<T, U: IntoProbeArg<T> + ?Sized> IntoProbeArg<T> for &mut U {
+    fn to_probe_arg(&self) -> T {
+        (**self).to_probe_arg()
+    }
+}
+
+macro_rules! impl_into_probe_arg {
+    ($($ty:ty),*) => {
+        $(
+            impl IntoProbeArg<$ty> for $ty {
+                fn to_probe_arg(&self) -> $ty {
+                    *self
+                }
+            }
+        )*
+    };
+}
+
+impl_into_probe_arg!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+/// IPv4 addresses may be passed to probes with a `uint32_t` argument.
+///
+/// The address is converted with `u32::from`, so that the first octet is the most significant
+/// byte. For example, a D script may print the address with:
+///
+/// ```text
+/// printf("%d.%d.%d.%d", (arg0 >> 24) & 0xff, (arg0 >> 16) & 0xff, (arg0 >> 8) & 0xff, arg0 & 0xff);
+/// ```
+///
+/// IPv6 addresses do not fit in a register, and are passed by address as [`Ipv6Octets`].
+impl IntoProbeArg<u32> for std::net::Ipv4Addr {
+    fn to_probe_arg(&self) -> u32 {
+        u32::from(*self)
+    }
+}
+
+/// Characters may be passed to probes with a `uint32_t` argument, as their Unicode code point.
+///
+/// A `char` is always a Unicode scalar value, so the argument is never a surrogate or otherwise
+/// invalid code point. Probes declared in a `#[usdt::provider]` module may also take a `char`
+/// argument, which is declared as a `uint32_t` to DTrace.
+impl IntoProbeArg<u32> for char {
+    fn to_probe_arg(&self) -> u32 {
+        u32::from(*self)
+    }
+}
+
+/// An IPv6 address passed to a probe with a `uint64_t` argument, which receives the address of
+/// its 16 octets, in network byte order, e.g.,
+/// `my_provider::connect!(|| usdt::Ipv6Octets::from(addr))`.
+///
+/// The octets are valid while the probe fires, and a D script may copy them in and print them with:
+///
+/// ```text
+/// printf("%s", inet_ntoa6((in6_addr_t *)copyin(arg0, 16)));
+/// ```
+///
+/// This is a `#[repr(C)]` struct, declared in D by [`ProbeArgs::D_STRUCT`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct Ipv6Octets(pub [u8; 16]);
+
+impl From<std::net::Ipv6Addr> for Ipv6Octets {
+    fn from(addr: std::net::Ipv6Addr) -> Self {
+        Self(addr.octets())
+    }
+}
+
+impl ProbeArgs for Ipv6Octets {
+    const D_STRUCT: &'static str = "struct Ipv6Octets {\n\tuint8_t octets[16];\n};\n";
+}
+
+impl IntoProbeArg<u64> for Ipv6Octets {
+    fn to_probe_arg(&self) -> u64 {
+        self as *const Self as usize as u64
+    }
+}
+
+/// Durations may be passed to probes with a `uint64_t` argument, in nanoseconds.
+///
+/// Durations too long to represent in nanoseconds, more than about 584 years, are passed as
+/// `u64::MAX`. To pass a duration in a coarser unit, wrap it in [`Micros`] or [`Millis`].
+impl IntoProbeArg<u64> for std::time::Duration {
+    fn to_probe_arg(&self) -> u64 {
+        u64::try_from(self.as_nanos()).unwrap_or(u64::MAX)
+    }
+}
+
+/// A duration passed to a probe with a `uint64_t` argument in whole microseconds, e.g.,
+/// `my_provider::latency!(|| usdt::Micros(elapsed))`.
+///
+/// Any fraction of a microsecond is truncated. Durations too long to represent are passed as
+/// `u64::MAX`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Micros(pub std::time::Duration);
+
+impl IntoProbeArg<u64> for Micros {
+    fn to_probe_arg(&self) -> u64 {
+        u64::try_from(self.0.as_micros()).unwrap_or(u64::MAX)
+    }
+}
+
+/// A duration passed to a probe with a `uint64_t` argument in whole milliseconds, e.g.,
+/// `my_provider::latency!(|| usdt::Millis(elapsed))`.
+///
+/// Any fraction of a millisecond is truncated. Durations too long to represent are passed as
+/// `u64::MAX`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Millis(pub std::time::Duration);
+
+impl IntoProbeArg<u64> for Millis {
+    fn to_probe_arg(&self) -> u64 {
+        u64::try_from(self.0.as_millis()).unwrap_or(u64::MAX)
+    }
+}
+
+/// A trait for `#[repr(C)]` structs which may be passed to probes by address.
+///
+/// This is derived with `#[derive(usdt::ProbeArgs)]`, for structs whose fields are all fixed-width
+/// integers, or fixed-size arrays of them, such as `[u8; 8]`. Such a struct may be passed to a
+/// probe with a `uint64_t` argument, which receives the address of the struct, valid while the
+/// probe fires. [`ProbeArgs::D_STRUCT`] declares the same struct in D, with the same layout, so
+/// that a D script can copy it in and read its fields:
+///
+/// ```ignore
+/// #[derive(usdt::ProbeArgs)]
+/// #[repr(C)]
+/// struct Req { id: u64, len: u32 }
+///
+/// // Given a probe `request(uint64_t)`
+/// my_provider::request!(|| &req);
+/// ```
+///
+/// With `Req::D_STRUCT` prepended to the script, the fields are read with:
+///
+/// ```text
+/// my_provider*:::request { this->req = (struct Req *)copyin(arg0, sizeof (struct Req)); }
+/// ```
+pub trait ProbeArgs {
+    /// The declaration of this struct in D.
+    const D_STRUCT: &'static str;
+}
+
+/// Marks the implementation of [`ProbeStr`] for types which can be borrowed as a `str`.
+#[doc(hidden)]
+pub enum Utf8Str {}
+
+/// Marks the implementation of [`ProbeStr`] for types which can be borrowed as a `CStr`.
+#[doc(hidden)]
+pub enum NulTerminatedStr {}
+
+/// Marks the implementation of [`ProbeStr`] for [`DebugStr`] and [`DisplayStr`].
+#[doc(hidden)]
+pub enum FormattedStr {}
+
+/// A trait for types which may be passed to a probe with a string argument.
+///
+/// This is implemented for any type which can be borrowed as a `str`, such as `&str` or `String`,
+/// and for any which can be borrowed as a `CStr`, such as `&CStr` or `CString`. The type parameter
+/// distinguishes those two implementations, and is inferred.
+///
+/// Probes require a null-terminated string, so a `str` is copied into a new buffer with a
+/// terminating null byte. A `CStr` is already null-terminated, and a pointer to it is passed to the
+/// probe directly. Because the probe only reads the string while it fires, the pointer need only
+/// be valid for the duration of the probe macro, which borrowing guarantees.
+pub trait ProbeStr<Kind> {
+    /// Return the null-terminated bytes of the string passed to the probe.
+    fn to_probe_str(&self) -> Cow<'_, [u8]>;
+
+    /// Return the string as UTF-8, replacing any invalid sequences with `U+FFFD`.
+    fn to_probe_string(&self) -> Cow<'_, str>;
+}
+
+impl<T: AsRef<str> + ?Sized> ProbeStr<Utf8Str> for T {
+    fn to_probe_str(&self) -> Cow<'_, [u8]> {
+        Cow::Owned([self.as_ref().as_bytes(), &[0_u8]].concat())
+    }
+
+    fn to_probe_string(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self.as_ref())
+    }
+}
+
+impl<T: AsRef<CStr> + ?Sized> ProbeStr<NulTerminatedStr> for T {
+    fn to_probe_str(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.as_ref().to_bytes_with_nul())
+    }
+
+    fn to_probe_string(&self) -> Cow<'_, str> {
+        self.as_ref().to_string_lossy()
+    }
+}
+
+/// The default maximum length in bytes of string arguments, which matches the default value of
+/// DTrace's `strsize` option.
+pub const DEFAULT_MAX_STRING_LEN: usize = 256;
+
+/// Truncate the null-terminated bytes of a string argument to at most `max_len` bytes, not
+/// counting the null byte, without splitting a UTF-8 character.
+#[doc(hidden)]
+pub fn truncate_probe_str(bytes: Cow<'_, [u8]>, max_len: usize) -> Cow<'_, [u8]> {
+    if bytes.len().saturating_sub(1) <= max_len {
+        return bytes;
+    }
+    let end = utf8_boundary(&bytes, max_len);
+    Cow::Owned([&bytes[..end], &[0_u8]].concat())
+}
+
+/// Truncate a string argument to at most `max_len` bytes, without splitting a UTF-8 character.
+#[doc(hidden)]
+pub fn truncate_probe_string(string: Cow<'_, str>, max_len: usize) -> Cow<'_, str> {
+    if string.len() <= max_len {
+        return string;
+    }
+    let end = utf8_boundary(string.as_bytes(), max_len);
+    match string {
+        Cow::Borrowed(string) => Cow::Borrowed(&string[..end]),
+        Cow::Owned(mut string) => {
+            string.truncate(end);
+            Cow::Owned(string)
+        }
+    }
+}
+
+// Return the largest index no greater than `max_len` which is not within a UTF-8 character.
+// `bytes` must be longer than `max_len`.
+fn utf8_boundary(bytes: &[u8], max_len: usize) -> usize {
+    let mut end = max_len;
+    while end > 0 && bytes[end] & 0xc0 == 0x80 {
+        end -= 1;
+    }
+    end
+}
+
+/// Pass a value to a string argument, formatted with its `Debug` implementation.
+///
+/// The value is only formatted when the probe fires, i.e., when it is enabled, so this is an
+/// inexpensive way to trace values which have no natural string form. For example,
+/// `my_provider::my_probe!(|| DebugStr(&request))` passes `format!("{:?}", request)`.
+#[derive(Debug, Clone, Copy)]
+pub struct DebugStr<T>(pub T);
+
+/// Pass a value to a string argument, formatted with its `Display` implementation.
+///
+/// As with [`DebugStr`], the value is only formatted when the probe fires.
+#[derive(Debug, Clone, Copy)]
+pub struct DisplayStr<T>(pub T);
+
+impl<T: std::fmt::Debug> ProbeStr<FormattedStr> for DebugStr<T> {
+    fn to_probe_str(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(format!("{:?}\0", self.0).into_bytes())
+    }
+
+    fn to_probe_string(&self) -> Cow<'_, str> {
+        Cow::Owned(format!("{:?}", self.0))
+    }
+}
+
+impl<T: std::fmt::Display> ProbeStr<FormattedStr> for DisplayStr<T> {
+    fn to_probe_str(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(format!("{}\0", self.0).into_bytes())
+    }
+
+    fn to_probe_string(&self) -> Cow<'_, str> {
+        Cow::Owned(self.0.to_string())
+    }
+}
+
 thread_local! {
     static CURRENT_ID: RefCell<u32> = RefCell::new(0);
     static THREAD_ID: RefCell<usize> = RefCell::new(thread_id::get());
@@ -461,20 +1375,70 @@ impl Clone for UniqueId {
 mod test {
     use super::*;
 
+    #[test]
+    fn test_probe_argument_docs() {
+        let source = r#"
+            provider foo {
+                /** Fired for each request. */
+                probe bar(uint64_t request_id /** The ID of the request. */, uint8_t);
+                probe baz(uint64_t request_id);
+            };"#;
+        let provider = &parse_providers(source).unwrap()[0];
+        assert_eq!(
+            provider.probes[0].docs.as_deref(),
+            Some(
+                "Fired for each request.\n\n# Arguments\n\n\
+                - `request_id` (`uint64_t`): The ID of the request.\n\
+                - `arg1` (`uint8_t`)"
+            )
+        );
+        assert!(provider.probes[1].docs.is_none());
+
+        let output = common::build_probe_macro(
+            &CompileProvidersConfig::default(),
+            provider,
+            "bar",
+            &provider.probes[0].types,
+            proc_macro2::TokenStream::new(),
+            proc_macro2::TokenStream::new(),
+            quote::quote! { true },
+        )
+        .to_string();
+        assert!(output.contains("\" - `request_id` (`uint64_t`): The ID of the request.\""));
+    }
+
     #[test]
     fn test_probe_to_d_source() {
         let probe = Probe {
             name: String::from("my_probe"),
             types: vec![DataType::Native(dtrace_parser::DataType::U8)],
+            arg_names: vec![],
+            docs: None,
         };
         assert_eq!(probe.to_d_source(), "probe my_probe(uint8_t);");
     }
 
+    #[test]
+    fn test_probe_to_d_source_signedness() {
+        let probe = Probe {
+            name: String::from("my_probe"),
+            types: vec![
+                DataType::Native(dtrace_parser::DataType::I32),
+                DataType::Native(dtrace_parser::DataType::U32),
+            ],
+            arg_names: vec![],
+            docs: None,
+        };
+        assert_eq!(probe.to_d_source(), "probe my_probe(int32_t, uint32_t);");
+    }
+
     #[test]
     fn test_provider_to_d_source() {
         let probe = Probe {
             name: String::from("my_probe"),
             types: vec![DataType::Native(dtrace_parser::DataType::U8)],
+            arg_names: vec![],
+            docs: None,
         };
         let provider = Provider {
             name: String::from("my_provider"),
@@ -487,6 +1451,67 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_wrap_probes_in_modules_docs() {
+        let provider = &parse_providers(
+            "provider my_provider {\n\
+                /**\n\
+                 * Fired when work begins.\n\
+                 *\n\
+                 * The argument is the amount of work.\n\
+                 */\n\
+                probe begin(uint8_t);\n\
+            };",
+        )
+        .unwrap()[0];
+        assert_eq!(
+            provider.probes[0].docs.as_deref(),
+            Some("Fired when work begins.\n\nThe argument is the amount of work.")
+        );
+
+        let config = CompileProvidersConfig {
+            module_docs: Some(String::from("Probes for {provider}.\nSee the README.")),
+            ..Default::default()
+        };
+        let output = wrap_probes_in_modules(&config, provider, quote::quote! {}).to_string();
+        let expected = quote::quote! {
+            pub(crate) mod my_provider {
+                #![doc = " Probes for my_provider."]
+                #![doc = " See the README."]
+            }
+        }
+        .to_string();
+        assert!(output.starts_with(&expected[..expected.len() - 1]));
+    }
+
+    #[test]
+    fn test_config_skip_is_enabled_alias() {
+        let config: CompileProvidersConfig =
+            serde_json::from_str(r#"{ "skip_is_enabled": true }"#).unwrap();
+        assert!(config.always_fire);
+        let config: CompileProvidersConfig = serde_json::from_str("{}").unwrap();
+        assert!(!config.always_fire);
+    }
+
+    #[test]
+    fn test_provider_to_manifest() {
+        let provider =
+            &parse_providers("provider my_provider { probe begin(uint8_t, char*); probe end(); };")
+                .unwrap()[0];
+        let manifest: serde_json::Value =
+            serde_json::from_str(&provider.to_manifest().unwrap()).unwrap();
+        assert_eq!(
+            manifest,
+            serde_json::json!({
+                "name": "my_provider",
+                "probes": [
+                    { "name": "begin", "arguments": ["uint8_t", "char*"] },
+                    { "name": "end", "arguments": [] },
+                ]
+            })
+        );
+    }
+
     #[test]
     fn test_data_type() {
         let ty = DataType::Native(dtrace_parser::DataType::U8);
@@ -502,6 +1527,203 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_data_type_rust_and_ffi_types() {
+        use dtrace_parser::DataType as D;
+        let cases = [
+            (DataType::Native(D::U8), "u8", "::std::os::raw::c_uchar"),
+            (DataType::Native(D::U16), "u16", "::std::os::raw::c_ushort"),
+            (DataType::Native(D::U32), "u32", "::std::os::raw::c_uint"),
+            (
+                DataType::Native(D::U64),
+                "u64",
+                "::std::os::raw::c_ulonglong",
+            ),
+            (DataType::Native(D::I8), "i8", "::std::os::raw::c_schar"),
+            (DataType::Native(D::I16), "i16", "::std::os::raw::c_short"),
+            (DataType::Native(D::I32), "i32", "::std::os::raw::c_int"),
+            (
+                DataType::Native(D::I64),
+                "i64",
+                "::std::os::raw::c_longlong",
+            ),
+            (
+                DataType::Native(D::String),
+                "&str",
+                "*const ::std::os::raw::c_char",
+            ),
+            (
+                DataType::UniqueId,
+                "::usdt::UniqueId",
+                "::std::os::raw::c_ulonglong",
+            ),
+            (
+                DataType::Serializable(syn::parse_str("Vec<u8>").unwrap()),
+                "Vec<u8>",
+                "*const ::std::os::raw::c_char",
+            ),
+        ];
+        for (ty, rust, ffi) in cases.iter() {
+            assert_eq!(ty.to_rust_type(), syn::parse_str(rust).unwrap());
+            assert_eq!(ty.to_rust_ffi_type(), syn::parse_str(ffi).unwrap());
+        }
+
+        // Strings and serialized types are passed across the FFI boundary as raw pointers, but are
+        // borrowed or owned Rust types at the call site.
+        for ty in cases
+            .iter()
+            .map(|case| &case.0)
+            .filter(|ty| matches!(ty, DataType::Native(D::String) | DataType::Serializable(_)))
+        {
+            assert_ne!(ty.to_rust_type(), ty.to_rust_ffi_type());
+            assert!(matches!(ty.to_rust_ffi_type(), syn::Type::Ptr(_)));
+            assert!(!matches!(ty.to_rust_type(), syn::Type::Ptr(_)));
+        }
+    }
+
+    #[test]
+    fn test_thread_id() {
+        #[cfg(target_os = "linux")]
+        assert_eq!(thread_id(), unsafe { libc::gettid() } as u64);
+        let id = thread_id();
+        assert_eq!(thread_id(), id);
+        assert_ne!(std::thread::spawn(thread_id).join().unwrap(), id);
+    }
+
+    #[test]
+    fn test_find_probe() {
+        const PROVIDERS: &[ProviderInfo] = &[
+            ProviderInfo {
+                name: "foo",
+                probes: &[ProbeInfo {
+                    name: "bar",
+                    args: &["uint8_t"],
+                }],
+            },
+            ProviderInfo {
+                name: "empty",
+                probes: &[],
+            },
+        ];
+        assert_eq!(PROVIDERS[0].probe("bar").unwrap().args, &["uint8_t"]);
+        assert!(PROVIDERS[0].probe("baz").is_none());
+        assert!(find_probe(PROVIDERS, "foo", "bar").is_some());
+        assert!(find_probe(PROVIDERS, "foo", "baz").is_none());
+        assert!(find_probe(PROVIDERS, "empty", "bar").is_none());
+        assert!(find_probe(PROVIDERS, "bar", "foo").is_none());
+        assert!(find_probe(&[], "foo", "bar").is_none());
+    }
+
+    #[test]
+    fn test_ipv4_addr_probe_arg() {
+        let addr = std::net::Ipv4Addr::new(192, 168, 1, 2);
+        assert_eq!(IntoProbeArg::<u32>::to_probe_arg(&addr), 0xc0a8_0102);
+        assert_eq!(IntoProbeArg::<u32>::to_probe_arg(&&addr), 0xc0a8_0102);
+    }
+
+    #[test]
+    fn test_ipv6_octets_probe_arg() {
+        let addr = std::net::Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        let octets = Ipv6Octets::from(addr);
+        let ptr = IntoProbeArg::<u64>::to_probe_arg(&octets) as usize as *const u8;
+        let received = unsafe { std::slice::from_raw_parts(ptr, 16) };
+        assert_eq!(received, &addr.octets()[..]);
+        assert_eq!(received[..2], [0x20, 0x01]);
+    }
+
+    #[test]
+    fn test_char_probe_arg() {
+        assert_eq!(IntoProbeArg::<u32>::to_probe_arg(&'a'), 0x61);
+        assert_eq!(IntoProbeArg::<u32>::to_probe_arg(&'\u{e9}'), 0xe9);
+        assert_eq!(IntoProbeArg::<u32>::to_probe_arg(&&'\u{1f600}'), 0x1f600);
+    }
+
+    #[test]
+    fn test_duration_probe_arg() {
+        let duration = std::time::Duration::new(3, 4_005_006);
+        assert_eq!(IntoProbeArg::<u64>::to_probe_arg(&duration), 3_004_005_006);
+        assert_eq!(
+            IntoProbeArg::<u64>::to_probe_arg(&Micros(duration)),
+            3_004_005
+        );
+        assert_eq!(IntoProbeArg::<u64>::to_probe_arg(&Millis(duration)), 3_004);
+
+        // Durations which overflow a `u64` in the chosen unit saturate.
+        let long = std::time::Duration::from_secs(u64::MAX);
+        assert_eq!(IntoProbeArg::<u64>::to_probe_arg(&long), u64::MAX);
+        assert_eq!(IntoProbeArg::<u64>::to_probe_arg(&Micros(long)), u64::MAX);
+        assert_eq!(IntoProbeArg::<u64>::to_probe_arg(&Millis(long)), u64::MAX);
+        let secs = std::time::Duration::from_secs(u64::MAX / 1000);
+        assert_eq!(
+            IntoProbeArg::<u64>::to_probe_arg(&Millis(secs)),
+            u64::MAX / 1000 * 1000
+        );
+    }
+
+    #[test]
+    fn test_probe_str() {
+        let cstr = CStr::from_bytes_with_nul(b"borrowed\0").unwrap();
+        let bytes = ProbeStr::to_probe_str(&cstr);
+        assert!(matches!(bytes, Cow::Borrowed(_)));
+        assert_eq!(bytes.as_ptr(), cstr.as_ptr() as *const u8);
+        assert_eq!(ProbeStr::to_probe_string(&cstr), "borrowed");
+
+        let owned = std::ffi::CString::new("owned").unwrap();
+        assert_eq!(
+            ProbeStr::to_probe_str(&owned).as_ptr(),
+            owned.as_ptr() as *const u8
+        );
+
+        let string = "copied";
+        let bytes = ProbeStr::to_probe_str(&string);
+        assert!(matches!(bytes, Cow::Owned(_)));
+        assert_eq!(&*bytes, b"copied\0");
+        assert_eq!(ProbeStr::to_probe_string(&string), "copied");
+    }
+
+    #[test]
+    fn test_truncate_probe_str() {
+        let short = Cow::Borrowed(&b"short\0"[..]);
+        assert!(matches!(
+            truncate_probe_str(short, 5),
+            Cow::Borrowed(b"short\0")
+        ));
+        assert_eq!(
+            &*truncate_probe_str(Cow::Borrowed(b"truncated\0"), 5),
+            b"trunc\0"
+        );
+
+        // "é" is two bytes, which must not be split.
+        let accented = "caf\u{e9}s".to_probe_str();
+        assert_eq!(
+            &*truncate_probe_str(accented.clone(), 5),
+            "caf\u{e9}\0".as_bytes()
+        );
+        assert_eq!(&*truncate_probe_str(accented, 4), b"caf\0");
+
+        assert_eq!(truncate_probe_string(Cow::Borrowed("caf\u{e9}s"), 4), "caf");
+        assert_eq!(
+            truncate_probe_string(Cow::Owned(String::from("caf\u{e9}s")), 5),
+            "caf\u{e9}"
+        );
+        assert_eq!(truncate_probe_string(Cow::Borrowed("short"), 5), "short");
+
+        let config = CompileProvidersConfig::default();
+        assert_eq!(config.max_string_len(), DEFAULT_MAX_STRING_LEN);
+    }
+
+    #[test]
+    fn test_formatted_probe_str() {
+        let value = Some((1, "two"));
+        let debug = DebugStr(&value);
+        assert_eq!(&*ProbeStr::to_probe_str(&debug), b"Some((1, \"two\"))\0");
+        assert_eq!(ProbeStr::to_probe_string(&debug), "Some((1, \"two\"))");
+
+        let display = DisplayStr(std::net::Ipv4Addr::LOCALHOST);
+        assert_eq!(&*ProbeStr::to_probe_str(&display), b"127.0.0.1\0");
+        assert_eq!(ProbeStr::to_probe_string(&display), "127.0.0.1");
+    }
+
     #[test]
     fn test_unique_id() {
         let id = UniqueId::new();
@@ -532,6 +1754,7 @@ mod test {
         let config = CompileProvidersConfig {
             probe_path: Some(String::from("a::{provider}::b")),
             probe_name: Some(String::from("probe_{probe}")),
+            ..Default::default()
         };
         assert_eq!(config.format_probe("prov", "prob"), "probe_prob");
         assert_eq!(config.format_path("prov"), "a::prov::b");
@@ -543,6 +1766,7 @@ mod test {
         let config = CompileProvidersConfig {
             probe_path: None,
             probe_name: None,
+            ..Default::default()
         };
         assert_eq!(
             config.macro_path("prov", "prob").to_string(),
@@ -552,10 +1776,158 @@ mod test {
         let config = CompileProvidersConfig {
             probe_path: Some(String::new()),
             probe_name: None,
+            ..Default::default()
         };
         assert_eq!(
             config.macro_path("prov", "prob").to_string(),
             quote::quote! { prob }.to_string()
         );
     }
+
+    #[test]
+    fn test_check_provider_source() {
+        assert!(check_provider_source("provider foo { probe bar(uint8_t, char*); };").is_ok());
+        assert!(matches!(
+            check_provider_source("provider foo { probe bar(); probe bar(); };"),
+            Err(Error::ParseError(_))
+        ));
+        assert!(matches!(
+            check_provider_source("provider foo { probe bar(uint8_t) };"),
+            Err(Error::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_find_compile_error() {
+        let file: syn::File = syn::parse_quote! {
+            mod foo {
+                macro_rules! bar {
+                    ($tree:tt) => { compile_error!("not an item"); };
+                }
+                mod inner {
+                    compile_error!("Probe \"bar\" is invalid");
+                }
+            }
+        };
+        assert_eq!(
+            find_compile_error(&file.items).as_deref(),
+            Some("Probe \"bar\" is invalid")
+        );
+        let file: syn::File = syn::parse_quote! {
+            mod foo {
+                macro_rules! bar {
+                    ($tree:tt) => { compile_error!("not an item"); };
+                }
+            }
+        };
+        assert!(find_compile_error(&file.items).is_none());
+    }
+
+    #[test]
+    fn test_compile_providers_source() {
+        let source = r#"
+            provider first { probe begin(uint8_t); };
+            provider second { probe end(); };
+        "#;
+        let config = CompileProvidersConfig::default();
+        let providers = compile_providers_source(source, &config).unwrap();
+        let names = providers
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["first", "second"]);
+
+        let (first, second) = (providers[0].1.to_string(), providers[1].1.to_string());
+        assert!(first.contains("mod first") && !first.contains("mod second"));
+        assert!(second.contains("mod second") && !second.contains("mod first"));
+        assert!(first.contains("macro_rules ! begin") && !first.contains("macro_rules ! end"));
+
+        let combined = compile_provider_source(source, &config).unwrap();
+        assert_eq!(combined.to_string(), format!("{} {}", first, second));
+    }
+
+    #[test]
+    fn test_compile_providers_source_omit_provider() {
+        let source = r#"
+            provider first { probe begin(uint8_t); };
+            provider second { probe end(); };
+        "#;
+        let config = CompileProvidersConfig {
+            probe_path: Some(String::new()),
+            ..Default::default()
+        };
+        let providers = compile_providers_source(source, &config).unwrap();
+        let first = providers[0].1.to_string();
+        assert!(first.contains("macro_rules ! begin"));
+        assert!(!first.contains("mod first"));
+
+        let source = r#"
+            provider first { probe begin(uint8_t); };
+            provider second { probe begin(); };
+        "#;
+        match compile_providers_source(source, &config) {
+            Err(Error::DuplicateMacro {
+                path,
+                first,
+                second,
+            }) => {
+                assert_eq!(path, "begin");
+                assert_eq!(first, "first:::begin");
+                assert_eq!(second, "second:::begin");
+            }
+            other => panic!("Expected a duplicate macro error, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_provider_merge() {
+        let probe = |name: &str, types: Vec<DataType>| Probe {
+            name: String::from(name),
+            types,
+            arg_names: vec![],
+            docs: None,
+        };
+        let u8_type = DataType::Native(dtrace_parser::DataType::U8);
+        let first = Provider {
+            name: String::from("foo"),
+            probes: vec![probe("begin", vec![]), probe("bar", vec![u8_type.clone()])],
+            use_statements: vec![syn::parse_str("use crate::Arg;").unwrap()],
+        };
+        let second = Provider {
+            name: String::from("foo"),
+            probes: vec![probe("bar", vec![u8_type.clone()]), probe("baz", vec![])],
+            use_statements: vec![
+                syn::parse_str("use crate::Arg;").unwrap(),
+                syn::parse_str("use crate::Other;").unwrap(),
+            ],
+        };
+        let merged = first.clone().merge(second).unwrap();
+        let names = merged
+            .probes
+            .iter()
+            .map(|probe| probe.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["begin", "bar", "baz"]);
+        assert_eq!(merged.use_statements.len(), 2);
+
+        let conflicting = Provider {
+            name: String::from("foo"),
+            probes: vec![probe("bar", vec![DataType::UniqueId])],
+            use_statements: vec![],
+        };
+        assert!(matches!(
+            first.clone().merge(conflicting),
+            Err(Error::MergeConflict(_))
+        ));
+
+        let other_name = Provider {
+            name: String::from("other"),
+            probes: vec![],
+            use_statements: vec![],
+        };
+        assert!(matches!(
+            first.merge(other_name),
+            Err(Error::MergeConflict(_))
+        ));
+    }
 }
@@ -51,51 +51,93 @@
 //! 3. The probe needs to be a function type (because we call it), but the types
 //!    of the `stability` and `typedefs` symbols could be anything--we just need
 //!    a symbol name we can reference for the asm! macro that won't get garbled.
+//! 4. The symbol names are derived entirely from the provider definition: the
+//!    provider and probe names, a version, and an encoding of the stability
+//!    attributes or argument types. So the same provider always generates the
+//!    same symbols, and we must use them verbatim, since the linker decodes them
+//!    to construct the DOF.
 
 // Copyright 2021 Oxide Computer Company
 
+use crate::header::{compute_provider_info, extract_providers, ProviderInfo};
 use crate::{common, wrap_probes_in_modules, DataType, Provider};
 use proc_macro2::TokenStream;
 use quote::{format_ident, quote};
 use std::{
     collections::BTreeMap,
     convert::TryFrom,
+    fs,
     io::Write,
-    process::{Command, Stdio},
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
-/// Compile a DTrace provider definition into Rust tokens that implement its probes.
-pub fn compile_provider_source(
+/// Compile each provider in a DTrace provider definition into Rust tokens that implement its
+/// probes.
+pub fn compile_providers_source(
     source: &str,
     config: &crate::CompileProvidersConfig,
-) -> Result<TokenStream, crate::Error> {
+) -> Result<Vec<(String, TokenStream)>, crate::Error> {
     let dfile = dtrace_parser::File::try_from(source)?;
-    let header = build_header_from_provider(&source)?;
-    let provider_info = extract_providers(&header);
     let providers = dfile
         .providers()
+        .iter()
+        .map(|provider| common::add_implicit_arguments(&Provider::from(provider), config))
+        .collect::<Vec<_>>();
+    // The header must describe any implicit arguments added to the probes. Pragmas and translators
+    // are passed through unchanged.
+    let source = if !common::implicit_arguments(config).is_empty() {
+        dfile
+            .pragmas()
+            .iter()
+            .chain(dfile.translators().iter())
+            .cloned()
+            .chain(providers.iter().map(Provider::to_d_source))
+            .collect::<Vec<_>>()
+            .join("\n")
+    } else {
+        source.to_string()
+    };
+    // Pragmas may set the stability attributes of the providers, which are encoded in the symbols.
+    let default_stability = dfile
+        .pragmas()
+        .iter()
+        .all(|pragma| !pragma.contains("attributes"));
+    let provider_info = provider_symbols(&source, &providers, default_stability, config)?;
+    let providers = providers
         .into_iter()
         .map(|provider| {
-            let provider = Provider::from(provider);
-            compile_provider(&provider, &provider_info[&provider.name], config)
+            let info = provider_info
+                .get(&provider.name)
+                .cloned()
+                .unwrap_or_default();
+            let tokens = compile_provider(&provider, &info, config);
+            (provider.name, tokens)
         })
         .collect::<Vec<_>>();
-    Ok(quote! {
-        #(#providers)*
-    })
+    Ok(providers)
 }
 
 pub fn compile_provider_from_definition(
     provider: &Provider,
     config: &crate::CompileProvidersConfig,
-) -> TokenStream {
-    // Unwrap safety: The type signature confirms that `provider` is valid.
-    let header = build_header_from_provider(&provider.to_d_source()).unwrap();
-    let provider_info = extract_providers(&header);
-    let provider_tokens = compile_provider(provider, &provider_info[&provider.name], config);
-    quote! {
+) -> Result<TokenStream, crate::Error> {
+    let provider = &common::add_implicit_arguments(provider, config);
+    let provider_info = provider_symbols(
+        &provider.to_d_source(),
+        std::slice::from_ref(provider),
+        true,
+        config,
+    )?;
+    let info = provider_info
+        .get(&provider.name)
+        .cloned()
+        .unwrap_or_default();
+    let provider_tokens = compile_provider(provider, &info, config);
+    Ok(quote! {
         #provider_tokens
-    }
+    })
 }
 
 fn compile_provider(
@@ -116,19 +158,44 @@ fn compile_provider(
     }
     let stability = &provider_info.stability;
     let typedefs = &provider_info.typedefs;
-    let tokens = quote! {
-        extern "C" {
-            // These are dummy symbols, which we declare so that we can name them inside the
-            // probe macro via a valid Rust path, e.g., `$crate::#mod_name::stability`.
-            // The macOS linker will actually define these symbols, which are required to
-            // generate valid DOF.
-            #[allow(unused)]
-            #[link_name = #stability]
-            pub(crate) fn stability();
-            #[allow(unused)]
-            #[link_name = #typedefs]
-            pub(crate) fn typedefs();
+    // The linker registers all probes itself, regardless of the section name.
+    let register_fn = common::build_register_provider_fn(config, provider, quote! { Ok(()) });
+
+    // DTrace may not emit these symbols at all for a provider with no probes, in which case there
+    // is nothing to refer to them either.
+    let vis = config.visibility();
+    let symbols = if stability.is_empty() || typedefs.is_empty() {
+        quote! {}
+    } else {
+        quote! {
+            extern "C" {
+                // These are dummy symbols, which we declare so that we can name them inside the
+                // probe macro via a valid Rust path, e.g., `$crate::#mod_name::stability`.
+                // The macOS linker will actually define these symbols, which are required to
+                // generate valid DOF.
+                #[doc(hidden)]
+                #[allow(unused)]
+                #[link_name = #stability]
+                #vis fn stability();
+                #[doc(hidden)]
+                #[allow(unused)]
+                #[link_name = #typedefs]
+                #vis fn typedefs();
+            }
         }
+    };
+    let enabled_fn = common::build_enabled_probes_fn(config, provider, |probe| {
+        if config.always_fire {
+            quote! { true }
+        } else {
+            let is_enabled_fn = format_ident!("{}_{}_enabled", &provider.name, probe.name);
+            quote! { unsafe { #is_enabled_fn() != 0 } }
+        }
+    });
+    let tokens = quote! {
+        #symbols
+        #register_fn
+        #enabled_fn
         #(#probe_impls)*
     };
     wrap_probes_in_modules(config, provider, tokens)
@@ -150,52 +217,83 @@ fn compile_probe(
         let ty = typ.to_rust_ffi_type();
         syn::parse2::<syn::FnArg>(quote! { _: #ty }).unwrap()
     });
-    let (unpacked_args, in_regs) = common::construct_probe_args(types);
+    let (unpacked_args, in_regs) = common::construct_probe_args(probe_name, types, config);
+    let tracing_event = common::build_tracing_event(config, &provider.name, probe_name, types);
 
     // Create identifiers for the stability and typedef symbols, used by Apple's linker.
     // Note that the Rust symbols these refer to are defined in the caller of this function.
     let stability_fn = format_ident!("stability");
     let typedef_fn = format_ident!("typedefs");
 
-    let pre_macro_block = quote! {
-        extern "C" {
+    // Always-fire probes don't need the is-enabled symbol at all.
+    let vis = config.visibility();
+    let is_enabled_decl = if config.always_fire {
+        quote! {}
+    } else {
+        quote! {
+            #[doc(hidden)]
             #[allow(unused)]
             #[link_name = #is_enabled]
-            pub(crate) fn #is_enabled_fn() -> i32;
+            #vis fn #is_enabled_fn() -> i32;
+        }
+    };
+    let pre_macro_block = quote! {
+        extern "C" {
+            #is_enabled_decl
+            #[doc(hidden)]
             #[allow(unused)]
             #[link_name = #probe]
-            pub(crate) fn #extern_probe_fn(#(#ffi_param_list),*);
+            #vis fn #extern_probe_fn(#(#ffi_param_list),*);
         }
     };
 
-    #[cfg(target_arch = "x86_64")]
-    let call_instruction = quote! { "call {extern_probe_fn}" };
-    #[cfg(target_arch = "aarch64")]
-    let call_instruction = quote! { "bl {extern_probe_fn}" };
-    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
-    compile_error!("USDT only supports x86_64 and AArch64 architectures");
+    let call_instruction = if config.target_arch() == "aarch64" {
+        quote! { "bl {extern_probe_fn}" }
+    } else {
+        quote! { "call {extern_probe_fn}" }
+    };
 
     let mod_name = if mod_name.is_empty() {
         quote! {}
     } else {
         quote! { #mod_name:: }
     };
-    let impl_block = quote! {
-        unsafe {
-            if $crate:: #mod_name #is_enabled_fn() != 0 {
-                #unpacked_args
-                asm!(
-                    ".reference {typedefs}",
-                    #call_instruction,
-                    ".reference {stability}",
-                    typedefs = sym $crate:: #mod_name #typedef_fn,
-                    extern_probe_fn = sym $crate:: #mod_name #extern_probe_fn,
-                    stability = sym $crate:: #mod_name #stability_fn,
-                    #in_regs
-                    options(nomem, nostack, preserves_flags)
-                );
+    let fire_probe = quote! {
+        #unpacked_args
+        #tracing_event
+        asm!(
+            ".reference {typedefs}",
+            #call_instruction,
+            ".reference {stability}",
+            typedefs = sym $crate:: #mod_name #typedef_fn,
+            extern_probe_fn = sym $crate:: #mod_name #extern_probe_fn,
+            stability = sym $crate:: #mod_name #stability_fn,
+            #in_regs
+            options(nomem, nostack, preserves_flags)
+        );
+    };
+    let (fired, skipped) = common::probe_status(config);
+    let (impl_block, is_enabled) = if config.always_fire {
+        let impl_block = quote! {
+            unsafe {
+                #fire_probe
+                #fired
             }
-        }
+        };
+        (impl_block, quote! { true })
+    } else {
+        let impl_block = quote! {
+            unsafe {
+                if $crate:: #mod_name #is_enabled_fn() != 0 {
+                    #fire_probe
+                    #fired
+                } else {
+                    #skipped
+                }
+            }
+        };
+        let is_enabled = quote! { unsafe { $crate:: #mod_name #is_enabled_fn() != 0 } };
+        (impl_block, is_enabled)
     };
 
     common::build_probe_macro(
@@ -205,105 +303,67 @@ fn compile_probe(
         types,
         pre_macro_block,
         impl_block,
+        is_enabled,
     )
 }
 
-#[derive(Debug, Default, Clone)]
-struct ProviderInfo {
-    pub stability: String,
-    pub typedefs: String,
-    pub is_enabled: BTreeMap<String, String>,
-    pub probes: BTreeMap<String, String>,
-}
-
-fn extract_providers(header: &str) -> BTreeMap<String, ProviderInfo> {
-    let mut providers = BTreeMap::new();
-    for line in header.lines() {
-        if let Some((provider_name, stability)) = is_stability_line(&line) {
-            let mut info = ProviderInfo::default();
-            info.stability = stability.to_string();
-            providers.insert(provider_name.to_string(), info);
-        }
-        if let Some((provider_name, typedefs)) = is_typedefs_line(&line) {
-            providers.get_mut(provider_name).unwrap().typedefs = typedefs.to_string();
-        }
-        if let Some((provider_name, probe_name, enabled)) = is_enabled_line(&line) {
-            providers
-                .get_mut(provider_name)
-                .unwrap()
-                .is_enabled
-                .insert(probe_name.to_string(), enabled.to_string());
-        }
-        if let Some((provider_name, probe_name, probe)) = is_probe_line(&line) {
-            providers
-                .get_mut(provider_name)
-                .unwrap()
-                .probes
-                .insert(probe_name.to_string(), probe.to_string());
+// Return the symbols for each provider defined in the source.
+//
+// If configured to, and the providers have the default stability attributes, the symbols are
+// computed directly from the provider definitions, without running `dtrace`. Otherwise, or if any
+// provider can't be handled that way, they are extracted from the header for the source.
+fn provider_symbols(
+    source: &str,
+    providers: &[Provider],
+    default_stability: bool,
+    config: &crate::CompileProvidersConfig,
+) -> Result<BTreeMap<String, ProviderInfo>, crate::Error> {
+    if config.compute_symbols && config.dtrace_header.is_none() && default_stability {
+        let computed = providers
+            .iter()
+            .map(|provider| {
+                compute_provider_info(provider).map(|info| (provider.name.clone(), info))
+            })
+            .collect::<Option<BTreeMap<_, _>>>();
+        if let Some(computed) = computed {
+            return Ok(computed);
         }
     }
-    providers
-}
-
-// Return the (provider_name, stability) from a line, if it looks like the appropriate #define'd
-// line from the autogenerated header file.
-fn is_stability_line(line: &str) -> Option<(&str, &str)> {
-    contains_needle(line, "___dtrace_stability$")
+    extract_providers(&provider_header(source, config)?)
 }
 
-// Return the (provider_name, typedefs) from a line, if it looks like the appropriate #define'd
-// line from the autogenerated header file.
-fn is_typedefs_line(line: &str) -> Option<(&str, &str)> {
-    contains_needle(line, "___dtrace_typedefs$")
-}
-
-fn contains_needle<'a>(line: &'a str, needle: &str) -> Option<(&'a str, &'a str)> {
-    if let Some(index) = line.find(needle) {
-        let rest = &line[index + needle.len()..];
-        let provider_end = rest.find("$").unwrap();
-        let provider_name = &rest[..provider_end];
-        // NOTE: The extra offset to the start index works as follows. The symbol name really needs
-        // to be `___dtrace_stability$...`. But that symbol name will have a "_" prefixed to it
-        // during compilation, so we remove the leading one here, knowing it will be added back.
-        let needle = &line[index + 1..line.len() - 1];
-        Some((provider_name, needle))
-    } else {
-        None
+// Return the header describing the probes in the provider source, either read from a previously
+// generated header, if one is configured, or by running `dtrace -h`.
+fn provider_header(
+    source: &str,
+    config: &crate::CompileProvidersConfig,
+) -> Result<String, crate::Error> {
+    match &config.dtrace_header {
+        Some(path) => {
+            let path = match std::env::var("CARGO_MANIFEST_DIR") {
+                Ok(dir) => Path::new(&dir).join(path),
+                Err(_) => Path::new(path).to_path_buf(),
+            };
+            Ok(fs::read_to_string(path)?)
+        }
+        None => build_header_from_provider(source),
     }
 }
 
-// Return the (provider, probe, enabled) from a line, if it looks like the appropriate extern
-// function declaration from the autogenerated header file.
-fn is_enabled_line(line: &str) -> Option<(&str, &str, &str)> {
-    contains_needle2(line, "extern int __dtrace_isenabled$")
-}
-
-// Return the (provider, probe, probe) from a line, if it looks like the appropriate extern
-// function declaration from the autogenerated header file.
-fn is_probe_line(line: &str) -> Option<(&str, &str, &str)> {
-    contains_needle2(line, "extern void __dtrace_probe$")
+fn build_header_from_provider(source: &str) -> Result<String, crate::Error> {
+    let use_stdio = Path::new("/dev/stdin").exists() && Path::new("/dev/stdout").exists();
+    build_header(source, use_stdio)
 }
 
-fn contains_needle2<'a>(line: &'a str, needle: &str) -> Option<(&'a str, &'a str, &'a str)> {
-    if let Some(index) = line.find(needle) {
-        let rest = &line[index + needle.len()..];
-        let provider_end = rest.find("$").unwrap();
-        let provider_name = &rest[..provider_end];
-
-        let rest = &rest[provider_end + 1..];
-        let probe_end = rest.find("$").unwrap();
-        let probe_name = &rest[..probe_end];
-
-        let end = line.rfind("(").unwrap();
-        let start = line.find(line.split(" ").nth(2).unwrap()).unwrap();
-        let needle = &line[start..end];
-        Some((provider_name, probe_name, needle))
-    } else {
-        None
+// Run `dtrace -h` on the provider source, returning the generated header.
+//
+// The source and header are piped through `/dev/stdin` and `/dev/stdout` if `use_stdio` is true.
+// Otherwise, e.g., in containers where those devices don't exist, they are passed through
+// temporary files.
+fn build_header(source: &str, use_stdio: bool) -> Result<String, crate::Error> {
+    if !use_stdio {
+        return build_header_with_temp_files(source);
     }
-}
-
-fn build_header_from_provider(source: &str) -> Result<String, crate::Error> {
     let mut child = Command::new("dtrace")
         .arg("-h")
         .arg("-s")
@@ -312,6 +372,7 @@ fn build_header_from_provider(source: &str) -> Result<String, crate::Error> {
         .arg("/dev/stdout")
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
         .spawn()?;
     {
         let stdin = child.stdin.as_mut().ok_or(crate::Error::DTraceError)?;
@@ -320,6 +381,73 @@ fn build_header_from_provider(source: &str) -> Result<String, crate::Error> {
             .map_err(|_| crate::Error::DTraceError)?;
     }
     let output = child.wait_with_output()?;
+    header_from_dtrace_output(output)
+}
+
+// Run `dtrace -h` on the provider source, passing the source and header through files in a new
+// temporary directory.
+//
+// The directory is created afresh, readable and writable only by the current user, so that nobody
+// else can replace the files with symlinks or with their own header while DTrace runs.
+fn build_header_with_temp_files(source: &str) -> Result<String, crate::Error> {
+    let dir = create_private_temp_dir()?;
+    let source_file = dir.join("provider.d");
+    let header_file = dir.join("provider.h");
+    let result = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&source_file)
+        .and_then(|mut file| file.write_all(source.as_bytes()))
+        .map_err(crate::Error::from)
+        .and_then(|_| {
+            let output = Command::new("dtrace")
+                .arg("-h")
+                .arg("-s")
+                .arg(&source_file)
+                .arg("-o")
+                .arg(&header_file)
+                .stderr(Stdio::piped())
+                .output()?;
+            header_from_dtrace_output(output)?;
+            Ok(fs::read_to_string(&header_file)?)
+        });
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+// Create a new directory, accessible only by the current user, in the system's temporary directory.
+//
+// Creating the directory fails if the path already exists, so a directory planted in advance is
+// skipped rather than used.
+fn create_private_temp_dir() -> Result<PathBuf, crate::Error> {
+    use std::os::unix::fs::DirBuilderExt;
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    loop {
+        let dir = std::env::temp_dir().join(format!(
+            "usdt-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        match fs::DirBuilder::new().mode(0o700).create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+// Extract the generated header from the output of `dtrace -h`, or return DTrace's own diagnostics
+// if it failed.
+fn header_from_dtrace_output(output: Output) -> Result<String, crate::Error> {
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = if stderr.trim().is_empty() {
+            format!("dtrace exited with {}", output.status)
+        } else {
+            stderr.trim().to_string()
+        };
+        return Err(crate::Error::DTraceBuildError(message));
+    }
     String::from_utf8(output.stdout).map_err(|_| crate::Error::DTraceError)
 }
 
@@ -329,51 +457,67 @@ pub fn register_probes() -> Result<(), crate::Error> {
     Ok(())
 }
 
+pub fn register_section(_: &[u8]) -> Result<(), crate::Error> {
+    // This is a NOP for the same reason as `register_probes`.
+    Ok(())
+}
+
+pub fn register_probes_scoped() -> Result<Vec<i32>, crate::Error> {
+    // Probes are registered by the code the linker generates, and cannot be removed.
+    register_probes().map(|_| Vec::new())
+}
+
+pub fn dof_section_size() -> Result<Option<usize>, crate::Error> {
+    Ok(None)
+}
+
+pub fn deregister_probes(_: i32) -> Result<(), crate::Error> {
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::Probe;
 
     #[test]
-    fn test_is_stability_line() {
-        let line = "this line is ok \"___dtrace_stability$foo$bar\"";
-        let result = is_stability_line(line);
-        assert!(result.is_some());
-        assert_eq!(result.unwrap().0, "foo");
-        assert_eq!(result.unwrap().1, "__dtrace_stability$foo$bar");
-        assert!(is_stability_line("bad").is_none());
-    }
-
-    #[test]
-    fn test_is_typedefs_line() {
-        let line = "this line is ok \"___dtrace_typedefs$foo$bar\"";
-        let result = is_typedefs_line(line);
-        assert!(result.is_some());
-        assert_eq!(result.unwrap().0, "foo");
-        assert_eq!(result.unwrap().1, "__dtrace_typedefs$foo$bar");
-        assert!(is_typedefs_line("bad").is_none());
-    }
+    fn test_header_from_dtrace_output() {
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::ExitStatus;
+
+        let output = Output {
+            status: ExitStatus::from_raw(0),
+            stdout: b"#define FOO_STABILITY".to_vec(),
+            stderr: vec![],
+        };
+        assert_eq!(
+            header_from_dtrace_output(output).unwrap(),
+            "#define FOO_STABILITY"
+        );
 
-    #[test]
-    fn test_is_enabled_line() {
-        let line = "extern int __dtrace_isenabled$foo$bar$xxx(void);";
-        let result = is_enabled_line(line);
-        assert!(result.is_some());
-        assert_eq!(result.unwrap().0, "foo");
-        assert_eq!(result.unwrap().1, "bar");
-        assert_eq!(result.unwrap().2, "__dtrace_isenabled$foo$bar$xxx");
-        assert!(is_enabled_line("bad").is_none());
-    }
+        // A failed invocation should report DTrace's error message, even if it emitted a partial
+        // header on stdout.
+        let message = "dtrace: failed to compile script /dev/stdin: line 2: \
+            syntax error near \"uint8_t\"";
+        let output = Output {
+            status: ExitStatus::from_raw(1 << 8),
+            stdout: b"#define FOO_STABILITY".to_vec(),
+            stderr: format!("{}\n", message).into_bytes(),
+        };
+        match header_from_dtrace_output(output) {
+            Err(crate::Error::DTraceBuildError(msg)) => assert_eq!(msg, message),
+            other => panic!("Expected a DTrace build error, found {:?}", other),
+        }
 
-    #[test]
-    fn test_is_probe_line() {
-        let line = "extern void __dtrace_probe$foo$bar$xxx(whatever);";
-        let result = is_probe_line(line);
-        assert!(result.is_some());
-        assert_eq!(result.unwrap().0, "foo");
-        assert_eq!(result.unwrap().1, "bar");
-        assert_eq!(result.unwrap().2, "__dtrace_probe$foo$bar$xxx");
-        assert!(is_enabled_line("bad").is_none());
+        let output = Output {
+            status: ExitStatus::from_raw(1 << 8),
+            stdout: vec![],
+            stderr: vec![],
+        };
+        assert!(matches!(
+            header_from_dtrace_output(output),
+            Err(crate::Error::DTraceBuildError(_))
+        ));
     }
 
     #[test]
@@ -389,6 +533,8 @@ mod tests {
             probes: vec![Probe {
                 name: probe_name.to_string(),
                 types: types.clone(),
+                arg_names: vec![],
+                docs: None,
             }],
             use_statements: vec![],
         };
@@ -439,4 +585,216 @@ mod tests {
             assert!(output.find(needle).is_some());
         }
     }
+
+    #[test]
+    fn test_build_header_without_stdio() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Routing the source and header through temporary files generates the same header as
+        // piping them through `/dev/stdin` and `/dev/stdout`. Skipped where DTrace isn't available.
+        let source = "provider foo { probe bar(uint8_t); };";
+        if let Ok(header) = build_header(source, true) {
+            assert_eq!(build_header(source, false).unwrap(), header);
+        }
+        assert!(build_header("provider foo { probe bar(", false).is_err());
+
+        // Each temporary directory is new, and private to the current user.
+        let first = create_private_temp_dir().unwrap();
+        let second = create_private_temp_dir().unwrap();
+        assert_ne!(first, second);
+        let mode = fs::metadata(&first).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        fs::remove_dir(first).unwrap();
+        fs::remove_dir(second).unwrap();
+
+        // The temporary directories are removed, whether or not DTrace succeeds.
+        let prefix = format!("usdt-{}-", std::process::id());
+        let leftover = fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .any(|entry| entry.file_name().to_string_lossy().starts_with(&prefix));
+        assert!(!leftover);
+    }
+
+    #[test]
+    fn test_extract_providers_deterministic() {
+        // The symbols depend only on the provider definition, not on the particular build.
+        let source = "provider foo { probe bar(uint8_t, char*); probe baz(); };";
+        let extract = || {
+            build_header_from_provider(source)
+                .ok()
+                .map(|header| extract_providers(&header).unwrap())
+        };
+        assert_eq!(extract(), extract());
+    }
+
+    #[test]
+    fn test_compute_symbols_matches_dtrace() {
+        // Skipped where DTrace isn't available.
+        let source = "provider foo { probe bar(uint8_t, char*, int64_t); probe baz(); };";
+        if let Ok(header) = build_header_from_provider(source) {
+            let dfile = dtrace_parser::File::try_from(source).unwrap();
+            let provider = Provider::from(&dfile.providers()[0]);
+            assert_eq!(
+                compute_provider_info(&provider),
+                extract_providers(&header).unwrap().remove("foo")
+            );
+        }
+    }
+
+    #[test]
+    fn test_compile_providers_source_compute_symbols() {
+        // Computing the symbols doesn't require DTrace.
+        let config = crate::CompileProvidersConfig {
+            compute_symbols: true,
+            ..Default::default()
+        };
+        let source = "provider foo { probe bar(uint8_t); };";
+        let output = compile_providers_source(source, &config).unwrap()[0]
+            .1
+            .to_string();
+        assert!(output.contains("\"__dtrace_probe$foo$bar$v1$75696e74385f74\""));
+        assert!(output.contains("\"__dtrace_isenabled$foo$bar$v1\""));
+        assert!(output.contains("\"__dtrace_stability$foo$v1$1_1_0_1_1_0_1_1_0_1_1_0_1_1_0\""));
+        assert!(output.contains("\"__dtrace_typedefs$foo$v2\""));
+
+        // The sequence number is included in the symbol.
+        let config = crate::CompileProvidersConfig {
+            with_sequence: true,
+            ..config
+        };
+        let output = compile_providers_source(source, &config).unwrap()[0]
+            .1
+            .to_string();
+        assert!(output.contains("\"__dtrace_probe$foo$bar$v1$75696e7436345f74$75696e74385f74\""));
+    }
+
+    #[test]
+    fn test_compile_providers_source_dtrace_header() {
+        let source = "provider foo { probe bar(uint8_t); };";
+        let header = [
+            "#define FOO_STABILITY \"___dtrace_stability$foo$v1$1_1_0\"",
+            "#define FOO_TYPEDEFS \"___dtrace_typedefs$foo$v2\"",
+            "extern int __dtrace_isenabled$foo$bar$v1(void);",
+            "extern void __dtrace_probe$foo$bar$v1$75696e74385f74(uint8_t);",
+        ]
+        .join("\n");
+        let path = std::env::temp_dir().join(format!("usdt-test-{}.h", std::process::id()));
+        let compile = |header: &str| {
+            fs::write(&path, header).unwrap();
+            let config = crate::CompileProvidersConfig {
+                dtrace_header: Some(path.to_string_lossy().into_owned()),
+                ..Default::default()
+            };
+            let output = compile_providers_source(source, &config).map(|providers| {
+                providers
+                    .into_iter()
+                    .map(|(_, tokens)| tokens.to_string())
+                    .collect::<Vec<_>>()
+            });
+            fs::remove_file(&path).unwrap();
+            output.unwrap()
+        };
+        let output = compile(&header);
+        assert!(output[0].contains("\"__dtrace_probe$foo$bar$v1$75696e74385f74\""));
+        assert!(output[0].contains("\"__dtrace_isenabled$foo$bar$v1\""));
+
+        // Given the header DTrace generates, the output is the same as when running DTrace.
+        if let Ok(header) = build_header_from_provider(source) {
+            let config = crate::CompileProvidersConfig::default();
+            let live = compile_providers_source(source, &config).unwrap();
+            assert_eq!(compile(&header), [live[0].1.to_string()]);
+        }
+    }
+
+    #[test]
+    fn test_compile_provider_from_definition_dtrace_failure() {
+        // DTrace rejects this probe name, which the parser would never produce.
+        let provider = Provider {
+            name: String::from("foo"),
+            probes: vec![Probe {
+                name: String::from("not a probe"),
+                types: vec![],
+                arg_names: vec![],
+                docs: None,
+            }],
+            use_statements: vec![],
+        };
+        let config = crate::CompileProvidersConfig::default();
+        assert!(compile_provider_from_definition(&provider, &config).is_err());
+    }
+
+    #[test]
+    fn test_compile_empty_provider() {
+        let provider = Provider {
+            name: String::from("foo"),
+            probes: vec![],
+            use_statements: vec![],
+        };
+        let config = crate::CompileProvidersConfig::default();
+
+        // DTrace emitted no symbols at all for the provider.
+        let output = compile_provider(&provider, &ProviderInfo::default(), &config).to_string();
+        let expected = quote! {
+            pub(crate) mod foo {
+                /// The probes defined in this provider.
+                #[allow(dead_code)]
+                pub const PROBES: &[::usdt::ProbeInfo] = &[];
+                /// A description of this provider.
+                #[allow(dead_code)]
+                pub const PROVIDER: ::usdt::ProviderInfo = ::usdt::ProviderInfo {
+                    name: "foo",
+                    probes: PROBES,
+                };
+            }
+        };
+        assert_eq!(output, expected.to_string());
+
+        // DTrace emitted only the provider-level symbols.
+        let info = extract_providers(
+            "#define FOO_STABILITY \"___dtrace_stability$foo$v1$1_1_0\"\n\
+             #define FOO_TYPEDEFS \"___dtrace_typedefs$foo$v2\"\n",
+        );
+        let output = compile_provider(&provider, &info.unwrap()["foo"], &config).to_string();
+        assert!(output.contains("fn stability ()"));
+        assert!(output.contains("fn typedefs ()"));
+        assert!(!output.contains("macro_rules"));
+    }
+
+    #[test]
+    fn test_compile_probe_always_fire() {
+        let provider_name = "foo";
+        let probe_name = "bar";
+        let is_enabled = "__dtrace_isenabled$foo$bar$xxx";
+        let probe = "__dtrace_probe$foo$bar$xxx";
+        let types = vec![];
+        let provider = Provider {
+            name: provider_name.to_string(),
+            probes: vec![Probe {
+                name: probe_name.to_string(),
+                types: types.clone(),
+                arg_names: vec![],
+                docs: None,
+            }],
+            use_statements: vec![],
+        };
+        let config = crate::CompileProvidersConfig {
+            always_fire: true,
+            ..Default::default()
+        };
+        let tokens = compile_probe(&provider, probe_name, &config, is_enabled, probe, &types);
+        let output = tokens.to_string();
+        assert!(output.find(is_enabled).is_none());
+        assert!(output.find("foo_bar_enabled").is_none());
+        let general = &output[output.rfind("($ args_lambda : expr").unwrap()..];
+        assert_eq!(general.matches(" if ").count(), 1);
+        assert!(general.contains("if :: usdt :: probes_globally_enabled ()"));
+        assert!(output.find("call {extern_probe_fn}").is_some());
+
+        // The linker still needs the references to the stability and typedef symbols.
+        assert!(output.contains("\".reference {typedefs}\""));
+        assert!(output.contains("\".reference {stability}\""));
+        assert!(output.contains("foo :: typedefs"));
+        assert!(output.contains("foo :: stability"));
+    }
 }
@@ -0,0 +1,215 @@
+//! A mock implementation of the USDT crate, for testing instrumented code.
+//!
+//! Used when the `mock-probes` feature is enabled. Rather than firing a DTrace probe, each probe
+//! macro evaluates its arguments and records them, along with the provider and probe names, in a
+//! thread-local list. Tests can then inspect which probes fired, and with what arguments, without
+//! a live DTrace consumer.
+
+// Copyright 2021 Oxide Computer Company
+
+use crate::{common, wrap_probes_in_modules, DataType, Probe, Provider};
+use proc_macro2::TokenStream;
+use quote::quote;
+use std::cell::RefCell;
+use std::convert::TryFrom;
+
+pub fn compile_providers_source(
+    source: &str,
+    config: &crate::CompileProvidersConfig,
+) -> Result<Vec<(String, TokenStream)>, crate::Error> {
+    let dfile = dtrace_parser::File::try_from(source)?;
+    let providers = dfile
+        .providers()
+        .iter()
+        .map(|provider| {
+            let provider = Provider::from(provider);
+            let tokens = compile_provider(&provider, config);
+            (provider.name, tokens)
+        })
+        .collect::<Vec<_>>();
+    Ok(providers)
+}
+
+pub fn compile_provider_from_definition(
+    provider: &Provider,
+    config: &crate::CompileProvidersConfig,
+) -> Result<TokenStream, crate::Error> {
+    Ok(compile_provider(provider, config))
+}
+
+fn compile_provider(provider: &Provider, config: &crate::CompileProvidersConfig) -> TokenStream {
+    let provider = &common::add_implicit_arguments(provider, config);
+    let probe_impls = provider
+        .probes
+        .iter()
+        .map(|probe| compile_probe(provider, probe, config))
+        .collect::<Vec<_>>();
+    let register_fn = common::build_register_provider_fn(config, provider, quote! { Ok(()) });
+    // Mocked probes are always enabled.
+    let enabled_fn = common::build_enabled_probes_fn(config, provider, |_| quote! { true });
+    wrap_probes_in_modules(
+        config,
+        provider,
+        quote! {
+            #register_fn
+            #enabled_fn
+            #(#probe_impls)*
+        },
+    )
+}
+
+fn compile_probe(
+    provider: &Provider,
+    probe: &Probe,
+    config: &crate::CompileProvidersConfig,
+) -> TokenStream {
+    let provider_name = &provider.name;
+    let probe_name = &probe.name;
+    let preamble = common::unpack_argument_lambda(&probe.types, /* clone = */ false);
+    let args = probe.types.iter().enumerate().map(|(i, typ)| {
+        let index = syn::Index::from(i);
+        format_argument(config, typ, quote! { args.#index })
+    });
+    // Mocked probes are always enabled, so they always fire.
+    let (fired, _) = common::probe_status(config);
+    let impl_block = quote! {
+        {
+            #preamble
+            ::usdt::mock::record_probe(#provider_name, #probe_name, vec![#(#args),*]);
+            #fired
+        }
+    };
+    common::build_probe_macro(
+        config,
+        provider,
+        &probe.name,
+        &probe.types,
+        TokenStream::new(),
+        impl_block,
+        quote! { true },
+    )
+}
+
+// Return code to format a probe argument as a string, as it would be seen by DTrace.
+fn format_argument(
+    config: &crate::CompileProvidersConfig,
+    typ: &DataType,
+    input: TokenStream,
+) -> TokenStream {
+    match typ {
+        DataType::Serializable(_) => quote! {
+            match ::usdt::to_json(&#input) {
+                Ok(json) => format!("{{\"ok\":{}}}", json),
+                Err(e) => format!("{{\"err\":\"{}\"}}", e.to_string()),
+            }
+        },
+        DataType::Native(dtrace_parser::DataType::String) => {
+            let max_len = config.max_string_len();
+            quote! {
+                ::std::string::String::from(::usdt::truncate_probe_string(
+                    ::usdt::ProbeStr::to_probe_string(&#input),
+                    #max_len,
+                ))
+            }
+        }
+        DataType::Native(_) => {
+            let ty = typ.to_rust_type();
+            quote! { <_ as ::usdt::IntoProbeArg<#ty>>::to_probe_arg(&#input).to_string() }
+        }
+        DataType::UniqueId => quote! { #input.as_u64().to_string() },
+    }
+}
+
+pub fn register_probes() -> Result<(), crate::Error> {
+    Ok(())
+}
+
+pub fn register_section(_: &[u8]) -> Result<(), crate::Error> {
+    Ok(())
+}
+
+pub fn register_probes_scoped() -> Result<Vec<i32>, crate::Error> {
+    register_probes().map(|_| Vec::new())
+}
+
+pub fn dof_section_size() -> Result<Option<usize>, crate::Error> {
+    Ok(None)
+}
+
+pub fn deregister_probes(_: i32) -> Result<(), crate::Error> {
+    Ok(())
+}
+
+/// A record of a single firing of a probe.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FiredProbe {
+    /// The name of the provider.
+    pub provider: String,
+    /// The name of the probe.
+    pub probe: String,
+    /// The arguments to the probe, in order, formatted as strings.
+    ///
+    /// Integers are formatted in decimal, and `UniqueId`s as their integer value. Serializable
+    /// types are formatted as the JSON seen by DTrace, e.g., `{"ok":...}`.
+    pub args: Vec<String>,
+}
+
+thread_local! {
+    static FIRED_PROBES: RefCell<Vec<FiredProbe>> = RefCell::new(Vec::new());
+}
+
+/// Record that a probe fired on the current thread.
+#[doc(hidden)]
+pub fn record_probe(provider: &str, probe: &str, args: Vec<String>) {
+    FIRED_PROBES.with(|fired| {
+        fired.borrow_mut().push(FiredProbe {
+            provider: provider.to_string(),
+            probe: probe.to_string(),
+            args,
+        })
+    });
+}
+
+/// Return the probes fired on the current thread since the last call, in the order they fired.
+pub fn take_fired_probes() -> Vec<FiredProbe> {
+    FIRED_PROBES.with(|fired| fired.replace(Vec::new()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_fired_probes() {
+        assert!(take_fired_probes().is_empty());
+        record_probe("provider", "first", vec![]);
+        record_probe("provider", "second", vec![String::from("1")]);
+        let fired = take_fired_probes();
+        assert_eq!(fired.len(), 2);
+        assert_eq!(fired[0].probe, "first");
+        assert_eq!(fired[1].probe, "second");
+        assert_eq!(fired[1].args, vec![String::from("1")]);
+        assert!(take_fired_probes().is_empty());
+    }
+
+    #[test]
+    fn test_compile_probe() {
+        let provider = Provider {
+            name: String::from("foo"),
+            probes: vec![Probe {
+                name: String::from("bar"),
+                types: vec![
+                    DataType::Native(dtrace_parser::DataType::U8),
+                    DataType::Native(dtrace_parser::DataType::String),
+                ],
+                arg_names: vec![],
+                docs: None,
+            }],
+            use_statements: vec![],
+        };
+        let output = compile_probe(&provider, &provider.probes[0], &Default::default());
+        let output = output.to_string();
+        assert!(output.contains(":: usdt :: mock :: record_probe (\"foo\" , \"bar\""));
+        assert!(!output.contains("asm !"));
+    }
+}
@@ -2,46 +2,97 @@
 
 // Copyright 2021 Oxide Computer Company
 
-use crate::record::{emit_probe_record, process_section};
+use crate::record::{emit_probe_record, peek_section, process_section, DEFAULT_SECTION};
 use crate::{common, wrap_probes_in_modules, Probe, Provider};
 use dof::{serialize_section, Section};
 use proc_macro2::TokenStream;
 use quote::quote;
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 
 /// Compile a DTrace provider definition into Rust tokens that implement its probes.
-pub fn compile_provider_source(
+pub fn compile_providers_source(
     source: &str,
     config: &crate::CompileProvidersConfig,
-) -> Result<TokenStream, crate::Error> {
+) -> Result<Vec<(String, TokenStream)>, crate::Error> {
     let dfile = dtrace_parser::File::try_from(source)?;
     let providers = dfile
         .providers()
         .iter()
         .map(|provider| {
             let provider = Provider::from(provider);
-            compile_provider(&provider, &config)
+            let tokens = compile_provider(&provider, &config);
+            (provider.name, tokens)
         })
         .collect::<Vec<_>>();
-    Ok(quote! {
-        #(#providers)*
-    })
+    Ok(providers)
 }
 
 pub fn compile_provider_from_definition(
     provider: &Provider,
     config: &crate::CompileProvidersConfig,
-) -> TokenStream {
-    compile_provider(provider, config)
+) -> Result<TokenStream, crate::Error> {
+    Ok(compile_provider(provider, config))
 }
 
 fn compile_provider(provider: &Provider, config: &crate::CompileProvidersConfig) -> TokenStream {
+    let provider = &common::add_implicit_arguments(provider, config);
     let probe_impls = provider
         .probes
         .iter()
         .map(|probe| compile_probe(provider, probe, config))
         .collect::<Vec<_>>();
-    wrap_probes_in_modules(config, provider, quote! { #(#probe_impls)* })
+    let register_fn = match config.format_section(&provider.name) {
+        Some(section) => {
+            let start = format!("__start_{}", section);
+            let stop = format!("__stop_{}", section);
+            common::build_register_provider_fn(
+                config,
+                provider,
+                quote! {
+                    extern "C" {
+                        #[link_name = #start]
+                        static probes_start: usize;
+                        #[link_name = #stop]
+                        static probes_stop: usize;
+                    }
+
+                    // See `extract_probe_records_from_section` for why this is required.
+                    #[cfg(any(target_os = "illumos", target_os = "freebsd"))]
+                    #[link_section = #section]
+                    #[used]
+                    static mut FORCE_LOAD: [u64; 0] = [];
+
+                    let data = unsafe {
+                        let start = (&probes_start as *const usize) as usize;
+                        let stop = (&probes_stop as *const usize) as usize;
+                        ::core::slice::from_raw_parts(start as *const u8, stop - start)
+                    };
+                    ::usdt::register_section(data)
+                },
+            )
+        }
+        None => quote! {},
+    };
+    let section = config
+        .format_section(&provider.name)
+        .unwrap_or_else(|| String::from(DEFAULT_SECTION));
+    let enabled_fn = common::build_enabled_probes_fn(config, provider, |probe| {
+        if config.always_fire {
+            quote! { true }
+        } else {
+            is_enabled_check(&section, &provider.name, &probe.name)
+        }
+    });
+    wrap_probes_in_modules(
+        config,
+        provider,
+        quote! {
+            #register_fn
+            #enabled_fn
+            #(#probe_impls)*
+        },
+    )
 }
 
 fn compile_probe(
@@ -49,34 +100,51 @@ fn compile_probe(
     probe: &Probe,
     config: &crate::CompileProvidersConfig,
 ) -> TokenStream {
-    let (unpacked_args, in_regs) = common::construct_probe_args(&probe.types);
-    let is_enabled_rec = emit_probe_record(&provider.name, &probe.name, None);
-    let probe_rec = emit_probe_record(&provider.name, &probe.name, Some(&probe.types));
+    let (unpacked_args, in_regs) = common::construct_probe_args(&probe.name, &probe.types, config);
+    let tracing_event =
+        common::build_tracing_event(config, &provider.name, &probe.name, &probe.types);
+    let section = config
+        .format_section(&provider.name)
+        .unwrap_or_else(|| String::from(DEFAULT_SECTION));
+    let probe_rec = emit_probe_record(&section, &provider.name, &probe.name, Some(&probe.types));
     let pre_macro_block = TokenStream::new();
-    let impl_block = quote! {
-        {
-            let mut is_enabled: u64;
-            unsafe {
-                asm!(
-                    "990:   clr rax",
-                    #is_enabled_rec,
-                    out("rax") is_enabled,
-                    options(nomem, nostack, preserves_flags)
-                );
-            }
+    let fire_probe = quote! {
+        #unpacked_args
+        #tracing_event
+        unsafe {
+            asm!(
+                "990:   nop",
+                #probe_rec,
+                #in_regs
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+    };
 
-            if is_enabled != 0 {
-                #unpacked_args
-                unsafe {
-                    asm!(
-                        "990:   nop",
-                        #probe_rec,
-                        #in_regs
-                        options(nomem, nostack, preserves_flags)
-                    );
+    // Always-fire probes omit the is-enabled probe record entirely, rather than emitting one
+    // which is never consulted.
+    let (fired, skipped) = common::probe_status(config);
+    let (impl_block, is_enabled) = if config.always_fire {
+        let impl_block = quote! {
+            {
+                #fire_probe
+                #fired
+            }
+        };
+        (impl_block, quote! { true })
+    } else {
+        let is_enabled = is_enabled_check(&section, &provider.name, &probe.name);
+        let impl_block = quote! {
+            {
+                if #is_enabled {
+                    #fire_probe
+                    #fired
+                } else {
+                    #skipped
                 }
             }
-        }
+        };
+        (impl_block, is_enabled)
     };
     common::build_probe_macro(
         config,
@@ -85,10 +153,40 @@ fn compile_probe(
         &probe.types,
         pre_macro_block,
         impl_block,
+        is_enabled,
     )
 }
 
+// Return an expression which is true if a probe is enabled, checked at a new is-enabled site.
+//
+// The is-enabled site must write `rax`, rather than a register chosen by the compiler. When the
+// probe is enabled, the kernel traps on the instruction at the site and emulates it by setting
+// `rax` to 1, which is DTrace's is-enabled contract on x86_64. Naming `rax` as the output operand
+// tells the compiler it is overwritten, so nothing else relies on it being preserved.
+fn is_enabled_check(section: &str, provider_name: &str, probe_name: &str) -> TokenStream {
+    let is_enabled_rec = emit_probe_record(section, provider_name, probe_name, None);
+    quote! {
+        {
+            let is_enabled: u64;
+            unsafe {
+                asm!(
+                    "990:   clr rax",
+                    #is_enabled_rec,
+                    out("rax") is_enabled,
+                    options(nomem, nostack, preserves_flags)
+                );
+            }
+            is_enabled != 0
+        }
+    }
+}
+
 fn extract_probe_records_from_section() -> Result<Option<Section>, crate::Error> {
+    process_section(probe_section_data())
+}
+
+// Return the contents of the linker section containing the probe records.
+fn probe_section_data() -> &'static [u8] {
     extern "C" {
         #[link_name = "__start_set_dtrace_probes"]
         static dtrace_probes_start: usize;
@@ -97,68 +195,593 @@ fn extract_probe_records_from_section() -> Result<Option<Section>, crate::Error>
     }
 
     // Without this the illumos linker may decide to omit the symbols above that
-    // denote the start and stop addresses for this section. Note that the variable
+    // denote the start and stop addresses for this section, and the FreeBSD linker
+    // fails to resolve them if no probes are defined. Note that the variable
     // must be mutable, otherwise this will generate a read-only section with the
     // name `set_dtrace_probes`. The section containing the actual probe records is
     // writable (to implement one-time registration), so an immutable variable here
     // leads to _two_ sections, one writable and one read-only. A mutable variable
     // here ensures this ends up in a mutable section, the same as the probe records.
-    #[cfg(target_os = "illumos")]
+    #[cfg(any(target_os = "illumos", target_os = "freebsd"))]
     #[link_section = "set_dtrace_probes"]
     #[used]
     static mut FORCE_LOAD: [u64; 0] = [];
 
-    let data = unsafe {
+    unsafe {
         let start = (&dtrace_probes_start as *const usize) as usize;
         let stop = (&dtrace_probes_stop as *const usize) as usize;
         std::slice::from_raw_parts(start as *const u8, stop - start)
-    };
-    process_section(data)
+    }
+}
+
+// Commands for the DTrace helper device, from `<sys/dtrace.h>`. FreeBSD encodes the direction and
+// size of each command's argument, as `_IOW('z', 2, int)` and `_IOWR('z', 3, dof_helper_t)`.
+#[cfg(not(target_os = "freebsd"))]
+const DTRACEHIOC_REMOVE: u32 = 0x64746802;
+#[cfg(not(target_os = "freebsd"))]
+const DTRACEHIOC_ADDDOF: u32 = 0x64746803;
+#[cfg(target_os = "freebsd")]
+const DTRACEHIOC_REMOVE: u32 = 0x80047a02;
+#[cfg(target_os = "freebsd")]
+const DTRACEHIOC_ADDDOF: u32 = 0xc0587a03;
+
+// FreeBSD's `dof_helper_t`, which extends that of illumos with the process whose DOF is added, and
+// the generation assigned to it. The ioctl itself returns zero on success.
+#[cfg(target_os = "freebsd")]
+#[repr(C)]
+struct DofHelper {
+    dofhp_mod: [std::os::raw::c_char; 64],
+    dofhp_addr: u64,
+    dofhp_dof: u64,
+    dofhp_pid: libc::pid_t,
+    dofhp_gen: i32,
 }
 
 pub fn register_probes() -> Result<(), crate::Error> {
-    if let Some(ref section) = extract_probe_records_from_section()? {
-        let module_name = section
-            .providers
-            .values()
-            .next()
-            .and_then(|provider| {
-                provider.probes.values().next().and_then(|probe| {
-                    crate::record::addr_to_info(probe.address)
-                        .1
-                        .map(|path| path.rsplit('/').next().map(String::from).unwrap_or(path))
-                        .or_else(|| Some(format!("?{:#x}", probe.address)))
-                })
-            })
-            .unwrap_or_else(|| String::from("unknown-module"));
+    register_records(extract_probe_records_from_section()?).map(|_| ())
+}
+
+pub fn register_section(data: &[u8]) -> Result<(), crate::Error> {
+    register_records(process_section(data)?).map(|_| ())
+}
+
+pub fn register_probes_scoped() -> Result<Vec<i32>, crate::Error> {
+    register_records(extract_probe_records_from_section()?)
+}
+
+pub fn dof_section_size() -> Result<Option<usize>, crate::Error> {
+    Ok(Some(dof_size(peek_section(probe_section_data())?)))
+}
+
+pub fn deregister_probes(generation: i32) -> Result<(), crate::Error> {
+    // illumos takes the generation as the argument itself, while FreeBSD copies it in.
+    #[cfg(not(target_os = "freebsd"))]
+    let arg = generation as libc::intptr_t as *const _;
+    #[cfg(target_os = "freebsd")]
+    let arg = &generation as *const i32 as *const _;
+    helper_ioctl(DTRACEHIOC_REMOVE, arg)?;
+    Ok(())
+}
+
+// Register the probes in a section, returning the generations assigned to them by DTrace.
+//
+// DTrace labels the probes in each DOF it is given with a single module name, so the probes are
+// grouped by the object file containing them, and registered with one ioctl per object. If any of
+// these fails, the probes already registered are removed again before returning the error.
+fn register_records(section: Option<Section>) -> Result<Vec<i32>, crate::Error> {
+    let mut generations = Vec::new();
+    for (module_name, dof) in module_dof(section) {
         let mut modname = [0; 64];
         for (i, byte) in module_name.bytes().take(modname.len() - 1).enumerate() {
             modname[i] = byte as i8;
         }
-        ioctl_section(&serialize_section(&section), modname).map_err(crate::Error::from)
-    } else {
-        Ok(())
+        match ioctl_section(&dof, modname) {
+            Ok(generation) => generations.push(generation),
+            Err(err) => {
+                for generation in generations {
+                    // The error adding the DOF is more useful than any removing it.
+                    let _ = deregister_probes(generation);
+                }
+                return Err(err);
+            }
+        }
     }
+    Ok(generations)
 }
 
-fn ioctl_section(buf: &[u8], modname: [std::os::raw::c_char; 64]) -> Result<(), std::io::Error> {
-    use std::fs::OpenOptions;
-    use std::os::unix::io::AsRawFd;
+// Return the serialized DOF registered for each module containing the probes in a section, keyed
+// by the module's file name.
+fn module_dof(section: Option<Section>) -> BTreeMap<String, Vec<u8>> {
+    section
+        .map(group_by_module)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(module_name, section)| (module_name, serialize_section(&section)))
+        .collect()
+}
+
+// Return the total size of the DOF registered for the probes in a section.
+fn dof_size(section: Option<Section>) -> usize {
+    module_dof(section).values().map(Vec::len).sum()
+}
+
+// Split a section into one section for each module containing its probes, keyed by the module's
+// file name.
+fn group_by_module(section: Section) -> BTreeMap<String, Section> {
+    let ident = section.ident;
+    let mut modules = BTreeMap::new();
+    for (provider_name, provider) in section.providers {
+        let dof::Provider { name, probes } = provider;
+        for (probe_name, probe) in probes {
+            let module_name = crate::record::addr_to_info(probe.address)
+                .1
+                .map(|path| path.rsplit('/').next().map(String::from).unwrap_or(path))
+                .unwrap_or_else(|| String::from("unknown-module"));
+            modules
+                .entry(module_name)
+                .or_insert_with(|| Section {
+                    ident,
+                    providers: BTreeMap::new(),
+                })
+                .providers
+                .entry(provider_name.clone())
+                .or_insert_with(|| dof::Provider {
+                    name: name.clone(),
+                    probes: BTreeMap::new(),
+                })
+                .probes
+                .insert(probe_name, probe);
+        }
+    }
+    modules
+}
 
+#[cfg(not(target_os = "freebsd"))]
+fn ioctl_section(buf: &[u8], modname: [std::os::raw::c_char; 64]) -> Result<i32, crate::Error> {
     let helper = dof::dof_bindings::dof_helper {
         dofhp_mod: modname,
         dofhp_addr: buf.as_ptr() as u64,
         dofhp_dof: buf.as_ptr() as u64,
     };
-    let data = &helper as *const _;
-    let cmd: i32 = 0x64746803;
+    helper_ioctl(DTRACEHIOC_ADDDOF, &helper as *const _ as *const _)
+        .map_err(|err| registration_error(err, current_zone()))
+}
+
+#[cfg(target_os = "freebsd")]
+fn ioctl_section(buf: &[u8], modname: [std::os::raw::c_char; 64]) -> Result<i32, crate::Error> {
+    let mut helper = DofHelper {
+        dofhp_mod: modname,
+        dofhp_addr: buf.as_ptr() as u64,
+        dofhp_dof: buf.as_ptr() as u64,
+        dofhp_pid: std::process::id() as libc::pid_t,
+        dofhp_gen: 0,
+    };
+    helper_ioctl(DTRACEHIOC_ADDDOF, &mut helper as *mut _ as *const _)
+        .map_err(|err| registration_error(err, current_zone()))?;
+    Ok(helper.dofhp_gen)
+}
+
+// Convert an error adding DOF to the helper device into a crate error.
+//
+// A zone may not permit its processes to register probes, in which case the helper device fails
+// with `EPERM`. This is reported as such, naming the zone, since the fix is in the zone's
+// configuration rather than the process.
+fn registration_error(err: std::io::Error, zone: Option<String>) -> crate::Error {
+    match zone {
+        Some(zone) if err.raw_os_error() == Some(libc::EPERM) => {
+            crate::Error::DTraceZoneUnsupported { zone }
+        }
+        _ => err.into(),
+    }
+}
+
+// Return the name of the zone in which this process runs, if it is not the global zone.
+#[cfg(all(not(test), target_os = "illumos"))]
+fn current_zone() -> Option<String> {
+    // From `<zone.h>` and `<sys/zone.h>`.
+    const GLOBAL_ZONEID: i32 = 0;
+    const ZONENAME_MAX: usize = 64;
+    extern "C" {
+        fn getzoneid() -> i32;
+        fn getzonenamebyid(id: i32, buf: *mut libc::c_char, buflen: libc::size_t) -> libc::ssize_t;
+    }
+
+    let id = unsafe { getzoneid() };
+    if id == GLOBAL_ZONEID {
+        return None;
+    }
+    let mut name = [0; ZONENAME_MAX];
+    let ret = unsafe { getzonenamebyid(id, name.as_mut_ptr(), name.len()) };
+    if ret < 0 {
+        return Some(format!("<zone {}>", id));
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+// Only illumos has zones.
+#[cfg(all(not(test), not(target_os = "illumos")))]
+fn current_zone() -> Option<String> {
+    None
+}
+
+// Issue a command to the DTrace helper device, returning the result of the ioctl.
+#[cfg(not(test))]
+fn helper_ioctl(cmd: u32, data: *const libc::c_void) -> Result<i32, std::io::Error> {
+    use std::fs::OpenOptions;
+    use std::os::unix::io::AsRawFd;
+
     let file = OpenOptions::new()
         .read(true)
         .write(true)
         .open("/dev/dtrace/helper")?;
-    if unsafe { libc::ioctl(file.as_raw_fd(), cmd, data) } < 0 {
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), cmd as _, data) };
+    if ret < 0 {
         Err(std::io::Error::last_os_error())
     } else {
-        Ok(())
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    static HELPER_IOCTLS: std::cell::RefCell<Vec<(u32, usize)>> = std::cell::RefCell::new(Vec::new());
+    static HELPER_MODULES: std::cell::RefCell<Vec<String>> = std::cell::RefCell::new(Vec::new());
+    static HELPER_ERRNO: std::cell::Cell<Option<i32>> = std::cell::Cell::new(None);
+    static HELPER_ADDDOF_LIMIT: std::cell::Cell<Option<usize>> = std::cell::Cell::new(None);
+    static ZONE: std::cell::RefCell<Option<String>> = std::cell::RefCell::new(None);
+}
+
+// Return the zone set by a test, rather than that of the process running the tests.
+#[cfg(test)]
+fn current_zone() -> Option<String> {
+    ZONE.with(|zone| zone.borrow().clone())
+}
+
+// Record the commands issued to the helper device, and the module name of any DOF added, rather
+// than requiring DTrace in tests. Adding DOF returns the number of commands issued so far as the
+// generation, or fails with the error set by a test. A test may also limit the number of times DOF
+// can be added, after which adding it fails with `ENOSPC`. The argument recorded for removing DOF
+// is the generation, on every system.
+#[cfg(test)]
+fn helper_ioctl(cmd: u32, data: *const libc::c_void) -> Result<i32, std::io::Error> {
+    if let Some(errno) = HELPER_ERRNO.with(|errno| errno.get()) {
+        return Err(std::io::Error::from_raw_os_error(errno));
+    }
+    if cmd == DTRACEHIOC_ADDDOF {
+        if let Some(limit) = HELPER_ADDDOF_LIMIT.with(|limit| limit.get()) {
+            let added = HELPER_IOCTLS.with(|ioctls| {
+                ioctls
+                    .borrow()
+                    .iter()
+                    .filter(|(cmd, _)| *cmd == DTRACEHIOC_ADDDOF)
+                    .count()
+            });
+            if added >= limit {
+                return Err(std::io::Error::from_raw_os_error(libc::ENOSPC));
+            }
+        }
+    }
+    if cmd == DTRACEHIOC_ADDDOF {
+        let helper = unsafe { &*(data as *const dof::dof_bindings::dof_helper) };
+        let modname = unsafe { std::ffi::CStr::from_ptr(helper.dofhp_mod.as_ptr()) };
+        HELPER_MODULES.with(|modules| {
+            modules
+                .borrow_mut()
+                .push(modname.to_string_lossy().into_owned())
+        });
+    }
+    #[cfg(target_os = "freebsd")]
+    let data = if cmd == DTRACEHIOC_REMOVE {
+        unsafe { *(data as *const i32) as usize as *const libc::c_void }
+    } else {
+        data
+    };
+    HELPER_IOCTLS.with(|ioctls| {
+        let mut ioctls = ioctls.borrow_mut();
+        ioctls.push((cmd, data as usize));
+        let generation = ioctls.len() as i32;
+        #[cfg(target_os = "freebsd")]
+        if cmd == DTRACEHIOC_ADDDOF {
+            unsafe { (*(data as *mut DofHelper)).dofhp_gen = generation };
+            return Ok(0);
+        }
+        Ok(generation)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+
+    fn test_provider() -> Provider {
+        Provider {
+            name: String::from("foo"),
+            probes: vec![Probe {
+                name: String::from("bar"),
+                types: vec![DataType::Native(dtrace_parser::DataType::U8)],
+                arg_names: vec![],
+                docs: None,
+            }],
+            use_statements: vec![],
+        }
+    }
+
+    #[test]
+    fn test_compile_probe() {
+        let provider = test_provider();
+        let config = crate::CompileProvidersConfig::default();
+        let output = compile_probe(&provider, &provider.probes[0], &config).to_string();
+        assert!(output.contains("clr rax"));
+        assert!(output.contains("is_enabled != 0"));
+        assert!(output.contains("990:   nop"));
+    }
+
+    #[test]
+    fn test_compile_probe_is_enabled_register() {
+        // DTrace sets `rax` at an enabled is-enabled site, so that register must be the output.
+        let provider = test_provider();
+        let config = crate::CompileProvidersConfig::default();
+        let output = compile_probe(&provider, &provider.probes[0], &config).to_string();
+        assert!(output.contains("out (\"rax\") is_enabled"));
+        assert!(!output.contains("out (reg)"));
+        // Only the is-enabled site itself refers to `rax`.
+        let rest = output.replace("clr rax", "").replace("out (\"rax\")", "");
+        assert!(!rest.contains("rax"));
+    }
+
+    #[test]
+    fn test_compile_probe_always_fire() {
+        let provider = test_provider();
+        let config = crate::CompileProvidersConfig {
+            always_fire: true,
+            ..Default::default()
+        };
+        let output = compile_probe(&provider, &provider.probes[0], &config).to_string();
+        assert!(!output.contains("clr rax"));
+        let general = &output[output.rfind("($ args_lambda : expr").unwrap()..];
+        assert!(!general.contains("is_enabled"));
+        assert!(output.contains("990:   nop"));
+    }
+
+    #[test]
+    fn test_compile_probe_return_status() {
+        let provider = test_provider();
+        let output = compile_probe(&provider, &provider.probes[0], &Default::default());
+        assert!(!output.to_string().contains("true"));
+
+        // The status is whether the is-enabled site reported the probe as enabled.
+        let config = crate::CompileProvidersConfig {
+            return_status: true,
+            ..Default::default()
+        };
+        let output = compile_probe(&provider, &provider.probes[0], &config).to_string();
+        let branch = &output[output.find("is_enabled != 0").unwrap()..];
+        let fired = branch.find("true").unwrap();
+        let skipped = branch.find("else { false }").unwrap();
+        assert!(branch.find("990:   nop").unwrap() < fired);
+        assert!(fired < skipped);
+
+        let config = crate::CompileProvidersConfig {
+            return_status: true,
+            always_fire: true,
+            ..Default::default()
+        };
+        let output = compile_probe(&provider, &provider.probes[0], &config).to_string();
+        let general = &output[output.rfind("($ args_lambda : expr").unwrap()..];
+        assert!(general.contains("true"));
+        let globally_enabled = general.find("probes_globally_enabled").unwrap();
+        assert_eq!(general.matches("false").count(), 1);
+        assert!(general.find("else { false }").unwrap() > globally_enabled);
+    }
+
+    #[test]
+    fn test_compile_empty_provider() {
+        let provider = Provider {
+            name: String::from("foo"),
+            probes: vec![],
+            use_statements: vec![],
+        };
+        let output = compile_provider(&provider, &crate::CompileProvidersConfig::default());
+        assert_eq!(
+            output.to_string(),
+            quote! {
+                pub(crate) mod foo {
+                    /// The probes defined in this provider.
+                    #[allow(dead_code)]
+                    pub const PROBES: &[::usdt::ProbeInfo] = &[];
+                    /// A description of this provider.
+                    #[allow(dead_code)]
+                    pub const PROVIDER: ::usdt::ProviderInfo = ::usdt::ProviderInfo {
+                        name: "foo",
+                        probes: PROBES,
+                    };
+                }
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn test_compile_provider_section_name() {
+        let provider = test_provider();
+        let output = compile_provider(&provider, &crate::CompileProvidersConfig::default());
+        let output = output.to_string();
+        assert!(output.contains("set_dtrace_probes"));
+        assert!(!output.contains("register_provider"));
+
+        let config = crate::CompileProvidersConfig {
+            section_name: Some(String::from("probes_{provider}")),
+            ..Default::default()
+        };
+        let output = compile_provider(&provider, &config).to_string();
+        assert!(!output.contains("set_dtrace_probes"));
+        assert!(output.contains("pushsection probes_foo"));
+        assert!(output.contains("fn register_provider"));
+        assert!(output.contains("\"__start_probes_foo\""));
+        assert!(output.contains("\"__stop_probes_foo\""));
+    }
+
+    #[test]
+    fn test_compile_provider_source_contains_asm() {
+        let source = "provider foo { probe bar(uint8_t); probe baz(char*, int64_t); };";
+        let config = crate::CompileProvidersConfig::default();
+        let output = crate::compile_provider_source(source, &config)
+            .unwrap()
+            .to_string();
+        assert_eq!(output.matches("macro_rules !").count(), 2);
+        assert!(output.matches("asm !").count() >= 2);
+        assert!(output.contains("990:   nop"));
+        assert!(output.contains("clr rax"));
+    }
+
+    // Construct a section with a probe in this test binary, and another in the C library.
+    fn two_module_section() -> Section {
+        let probe = |name: &str, address: u64| dof::Probe {
+            name: String::from(name),
+            function: String::new(),
+            address,
+            offsets: vec![0],
+            enabled_offsets: vec![],
+            arguments: vec![],
+        };
+        let mut probes = BTreeMap::new();
+        let local = probe("local", test_provider as *const () as u64);
+        let libc = probe("libc", libc::getpid as *const () as u64);
+        probes.insert(local.name.clone(), local);
+        probes.insert(libc.name.clone(), libc);
+        let mut section = Section::default();
+        section.providers.insert(
+            String::from("foo"),
+            dof::Provider {
+                name: String::from("foo"),
+                probes,
+            },
+        );
+        section
+    }
+
+    #[test]
+    fn test_register_records_by_module() {
+        HELPER_IOCTLS.with(|ioctls| ioctls.borrow_mut().clear());
+        HELPER_MODULES.with(|modules| modules.borrow_mut().clear());
+
+        let generations = register_records(Some(two_module_section())).unwrap();
+        assert_eq!(generations, vec![1, 2]);
+        HELPER_IOCTLS.with(|ioctls| {
+            assert!(ioctls
+                .borrow()
+                .iter()
+                .all(|(cmd, _)| *cmd == DTRACEHIOC_ADDDOF))
+        });
+        HELPER_MODULES.with(|modules| {
+            let modules = modules.borrow();
+            assert_eq!(modules.len(), 2);
+            assert_ne!(modules[0], modules[1]);
+            assert!(modules.iter().all(|name| !name.contains('/')));
+        });
+    }
+
+    #[test]
+    fn test_dof_size() {
+        assert_eq!(dof_size(None), 0);
+        let dof = module_dof(Some(two_module_section()));
+        assert_eq!(dof.len(), 2);
+        assert_eq!(
+            dof_size(Some(two_module_section())),
+            dof.values().map(Vec::len).sum::<usize>()
+        );
+        assert!(dof.values().all(|dof| !dof.is_empty()));
+    }
+
+    #[test]
+    fn test_probe_registration_guard() {
+        HELPER_IOCTLS.with(|ioctls| ioctls.borrow_mut().clear());
+
+        // Dropping the registration removes the probes from each module, using the generations
+        // returned when adding them.
+        let registration = crate::ProbeRegistration {
+            generations: register_records(Some(two_module_section())).unwrap(),
+        };
+        drop(registration);
+        HELPER_IOCTLS.with(|ioctls| {
+            let ioctls = ioctls.borrow();
+            assert_eq!(ioctls.len(), 4);
+            assert_eq!(ioctls[0].0, DTRACEHIOC_ADDDOF);
+            assert_eq!(ioctls[1].0, DTRACEHIOC_ADDDOF);
+            assert_eq!(ioctls[2], (DTRACEHIOC_REMOVE, 1));
+            assert_eq!(ioctls[3], (DTRACEHIOC_REMOVE, 2));
+        });
+
+        // Forgetting the registration leaves the probes registered.
+        std::mem::forget(crate::ProbeRegistration {
+            generations: register_records(Some(two_module_section())).unwrap(),
+        });
+        HELPER_IOCTLS.with(|ioctls| assert_eq!(ioctls.borrow().len(), 6));
+    }
+
+    #[test]
+    fn test_register_records_failure_removes_added_probes() {
+        HELPER_IOCTLS.with(|ioctls| ioctls.borrow_mut().clear());
+        HELPER_ADDDOF_LIMIT.with(|limit| limit.set(Some(1)));
+
+        // The second module fails to register, so the first is removed again, leaving no probes
+        // registered without a generation to remove them.
+        let err = register_records(Some(two_module_section())).unwrap_err();
+        assert!(matches!(err, crate::Error::IO(ref e) if e.raw_os_error() == Some(libc::ENOSPC)));
+        HELPER_IOCTLS.with(|ioctls| {
+            let ioctls = ioctls.borrow();
+            assert_eq!(ioctls.len(), 2);
+            assert_eq!(ioctls[0].0, DTRACEHIOC_ADDDOF);
+            assert_eq!(ioctls[1], (DTRACEHIOC_REMOVE, 1));
+        });
+
+        HELPER_ADDDOF_LIMIT.with(|limit| limit.set(None));
+    }
+
+    #[test]
+    fn test_auto_register_probe() {
+        HELPER_IOCTLS.with(|ioctls| ioctls.borrow_mut().clear());
+
+        // Firing the probe registers the probes in this test binary, which are all in one module,
+        // the first time only.
+        crate::auto_registered::fire!();
+        crate::auto_registered::fire!();
+        assert!(crate::REGISTER_PROBES.is_completed());
+        HELPER_IOCTLS.with(|ioctls| {
+            let ioctls = ioctls.borrow();
+            assert_eq!(ioctls.len(), 1);
+            assert_eq!(ioctls[0].0, DTRACEHIOC_ADDDOF);
+        });
+    }
+
+    #[test]
+    fn test_register_in_zone() {
+        HELPER_ERRNO.with(|errno| errno.set(Some(libc::EPERM)));
+
+        // In the global zone, the error from the helper device is returned as is.
+        ZONE.with(|zone| *zone.borrow_mut() = None);
+        let err = register_records(Some(two_module_section())).unwrap_err();
+        assert!(matches!(err, crate::Error::IO(ref e) if e.raw_os_error() == Some(libc::EPERM)));
+
+        // In a non-global zone, the zone is named, with guidance on configuring it.
+        ZONE.with(|zone| *zone.borrow_mut() = Some(String::from("myzone")));
+        let err = register_records(Some(two_module_section())).unwrap_err();
+        assert!(matches!(
+            err,
+            crate::Error::DTraceZoneUnsupported { ref zone } if zone == "myzone"
+        ));
+        let message = err.to_string();
+        assert!(message.contains("\"myzone\""));
+        assert!(message.contains("limitpriv"));
+
+        // Other errors are not attributed to the zone.
+        HELPER_ERRNO.with(|errno| errno.set(Some(libc::ENOENT)));
+        let err = register_records(Some(two_module_section())).unwrap_err();
+        assert!(matches!(err, crate::Error::IO(_)));
+
+        HELPER_ERRNO.with(|errno| errno.set(None));
+        ZONE.with(|zone| *zone.borrow_mut() = None);
     }
 }
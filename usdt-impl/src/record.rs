@@ -21,7 +21,8 @@ use goblin::Object;
 
 use crate::DataType;
 
-// Version number for probe records containing data about all probes.
+// Version number for probe records containing data about all probes. This identifies the layout
+// of the records, which may differ between versions of this crate linked into the same program.
 //
 // NOTE: This must have a maximum of `u8::MAX - 1`. See `read_and_update_record_version` for
 // details.
@@ -117,7 +118,20 @@ pub fn extract_probe_records<P: AsRef<Path>>(file: P) -> Result<Option<Section>,
 }
 
 // Extract records for all defined probes from our custom linker sections.
-pub(crate) fn process_section(mut data: &[u8]) -> Result<Option<Section>, crate::Error> {
+pub(crate) fn process_section(data: &[u8]) -> Result<Option<Section>, crate::Error> {
+    read_section(data, true)
+}
+
+// Extract records for the probes in a section which have not yet been processed, without marking
+// them as processed, so that a later call to `process_section` still returns them.
+#[allow(dead_code)]
+pub(crate) fn peek_section(data: &[u8]) -> Result<Option<Section>, crate::Error> {
+    read_section(data, false)
+}
+
+// Extract the probe records from a section, marking each as processed if `claim` is true.
+fn read_section(mut data: &[u8], claim: bool) -> Result<Option<Section>, crate::Error> {
+    check_record_versions(data)?;
     let mut providers = BTreeMap::new();
 
     while !data.is_empty() {
@@ -129,7 +143,7 @@ pub(crate) fn process_section(mut data: &[u8]) -> Result<Option<Section>, crate:
         let mut len_bytes = data;
         let len = len_bytes.read_u32::<NativeEndian>()? as usize;
         let (rec, rest) = data.split_at(len);
-        process_probe_record(&mut providers, &rec)?;
+        process_probe_record(&mut providers, &rec, claim)?;
         data = rest;
     }
 
@@ -139,6 +153,37 @@ pub(crate) fn process_section(mut data: &[u8]) -> Result<Option<Section>, crate:
     }))
 }
 
+// Check that the unprocessed records in a section can all be read, before any are claimed.
+//
+// A section containing only records from a future version is skipped, in the expectation that the
+// newer crate which wrote them also registers them, along with any from older versions. But if the
+// section also contains records we can read, then two versions of this crate are in use, and only
+// some of the probes would be registered. That's an error, reported before any record is claimed,
+// so that the newer crate can still register all of them.
+fn check_record_versions(mut data: &[u8]) -> Result<(), crate::Error> {
+    let mut readable = false;
+    let mut unreadable = None;
+    while data.len() > std::mem::size_of::<u32>() {
+        let mut len_bytes = data;
+        let len = len_bytes.read_u32::<NativeEndian>()? as usize;
+        match data[4] {
+            u8::MAX => {}
+            version if version <= PROBE_REC_VERSION => readable = true,
+            version => {
+                unreadable.get_or_insert(version);
+            }
+        }
+        data = &data[len.max(5).min(data.len())..];
+    }
+    match unreadable {
+        Some(version) if readable => Err(crate::Error::MixedRecordVersions {
+            version,
+            supported: PROBE_REC_VERSION,
+        }),
+        _ => Ok(()),
+    }
+}
+
 // Convert an address in an object file into a function and file name, if possible.
 pub(crate) fn addr_to_info(addr: u64) -> (Option<String>, Option<String>) {
     unsafe {
@@ -151,10 +196,15 @@ pub(crate) fn addr_to_info(addr: u64) -> (Option<String>, Option<String>) {
         if libc::dladdr(addr as *const c_void, &mut info as *mut _) == 0 {
             (None, None)
         } else {
-            (
-                Some(CStr::from_ptr(info.dli_sname).to_string_lossy().to_string()),
-                Some(CStr::from_ptr(info.dli_fname).to_string_lossy().to_string()),
-            )
+            // Either name may be null, e.g., if no exported symbol contains the address.
+            let to_string = |s: *const libc::c_char| {
+                if s.is_null() {
+                    None
+                } else {
+                    Some(CStr::from_ptr(s).to_string_lossy().to_string())
+                }
+            };
+            (to_string(info.dli_sname), to_string(info.dli_fname))
         }
     }
 }
@@ -206,14 +256,20 @@ fn read_and_update_record_version(data: &[u8]) -> Result<u8, crate::Error> {
     }
 }
 
-// Process a single record from the custom linker section.
+// Process a single record from the custom linker section, marking it as processed if `claim` is
+// true.
 fn process_probe_record(
     providers: &mut BTreeMap<String, Provider>,
     rec: &[u8],
+    claim: bool,
 ) -> Result<(), crate::Error> {
     // First four bytes are the length, next byte is the version number.
     let (rec, mut data) = rec.split_at(5);
-    let version = read_and_update_record_version(&rec[4..5])?;
+    let version = if claim {
+        read_and_update_record_version(&rec[4..5])?
+    } else {
+        rec[4]
+    };
 
     // If this record comes from a future version of the data format, we skip it
     // and hope that the author of main will *also* include a call to a more
@@ -290,10 +346,20 @@ impl<'a> ReadCstrExt<'a> for &'a [u8] {
     }
 }
 
-// Construct the ASM record for a probe. If `types` is `None`, then is is an is-enabled probe.
+// The name of the linker section into which probe records are emitted by default.
 #[allow(dead_code)]
-pub(crate) fn emit_probe_record(prov: &str, probe: &str, types: Option<&[DataType]>) -> String {
-    let section_ident = r#"set_dtrace_probes,"aw","progbits""#;
+pub(crate) const DEFAULT_SECTION: &str = "set_dtrace_probes";
+
+// Construct the ASM record for a probe, emitted into the named linker section. If `types` is
+// `None`, then is is an is-enabled probe.
+#[allow(dead_code)]
+pub(crate) fn emit_probe_record(
+    section: &str,
+    prov: &str,
+    probe: &str,
+    types: Option<&[DataType]>,
+) -> String {
+    let section_ident = format!(r#"{},"aw","progbits""#, section);
     let is_enabled = types.is_none();
     let n_args = types.map_or(0, |typ| typ.len());
     let arguments = types.map_or_else(String::new, |types| {
@@ -351,9 +417,9 @@ mod test {
 
     use super::emit_probe_record;
     use super::process_probe_record;
-    use super::process_section;
     use super::DataType;
     use super::PROBE_REC_VERSION;
+    use super::{peek_section, process_section};
     use super::{MAX_PROBE_NAME_LEN, MAX_PROVIDER_NAME_LEN};
 
     #[test]
@@ -375,7 +441,7 @@ mod test {
             .unwrap();
 
         let mut providers = BTreeMap::new();
-        process_probe_record(&mut providers, rec.as_slice()).unwrap();
+        process_probe_record(&mut providers, rec.as_slice(), true).unwrap();
 
         let probe = providers
             .get("provider")
@@ -408,7 +474,7 @@ mod test {
             .unwrap();
 
         let mut providers = BTreeMap::new();
-        process_probe_record(&mut providers, rec.as_slice()).unwrap();
+        process_probe_record(&mut providers, rec.as_slice(), true).unwrap();
 
         let expected_provider_name = &long_name[..MAX_PROVIDER_NAME_LEN - 1];
         let expected_probe_name = &long_name[..MAX_PROBE_NAME_LEN - 1];
@@ -489,6 +555,25 @@ mod test {
         assert_eq!(section.providers.len(), 0);
     }
 
+    #[test]
+    fn test_peek_section() {
+        // Peeking at a section returns its probes without marking them as processed, so that they
+        // are still returned when the section is processed.
+        let data = make_record(PROBE_REC_VERSION);
+        let peeked = format!("{:?}", peek_section(&data).unwrap().unwrap());
+        assert_eq!(data[4], PROBE_REC_VERSION);
+        assert_eq!(
+            format!("{:?}", peek_section(&data).unwrap().unwrap()),
+            peeked
+        );
+        assert_eq!(
+            format!("{:?}", process_section(&data).unwrap().unwrap()),
+            peeked
+        );
+        assert_eq!(data[4], u8::MAX);
+        assert!(peek_section(&data).unwrap().unwrap().providers.is_empty());
+    }
+
     #[test]
     fn test_process_section_future_version() {
         // Ensure that we _don't_ modify a future version number in a probe record, but that the
@@ -499,6 +584,33 @@ mod test {
         assert_eq!(data[4], PROBE_REC_VERSION + 1);
     }
 
+    #[test]
+    fn test_process_section_mixed_versions() {
+        // Records from a future version alongside our own mean that two versions of the crate are
+        // in use, which is an error. None of the records are claimed, so that the newer version can
+        // still register them all.
+        let mut data = make_record(PROBE_REC_VERSION);
+        let len = data.len();
+        data.extend(make_record(PROBE_REC_VERSION + 1));
+        for result in [peek_section(&data), process_section(&data)] {
+            match result {
+                Err(crate::Error::MixedRecordVersions { version, supported }) => {
+                    assert_eq!(version, PROBE_REC_VERSION + 1);
+                    assert_eq!(supported, PROBE_REC_VERSION);
+                }
+                other => panic!("Expected a mixed-version error, found {:?}", other),
+            }
+        }
+        assert_eq!(data[4], PROBE_REC_VERSION);
+        assert_eq!(data[len + 4], PROBE_REC_VERSION + 1);
+
+        // Once our own records are registered, the remaining ones are skipped as usual.
+        let mut data = make_record(u8::MAX);
+        data.extend(make_record(PROBE_REC_VERSION + 1));
+        let section = process_section(&data).unwrap().unwrap();
+        assert!(section.providers.is_empty());
+    }
+
     trait WriteCstrExt {
         fn write_cstr(&mut self, s: &str);
     }
@@ -518,7 +630,7 @@ mod test {
             DataType::Native(dtrace_parser::DataType::U8),
             DataType::Native(dtrace_parser::DataType::String),
         ];
-        let record = emit_probe_record(provider, probe, Some(&types));
+        let record = emit_probe_record(super::DEFAULT_SECTION, provider, probe, Some(&types));
         let mut lines = record.lines();
         println!("{}", record);
         lines.next(); // empty line
@@ -0,0 +1,502 @@
+//! USDT implementation on Linux, using SystemTap-compatible SDT notes.
+//!
+//! Linux has no DTrace kernel module to register DOF with. Instead, tools such as `bpftrace` and
+//! `perf` discover statically-defined probes by reading ELF notes from the `.note.stapsdt`
+//! section of a binary. Each probe site emits one such note, describing the address of the probe
+//! (a `nop` instruction), the provider and probe names, and where each argument may be found when
+//! the probe fires. Tracers attach by patching the `nop` instruction, so there is no registration
+//! step at all.
+//!
+//! The format of the notes is described in detail at
+//! <https://sourceware.org/systemtap/wiki/UserSpaceProbeImplementation>.
+
+// Copyright 2021 Oxide Computer Company
+
+use crate::{common, wrap_probes_in_modules, DataType, Probe, Provider};
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use std::convert::TryFrom;
+
+/// Compile a DTrace provider definition into Rust tokens that implement its probes.
+pub fn compile_providers_source(
+    source: &str,
+    config: &crate::CompileProvidersConfig,
+) -> Result<Vec<(String, TokenStream)>, crate::Error> {
+    let dfile = dtrace_parser::File::try_from(source)?;
+    let providers = dfile
+        .providers()
+        .iter()
+        .map(|provider| {
+            let provider = Provider::from(provider);
+            let tokens = compile_provider(&provider, config);
+            (provider.name, tokens)
+        })
+        .collect::<Vec<_>>();
+    Ok(providers)
+}
+
+pub fn compile_provider_from_definition(
+    provider: &Provider,
+    config: &crate::CompileProvidersConfig,
+) -> Result<TokenStream, crate::Error> {
+    Ok(compile_provider(provider, config))
+}
+
+fn compile_provider(provider: &Provider, config: &crate::CompileProvidersConfig) -> TokenStream {
+    let provider = &common::add_implicit_arguments(provider, config);
+    let probe_impls = provider
+        .probes
+        .iter()
+        .map(|probe| compile_probe(provider, probe, config))
+        .collect::<Vec<_>>();
+    // Notes are always emitted into `.note.stapsdt`, and need no registration.
+    let register_fn = common::build_register_provider_fn(config, provider, quote! { Ok(()) });
+    let enabled_fn = common::build_enabled_probes_fn(config, provider, |probe| {
+        if config.semaphore && !config.always_fire {
+            let semaphore = semaphore_ident(&provider.name, &probe.name);
+            quote! {
+                unsafe { ::core::ptr::read_volatile(::core::ptr::addr_of!(#semaphore)) != 0 }
+            }
+        } else {
+            quote! { true }
+        }
+    });
+    wrap_probes_in_modules(
+        config,
+        provider,
+        quote! {
+            #register_fn
+            #enabled_fn
+            #(#probe_impls)*
+        },
+    )
+}
+
+fn compile_probe(
+    provider: &Provider,
+    probe: &Probe,
+    config: &crate::CompileProvidersConfig,
+) -> TokenStream {
+    let (unpacked_args, in_regs) = common::construct_probe_args(&probe.name, &probe.types, config);
+    let tracing_event =
+        common::build_tracing_event(config, &provider.name, &probe.name, &probe.types);
+    let use_semaphore = config.semaphore && !config.always_fire;
+    let note = emit_probe_note(
+        &provider.name,
+        &probe.name,
+        &probe.types,
+        use_semaphore,
+        config,
+    );
+    let fire_probe = |semaphore_operand| {
+        quote! {
+            #unpacked_args
+            #tracing_event
+            unsafe {
+                asm!(
+                    "990:   nop",
+                    #note,
+                    #semaphore_operand
+                    #in_regs
+                    options(nomem, nostack, preserves_flags)
+                );
+            }
+        }
+    };
+
+    // Without a semaphore there is no is-enabled mechanism for SDT notes, so probes always fire.
+    let (fired, skipped) = common::probe_status(config);
+    let (pre_macro_block, impl_block, is_enabled) = if use_semaphore {
+        let semaphore = semaphore_ident(&provider.name, &probe.name);
+        let mod_name = config.provider_module(&provider.name);
+        let mod_name = if mod_name.is_empty() {
+            quote! {}
+        } else {
+            quote! { #mod_name:: }
+        };
+        let vis = config.visibility();
+        let pre_macro_block = quote! {
+            // Tracers increment the semaphore when they attach to the probe, and decrement it when
+            // they detach. By convention, semaphores are placed in the `.probes` section.
+            #[doc(hidden)]
+            #[link_section = ".probes"]
+            #[used]
+            #vis static mut #semaphore: u16 = 0;
+        };
+        let fire_probe = fire_probe(quote! { semaphore = sym $crate:: #mod_name #semaphore, });
+        let impl_block = quote! {
+            {
+                let is_enabled = unsafe {
+                    ::core::ptr::read_volatile(::core::ptr::addr_of!($crate:: #mod_name #semaphore))
+                };
+                if is_enabled != 0 {
+                    #fire_probe
+                    #fired
+                } else {
+                    #skipped
+                }
+            }
+        };
+        let is_enabled = quote! {
+            unsafe {
+                ::core::ptr::read_volatile(::core::ptr::addr_of!($crate:: #mod_name #semaphore)) != 0
+            }
+        };
+        (pre_macro_block, impl_block, is_enabled)
+    } else {
+        let fire_probe = fire_probe(quote! {});
+        (
+            TokenStream::new(),
+            quote! { { #fire_probe #fired } },
+            quote! { true },
+        )
+    };
+    common::build_probe_macro(
+        config,
+        provider,
+        &probe.name,
+        &probe.types,
+        pre_macro_block,
+        impl_block,
+        is_enabled,
+    )
+}
+
+// Return the identifier of the semaphore for a probe.
+fn semaphore_ident(provider_name: &str, probe_name: &str) -> proc_macro2::Ident {
+    format_ident!("__usdt_semaphore_{}_{}", provider_name, probe_name)
+}
+
+// Return the SDT argument descriptor for a probe argument passed in the given register, on the
+// given architecture.
+//
+// The descriptor has the form `size@location`, where `size` is the size of the argument in bytes,
+// negated for signed integers.
+fn argument_descriptor(typ: &DataType, reg: &str, arch: &str) -> String {
+    use dtrace_parser::DataType as D;
+    let size = match typ {
+        DataType::Native(D::U8) => 1,
+        DataType::Native(D::I8) => -1,
+        DataType::Native(D::U16) => 2,
+        DataType::Native(D::I16) => -2,
+        DataType::Native(D::U32) => 4,
+        DataType::Native(D::I32) => -4,
+        DataType::Native(D::U64) => 8,
+        DataType::Native(D::I64) => -8,
+        // Strings and serialized data are passed as pointers.
+        DataType::Native(D::String) | DataType::Serializable(_) => 8,
+        DataType::UniqueId => 8,
+    };
+    // Register operands are written in AT&T syntax on x86_64.
+    let prefix = if arch == "x86_64" { "%" } else { "" };
+    format!("{}@{}{}", size, prefix, reg)
+}
+
+// Construct the `.note.stapsdt` ELF note describing a probe, whose address is the `990` label.
+//
+// If `semaphore` is true, the note refers to the probe's semaphore via the `semaphore` operand of
+// the enclosing `asm!` invocation. Otherwise the semaphore address is zero, indicating that there
+// is no semaphore.
+//
+// The arguments are described in the registers in which `construct_probe_args` passes them. If
+// those cannot be assigned, that has already generated a compile error, and none are described.
+//
+// The note refers to the `_.stapsdt.base` symbol, which tracers use to adjust the probe address if
+// the binary has been prelinked. The symbol is defined once per object, in its own section. That
+// section is marked as retained (the `R` flag), since nothing in an allocated section refers to it,
+// and the linker would otherwise garbage-collect it.
+pub(crate) fn emit_probe_note(
+    prov: &str,
+    probe: &str,
+    types: &[DataType],
+    semaphore: bool,
+    config: &crate::CompileProvidersConfig,
+) -> String {
+    let arch = config.target_arch();
+    let regs = common::argument_regs(probe, types.len(), config).unwrap_or_default();
+    let arguments = types
+        .iter()
+        .zip(regs)
+        .map(|(typ, reg)| argument_descriptor(typ, reg, arch))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(
+        r#"
+                    .pushsection .note.stapsdt,"?","note"
+                    .balign 4
+                    .4byte 992f-991f, 994f-993f, 3
+            991:    .asciz "stapsdt"
+            992:    .balign 4
+            993:    .8byte 990b
+                    .8byte _.stapsdt.base
+                    .8byte {semaphore}
+                    .asciz "{prov}"
+                    .asciz "{probe}"
+                    .asciz "{arguments}"
+            994:    .balign 4
+                    .popsection
+                    .ifndef _.stapsdt.base
+                    .pushsection .stapsdt.base,"aGR","progbits",.stapsdt.base,comdat
+                    .weak _.stapsdt.base
+                    .hidden _.stapsdt.base
+            _.stapsdt.base:
+                    .space 1
+                    .size _.stapsdt.base, 1
+                    .popsection
+                    .endif
+        "#,
+        semaphore = if semaphore { "{semaphore}" } else { "0" },
+        prov = prov,
+        probe = probe,
+        arguments = arguments,
+    )
+}
+
+pub fn register_probes() -> Result<(), crate::Error> {
+    // Tracers read the SDT notes directly from the binary, so there's nothing to register.
+    Ok(())
+}
+
+pub fn register_section(_: &[u8]) -> Result<(), crate::Error> {
+    Ok(())
+}
+
+pub fn register_probes_scoped() -> Result<Vec<i32>, crate::Error> {
+    register_probes().map(|_| Vec::new())
+}
+
+pub fn dof_section_size() -> Result<Option<usize>, crate::Error> {
+    Ok(None)
+}
+
+pub fn deregister_probes(_: i32) -> Result<(), crate::Error> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_argument_descriptor() {
+        let cases = [
+            (DataType::Native(dtrace_parser::DataType::U8), "1"),
+            (DataType::Native(dtrace_parser::DataType::I16), "-2"),
+            (DataType::Native(dtrace_parser::DataType::U32), "4"),
+            (DataType::Native(dtrace_parser::DataType::I64), "-8"),
+            (DataType::Native(dtrace_parser::DataType::String), "8"),
+            (DataType::UniqueId, "8"),
+            (DataType::Serializable(syn::parse_str("Foo").unwrap()), "8"),
+        ];
+        for (typ, size) in cases.iter() {
+            assert_eq!(
+                argument_descriptor(typ, "reg", "x86_64"),
+                format!("{}@%reg", size)
+            );
+            assert_eq!(
+                argument_descriptor(typ, "reg", "aarch64"),
+                format!("{}@reg", size)
+            );
+        }
+    }
+
+    #[test]
+    fn test_emit_probe_note() {
+        let types = [
+            DataType::Native(dtrace_parser::DataType::U8),
+            DataType::Native(dtrace_parser::DataType::String),
+        ];
+        let config = crate::CompileProvidersConfig {
+            target_arch: Some(String::from("x86_64")),
+            ..Default::default()
+        };
+        let note = emit_probe_note("provider", "probe", &types, false, &config);
+        let lines = note.lines().map(str::trim).collect::<Vec<_>>();
+        assert!(lines.contains(&".pushsection .note.stapsdt,\"?\",\"note\""));
+        assert!(lines.contains(&"993:    .8byte 990b"));
+        assert!(
+            lines.contains(&".8byte 0"),
+            "Expected a zero semaphore address"
+        );
+        assert!(lines.contains(&".asciz \"provider\""));
+        assert!(lines.contains(&".asciz \"probe\""));
+
+        assert!(lines.contains(&".asciz \"1@%rdi 8@%rsi\""));
+
+        // Arguments are described in the registers of the target, not the host.
+        let config = crate::CompileProvidersConfig {
+            target_arch: Some(String::from("aarch64")),
+            ..Default::default()
+        };
+        let note = emit_probe_note("provider", "probe", &types, false, &config);
+        assert!(note
+            .lines()
+            .any(|line| line.trim() == ".asciz \"1@x0 8@x1\""));
+
+        // Pinned arguments are described in the registers to which they're pinned.
+        let mut config = crate::CompileProvidersConfig {
+            target_arch: Some(String::from("x86_64")),
+            ..Default::default()
+        };
+        config.register_pins.insert(
+            String::from("probe"),
+            vec![String::from("r12"), String::from("rax")],
+        );
+        let note = emit_probe_note("provider", "probe", &types, false, &config);
+        assert!(note
+            .lines()
+            .any(|line| line.trim() == ".asciz \"1@%r12 8@%rax\""));
+
+        // Braces are interpreted by `asm!` as operand placeholders.
+        assert!(!note.contains('{') && !note.contains('}'));
+    }
+
+    #[test]
+    fn test_emit_probe_note_semaphore() {
+        let note = emit_probe_note(
+            "provider",
+            "probe",
+            &[],
+            true,
+            &crate::CompileProvidersConfig::default(),
+        );
+        let lines = note.lines().map(str::trim).collect::<Vec<_>>();
+        assert!(lines.contains(&".8byte {semaphore}"));
+        assert!(!lines.contains(&".8byte 0"));
+    }
+
+    #[test]
+    fn test_compile_probe_semaphore() {
+        let provider = Provider {
+            name: String::from("foo"),
+            probes: vec![Probe {
+                name: String::from("bar"),
+                types: vec![],
+                arg_names: vec![],
+                docs: None,
+            }],
+            use_statements: vec![],
+        };
+        let config = crate::CompileProvidersConfig {
+            semaphore: true,
+            ..Default::default()
+        };
+        let output = compile_probe(&provider, &provider.probes[0], &config).to_string();
+        assert!(output.contains("static mut __usdt_semaphore_foo_bar : u16 = 0"));
+        assert!(output.contains("semaphore = sym $ crate :: foo :: __usdt_semaphore_foo_bar"));
+        assert!(output.contains("if is_enabled != 0"));
+
+        let output = compile_probe(&provider, &provider.probes[0], &Default::default());
+        assert!(!output.to_string().contains("semaphore"));
+    }
+
+    #[test]
+    fn test_compile_probe_return_status() {
+        let provider = Provider {
+            name: String::from("foo"),
+            probes: vec![Probe {
+                name: String::from("bar"),
+                types: vec![],
+                arg_names: vec![],
+                docs: None,
+            }],
+            use_statements: vec![],
+        };
+        let config = crate::CompileProvidersConfig {
+            semaphore: true,
+            return_status: true,
+            ..Default::default()
+        };
+        let output = compile_probe(&provider, &provider.probes[0], &config).to_string();
+        assert!(output.contains("if is_enabled != 0"));
+        assert!(output.contains("else { false }"));
+
+        // Without a semaphore, the probe always fires, unless it's given a predicate or probes are
+        // disabled globally.
+        let config = crate::CompileProvidersConfig {
+            return_status: true,
+            ..Default::default()
+        };
+        let output = compile_probe(&provider, &provider.probes[0], &config).to_string();
+        let general = &output[output.rfind("($ args_lambda : expr").unwrap()..];
+        assert!(general.contains("true"));
+        let globally_enabled = general.find("probes_globally_enabled").unwrap();
+        assert_eq!(general.matches("false").count(), 1);
+        assert!(general.find("else { false }").unwrap() > globally_enabled);
+    }
+
+    #[test]
+    fn test_compile_provider_source_contains_asm() {
+        let source = "provider foo { probe bar(uint8_t); probe baz(char*, int64_t); };";
+        let config = crate::CompileProvidersConfig::default();
+        let output = crate::compile_provider_source(source, &config)
+            .unwrap()
+            .to_string();
+        assert_eq!(output.matches("macro_rules !").count(), 2);
+        assert!(output.matches("asm !").count() >= 2);
+        assert!(output.contains("990:   nop"));
+        assert!(output.contains(".note.stapsdt"));
+    }
+
+    #[test]
+    fn test_compile_probe_zero_args() {
+        let provider = Provider {
+            name: String::from("foo"),
+            probes: vec![Probe {
+                name: String::from("bar"),
+                types: vec![],
+                arg_names: vec![],
+                docs: None,
+            }],
+            use_statements: vec![],
+        };
+        let output = compile_probe(&provider, &provider.probes[0], &Default::default()).to_string();
+        assert!(output.contains("990:   nop"));
+        assert!(!output.contains("in ("));
+        assert!(!output.contains("let args"));
+    }
+
+    #[test]
+    fn test_compile_probe_signal_safe() {
+        let provider = Provider {
+            name: String::from("foo"),
+            probes: vec![Probe {
+                name: String::from("bar"),
+                types: vec![
+                    DataType::Native(dtrace_parser::DataType::U8),
+                    DataType::Native(dtrace_parser::DataType::I64),
+                ],
+                arg_names: vec![],
+                docs: None,
+            }],
+            use_statements: vec![],
+        };
+        let config = crate::CompileProvidersConfig {
+            auto_register: true,
+            semaphore: true,
+            ..Default::default()
+        };
+        let output = compile_probe(&provider, &provider.probes[0], &config).to_string();
+        let start = output
+            .find("(signal_safe $ args_lambda : expr)")
+            .expect("Expected a signal-safe arm");
+        let end = start + output[start..].find("($ tree : tt)").unwrap();
+        let arm = &output[start..end];
+        assert!(arm.contains("990:   nop"));
+        for unsafe_call in &[
+            "register_probes_once",
+            "concat",
+            "format !",
+            "to_json",
+            "lock",
+        ] {
+            assert!(
+                !arm.contains(unsafe_call),
+                "Signal-safe arm contains \"{}\"",
+                unsafe_call
+            );
+        }
+        // The general arm registers the probes, which takes a lock.
+        assert!(output[end..].contains("register_probes_once"));
+    }
+}
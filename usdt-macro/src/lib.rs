@@ -2,7 +2,7 @@
 // Copyright 2021 Oxide Computer Company
 
 use std::iter::FromIterator;
-use std::{fs, path::Path};
+use std::path::Path;
 
 use quote::quote;
 use syn::{parse_macro_input, Lit};
@@ -96,10 +96,12 @@ pub fn dtrace_provider(item: proc_macro::TokenStream) -> proc_macro::TokenStream
         );
 
         let path = dir.join(&filename);
-        fs::read_to_string(path).expect(&format!(
-            "Could not read D source file \"{}\" in {:?}",
-            &filename, dir,
-        ))
+        usdt_impl::read_provider_file(&path)
+            .expect(&format!(
+                "Could not read D source file \"{}\" in {:?}",
+                &filename, dir,
+            ))
+            .0
     } else {
         filename.clone()
     };
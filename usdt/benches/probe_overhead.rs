@@ -0,0 +1,50 @@
+//! Benchmark the cost of firing probes that no tracer is attached to.
+//!
+//! The `disabled` provider checks whether each probe is enabled, which is the cost of an
+//! idle probe in production. The `enabled` provider is generated with `always_fire`, so its
+//! arguments are constructed and passed to the probe on every call, which bounds the cost of the
+//! argument-passing code generated for each probe.
+//!
+//! This measures the probes of whichever backend usdt is built with: DTrace probes on macOS and
+//! illumos, or SystemTap SDT probes on Linux with `--features stapsdt`. Otherwise, Linux uses the
+//! no-op backend, whose probes neither check whether they're enabled nor evaluate their
+//! arguments, so the results there measure nothing of interest.
+
+// Copyright 2021 Oxide Computer Company
+
+#![cfg_attr(feature = "asm", feature(asm))]
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+#[usdt::provider]
+mod disabled {
+    fn fire(_: u64, _: &str) {}
+}
+
+#[usdt::provider(always_fire = true)]
+mod enabled {
+    fn fire(_: u64, _: &str) {}
+}
+
+fn probe_overhead(c: &mut Criterion) {
+    usdt::register_probes().unwrap();
+    let mut group = c.benchmark_group("probe");
+    group.bench_function("disabled", |b| {
+        let mut count = 0u64;
+        b.iter(|| {
+            count += 1;
+            disabled::fire!(|| (black_box(count), black_box("bench")));
+        })
+    });
+    group.bench_function("enabled", |b| {
+        let mut count = 0u64;
+        b.iter(|| {
+            count += 1;
+            enabled::fire!(|| (black_box(count), black_box("bench")));
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, probe_overhead);
+criterion_main!(benches);
@@ -50,6 +50,10 @@
 //! `feature` directive and the invocation of `dtrace_provider` **should both be at the crate
 //! root**, i.e., `src/lib.rs` or `src/main.rs`.
 //!
+//! Definitions may be split across several files with `#include "file.d"` directives, which are
+//! resolved relative to the including file before the provider is parsed, the same way `dtrace`
+//! itself would through the C preprocessor. Only quoted includes are resolved.
+//!
 //! One may then call the `start` probe via:
 //!
 //! ```ignore
@@ -137,12 +141,39 @@
 //! will be interpolated with the actual provider and probe names. So given a provider `bar` and `
 //! probe `baz`, the macro would be, in full: `foo::bar::probe_baz!`.
 //!
+//! Alongside the probe macros, each provider's module contains a function `enabled_probes()`,
+//! which returns the names of the provider's probes that are currently enabled by a DTrace
+//! consumer. This is not generated for `no_std` crates.
+//!
+//! ## Re-exporting probes
+//!
+//! The generated modules and probe macros are normally private to the crate defining them. With
+//! the `export` option, as in `#[usdt::provider(export = true)]` or [`Builder::export`], they are
+//! public, and a library may expose a provider to its users with a single re-export:
+//!
+//! ```ignore
+//! pub use crate::my_provider;
+//! ```
+//!
+//! Users may then fire the probes as `my_lib::my_provider::my_probe!(|| ...)`. Each probe macro
+//! is exported from the library under a hidden name, and re-exported from the provider's module,
+//! so the module and its macros are always re-exported together. Probes are still registered by
+//! the library's [`register_probes`]. The generated code refers to this crate as `::usdt`, so a
+//! crate firing the re-exported probes must also depend on `usdt`. `use crate::...` statements in
+//! a provider defined with the attribute macro are resolved relative to the library.
+//!
 //! Examples
 //! --------
 //!
 //! See the [probe_test_macro], [probe_test_build], and [probe_test_attr] crates for detailed working
 //! examples showing how the probes may be defined, included, and used.
 //!
+//! A provider file may be checked in a unit test with [`check_provider_source`], as in
+//! `usdt::check_provider_source(include_str!("provider.d")).unwrap()`, which compiles it exactly
+//! as the macro and build.rs versions would, and checks that the result is valid Rust code. A
+//! provider file on disk may be checked in the same way, with the configuration of a build.rs
+//! script, with [`Builder::validate`] or [`validate_providers`], which write no files.
+//!
 //! Probe arguments
 //! ---------------
 //!
@@ -154,6 +185,38 @@
 //! expensive to construct. However, this cost will only be incurred if the probe is actually
 //! enabled.
 //!
+//! Probes whose arguments are all integers may instead be passed a slice of already-prepared
+//! values, as in `my_provider::my_probe!(raw &[a, b])`. Each `u64` is cast to the type of the
+//! corresponding argument, exactly as if passed from a closure. In debug builds, the number of
+//! values is checked against the number of arguments.
+//!
+//! A probe may be fired only when some condition also holds, by prefixing the closure with `if`
+//! and a predicate, as in `my_provider::my_probe!(if len > 1024, || (id, len))`, or with
+//! [`probe_if`]. The predicate is only evaluated if the probe is enabled, and the probe fires only
+//! if the predicate is true.
+//!
+//! Probes whose arguments are all integers may also be fired from a signal handler, by prefixing
+//! the closure with `signal_safe`, as in `my_provider::my_probe!(signal_safe || (a, b))`. This
+//! fires the probe without allocating or taking any locks: probes are never registered
+//! automatically, even with the `auto_register` option, and it is a compile error if the probe is
+//! mirrored to `tracing`. The closure and any `IntoProbeArg` implementations it relies on must
+//! themselves be async-signal-safe. Probes generated with the `mock-probes` feature record each
+//! firing behind a lock, and so are never safe to fire from a signal handler.
+//!
+//! Arguments may also be passed by name, in any order, as in
+//! `my_provider::my_probe!(bytes = b, request_id = a)`. The names are those given to the
+//! arguments in the D provider file, e.g., `probe my_probe(uint64_t request_id, uint64_t bytes)`,
+//! or in the function signature of a probe defined in Rust. The values are evaluated only if the
+//! probe is enabled, exactly as if returned from a closure. A misspelled or missing name is a
+//! compile error, as is passing named arguments to a probe whose arguments are not all named.
+//!
+//! A probe macro evaluates to `()`. With the `return_status` option, it instead evaluates to a
+//! `bool`, which is true if the probe was enabled and so fired, as in
+//! `if my_provider::my_probe!(|| a) { ... }`. This may be used to do other work, such as logging,
+//! only while the probe is being traced. Probes without an is-enabled check, such as SDT probes
+//! without a semaphore, always evaluate to true. Probes built without the `asm` feature always
+//! evaluate to false.
+//!
 //! Data types
 //! ----------
 //!
@@ -165,11 +228,36 @@
 //!
 //! - `(u?)int(8|16|32|64)_t`
 //! - `char *`
+//! - `char` (Only when defining probes in Rust, passed as its code point in a `uint32_t`)
 //! - `T: Clone + serde::Serialize` (Only when defining probes in Rust)
 //!
+//! Any other type in a probe defined in Rust, including primitives such as `u128`, `f64` or `bool`,
+//! is treated as serializable and passed to the probe as JSON. With
+//! `#[usdt::provider(strict = true)]`, an argument of such a primitive type, or of a zero-sized
+//! type such as `()`, is instead a compile error naming the probe and type.
+//!
 //! Currently, up to six (6) arguments are supported, though this limitation may be lifted in the
 //! future.
 //!
+//! Other types may be passed to integer arguments by implementing [`IntoProbeArg`]. For fieldless
+//! enums with an integer representation, such as `#[repr(u32)]`, this can be derived with
+//! `#[derive(usdt::ProbeArg)]`, which passes each variant's discriminant to the probe. A
+//! `std::time::Duration` may be passed to a `uint64_t` argument in nanoseconds, or in microseconds
+//! or milliseconds by wrapping it in [`Micros`] or [`Millis`]. The address of the site firing a
+//! probe may be passed to a `uint64_t` argument with [`caller_addr!`], and symbolized in DTrace
+//! with `usym()`. A `#[repr(C)]` struct of fixed-width integers, or fixed-size arrays of them,
+//! may be passed by address to a `uint64_t` argument with `#[derive(usdt::ProbeArgs)]`, which also
+//! declares the struct in D. See [`ProbeArgs`].
+//!
+//! String arguments accept anything implementing `AsRef<str>`, which is copied into a
+//! null-terminated buffer before the probe fires, or `AsRef<CStr>`, such as `&CStr` or `CString`,
+//! which is already null-terminated and is passed without copying. See [`ProbeStr`]. Any value
+//! implementing `Debug` or `Display` may be passed to a string argument by wrapping it in
+//! [`DebugStr`] or [`DisplayStr`], and is formatted only if the probe is enabled. Strings longer
+//! than [`DEFAULT_MAX_STRING_LEN`] bytes are truncated before being passed to the probe, as DTrace
+//! would truncate them itself. The limit may be set with `max_string_len`, e.g.,
+//! `#[usdt::provider(max_string_len = 1024)]` or [`Builder::max_string_len`].
+//!
 //! > **Note**: Serializable types must implement the `Clone` trait. It's important to note that
 //! this may almost always be derived, and, more importantly, that the data in probes will _never
 //! actually be cloned_, even when probes are enabled. The trait bound `Clone` is required to
@@ -229,6 +317,71 @@
 //!
 //! The `asm` feature is a default of the `usdt` crate.
 //!
+//! All probes in a process may be disabled at runtime with [`set_probes_enabled`], e.g., to
+//! mitigate a probe which causes problems in production. While disabled, probes behave as if
+//! DTrace had not enabled them, at the cost of one relaxed atomic load per probe site.
+//!
+//! ### Pinning argument registers
+//!
+//! Probe arguments are passed in the registers of the platform's C calling convention, which is
+//! where DTrace reads `arg0`, `arg1`, and so on. For interoperating with existing scripts which
+//! read specific registers directly, e.g., with `uregs`, the leading arguments of a probe may
+//! instead be pinned to other registers, with the `register_pins` option or
+//! [`Builder::register_pins`]:
+//!
+//! ```ignore
+//! #[usdt::provider(register_pins = { work = ["r12", "rax"] })]
+//! mod my_provider {
+//!     fn work(id: u64, len: u64, flags: u8) {}
+//! }
+//! ```
+//!
+//! Here `id` is passed in `r12`, `len` in `rax`, and `flags` in its usual register, `rdx`. Pins
+//! count any sequence number or thread ID arguments. Pinning two arguments to the same register,
+//! or an argument to a register which is reserved or clobbered by the probe, such as the stack
+//! pointer, is a compile error. Only SystemTap SDT notes describe pinned arguments, so DTrace's
+//! `arg0` and friends no longer refer to pinned arguments: this is for experts matching an
+//! existing ABI.
+//!
+//! Testing probes
+//! --------------
+//!
+//! It can be useful to check that instrumented code fires the expected probes, without attaching
+//! DTrace. With the `mock-probes` feature, probe macros record each firing of a probe rather than
+//! calling into DTrace, and the probes fired on the current thread can be retrieved with
+//! [`mock::take_fired_probes`]. Note that mock probes are always enabled, so the argument closure
+//! is always evaluated. This feature is intended for tests, and would usually be enabled only as a
+//! feature of a dev-dependency on `usdt`.
+//!
+//! ```ignore
+//! #[usdt::provider]
+//! mod my_provider {
+//!     fn start_work(_: u8) {}
+//! }
+//!
+//! my_provider::start_work!(|| 1);
+//! let fired = usdt::mock::take_fired_probes();
+//! assert_eq!(fired[0].probe, "start_work");
+//! assert_eq!(fired[0].args, vec!["1"]);
+//! ```
+//!
+//! SystemTap-compatible probes on Linux
+//! ------------------------------------
+//!
+//! DTrace isn't generally available on Linux, so the no-op implementation is used there by default.
+//! With the `stapsdt` feature, probes are instead emitted as SystemTap-compatible SDT notes, in the
+//! `.note.stapsdt` section of the binary. These are understood by Linux tracing tools such as
+//! `bpftrace` and `perf`, for example:
+//!
+//! ```bash
+//! $ bpftrace -e 'usdt:./target/debug/my-app:my_provider:start_work { printf("%d\n", arg0); }'
+//! ```
+//!
+//! No registration is required in this case, and [`register_probes`] does nothing. Note that SDT
+//! probes do not have an is-enabled check by default, so the argument closure is always evaluated.
+//! Probes may instead be guarded by a _semaphore_, which tracers increment when they attach, by
+//! passing `semaphore = true` to the attribute macro or via [`Builder::semaphore`].
+//!
 //! Selecting the no-op implementation
 //! ----------------------------------
 //!
@@ -258,40 +411,85 @@
 //! [serde]: https://serde.rs
 // Copyright 2021 Oxide Computer Company
 
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
-use std::{env, fs};
+use std::{env, fs, io};
 
-pub use usdt_attr_macro::provider;
+pub use usdt_attr_macro::{provider, ProbeArg, ProbeArgs};
+#[cfg(feature = "mock-probes")]
+pub use usdt_impl::mock;
 #[cfg(any(feature = "des"))]
 pub use usdt_impl::record;
+pub use usdt_impl::{
+    check_provider_source, dof_section_size, find_probe, probes_globally_enabled,
+    register_probes_scoped, set_probes_enabled, DebugStr, DisplayStr, Error, IntoProbeArg,
+    Ipv6Octets, Micros, Millis, ProbeArgs, ProbeInfo, ProbeRegistration, ProbeStr, ProviderInfo,
+    UniqueId, DEFAULT_MAX_STRING_LEN,
+};
 #[doc(hidden)]
-pub use usdt_impl::to_json;
-pub use usdt_impl::{Error, UniqueId};
+pub use usdt_impl::{
+    next_sequence, register_probes_once, register_section, thread_id, to_json, truncate_probe_str,
+    truncate_probe_string,
+};
+#[doc(hidden)]
+pub use usdt_impl::{FormattedStr, NulTerminatedStr, Utf8Str};
 pub use usdt_macro::dtrace_provider;
 
+#[cfg(feature = "tracing")]
+#[doc(hidden)]
+pub use tracing;
+
 /// A simple struct used to build DTrace probes into Rust code in a build.rs script.
 #[derive(Debug)]
 pub struct Builder {
-    source_file: PathBuf,
+    source: Source,
     out_file: PathBuf,
     config: usdt_impl::CompileProvidersConfig,
+    manifest: bool,
 }
 
 impl Builder {
     /// Construct a new builder from a path to a D provider definition file.
+    ///
+    /// The default output file is named for the stem of the provider file, with any characters
+    /// other than ASCII letters and digits replaced by `_`. For example, `my.provider.d` generates
+    /// `my_provider.rs`.
+    ///
+    /// Any `#include "file"` directives in the provider file are resolved relative to its
+    /// directory.
     pub fn new<P: AsRef<Path>>(file: P) -> Self {
         let source_file = file.as_ref().to_path_buf();
-        let mut out_file = source_file.clone();
-        out_file.set_extension("rs");
+        let stem = source_file
+            .file_stem()
+            .expect("Could not extract filename")
+            .to_string_lossy();
+        let out_file = PathBuf::from(format!("{}.rs", sanitize_file_stem(&stem)));
         Builder {
-            source_file,
+            source: Source::File(source_file),
             out_file,
             config: usdt_impl::CompileProvidersConfig::default(),
+            manifest: false,
         }
     }
 
-    /// Set the output filename of the generated Rust code. The default has the same stem as the
-    /// provider file, with the `".rs"` extension.
+    /// Construct a new builder from the text of a D provider definition.
+    ///
+    /// This allows build scripts to embed provider definitions shared between crates, e.g., with
+    /// `include_str!`. The default output file is named for `name`, sanitized as for a file stem in
+    /// [`Builder::new`].
+    pub fn from_source(name: &str, source: &str) -> Self {
+        let out_file = PathBuf::from(format!("{}.rs", sanitize_file_stem(name)));
+        Builder {
+            source: Source::Text(source.to_string()),
+            out_file,
+            config: usdt_impl::CompileProvidersConfig::default(),
+            manifest: false,
+        }
+    }
+
+    /// Set the output filename of the generated Rust code. The default has the sanitized stem of
+    /// the provider file, with the `".rs"` extension.
     pub fn out_file<P: AsRef<Path>>(mut self, file: P) -> Self {
         self.out_file = file.as_ref().to_path_buf();
         self.out_file.set_extension("rs");
@@ -314,26 +512,406 @@ impl Builder {
     /// `"{provider}"`.
     ///
     /// For example, given the string `"some::mod::{provider}"`, for a provider named `foo`, the
-    /// generated probe macros will be available as `some::mod::provider::{probe}`.
+    /// generated probe macros will be available as `some::mod::provider::{probe}`. An empty string
+    /// places the macros directly in the including module. If this or the format of the macro names
+    /// omits the provider name, then probes of the same name in different providers generate the
+    /// same macro, which is an error.
     pub fn probe_path(mut self, format: &str) -> Self {
         self.config.probe_path = Some(format.to_string());
         self
     }
 
-    /// Generate the Rust code from the D provider file, writing the result to the output file.
-    pub fn build(self) -> Result<(), Error> {
-        let source = fs::read_to_string(self.source_file)?;
-        let tokens = usdt_impl::compile_provider_source(&source, &self.config)?;
-        let mut out_file = Path::new(&env::var("OUT_DIR")?).to_path_buf();
-        out_file.push(
-            &self
-                .out_file
-                .file_name()
-                .expect("Could not extract filename"),
+    /// Set whether probes should fire unconditionally.
+    ///
+    /// By default, each probe first checks whether it has been enabled by DTrace, and only evaluates
+    /// its arguments and fires if so. Setting this to `true` skips that check, so that the probe's
+    /// argument closure is always evaluated and the probe always fires. This may be useful for probes
+    /// which are expected to be enabled all the time, at the cost of always paying for the
+    /// arguments. On macOS, the is-enabled symbol of each probe is also omitted.
+    pub fn always_fire(mut self, always_fire: bool) -> Self {
+        self.config.always_fire = always_fire;
+        self
+    }
+
+    /// Set whether probes are guarded by a semaphore.
+    ///
+    /// When enabled, each probe has a semaphore which tracers increment when they attach to the
+    /// probe, and the probe fires only when the semaphore is nonzero. This is only supported with
+    /// the `stapsdt` feature on Linux; DTrace has its own mechanism for determining whether a probe
+    /// is enabled.
+    pub fn semaphore(mut self, semaphore: bool) -> Self {
+        self.config.semaphore = semaphore;
+        self
+    }
+
+    /// Also emit a [`tracing`](https://docs.rs/tracing) event at the given level whenever a probe
+    /// fires.
+    ///
+    /// The level is one of `"trace"`, `"debug"`, `"info"`, `"warn"`, or `"error"`. Each event has
+    /// the target `provider::probe`, and records the probe's arguments as the fields `arg0`,
+    /// `arg1`, and so on. This requires the `tracing` feature of this crate.
+    ///
+    /// Events are emitted only when the probe is enabled. When the no-op implementation is used,
+    /// for example on Linux, the arguments are always evaluated and the event is always emitted,
+    /// which makes the instrumentation visible in logs on systems without DTrace.
+    pub fn mirror_tracing(mut self, level: &str) -> Self {
+        self.config.mirror_tracing = Some(level.to_string());
+        self
+    }
+
+    /// Set whether probes register themselves with DTrace.
+    ///
+    /// When enabled, the first time any probe is reached, all probes in the application are
+    /// registered as if by a call to [`register_probes`], so that an explicit call is not required.
+    /// This adds a small check to every probe site, and a probe first reached from within a signal
+    /// handler may deadlock if another thread is registering probes at the same time. Any error
+    /// during registration is ignored.
+    pub fn auto_register(mut self, auto_register: bool) -> Self {
+        self.config.auto_register = auto_register;
+        self
+    }
+
+    /// Set the name of the linker section into which the provider's probe records are emitted.
+    ///
+    /// The format may include the token `{provider}`, which is substituted with the name of each
+    /// provider. By default, all probes are emitted into a single section, and are registered
+    /// together by [`register_probes`]. Providers with a custom section are _not_ registered by
+    /// that function. Instead, a function `register_provider()` is generated alongside the
+    /// provider's probe macros, which registers only the probes in that provider's section.
+    ///
+    /// The section name must be a valid C identifier, so that the linker defines symbols denoting
+    /// the start and end of the section.
+    ///
+    /// If this is not called, the format may instead be given in the environment variable
+    /// `USDT_SECTION_NAME` when the build script is run, which is useful to segregate or inspect
+    /// the probe records of a build without modifying it.
+    pub fn section_name(mut self, format: &str) -> Self {
+        self.config.section_name = Some(format.to_string());
+        self
+    }
+
+    /// Set whether each probe macro returns whether the probe fired.
+    ///
+    /// If enabled, probe macros evaluate to a `bool`, which is true if the probe was enabled and
+    /// fired, rather than to `()`.
+    pub fn return_status(mut self, return_status: bool) -> Self {
+        self.config.return_status = return_status;
+        self
+    }
+
+    /// Set the maximum length in bytes of each string argument.
+    ///
+    /// Longer strings are truncated, without splitting a UTF-8 character, before being passed to
+    /// the probe. This defaults to [`DEFAULT_MAX_STRING_LEN`], which is the default value of
+    /// DTrace's `strsize` option, beyond which DTrace itself truncates strings. If `strsize` is
+    /// raised, this should be raised to match.
+    pub fn max_string_len(mut self, max_string_len: usize) -> Self {
+        self.config.max_string_len = Some(max_string_len);
+        self
+    }
+
+    /// Set the documentation for each generated provider module.
+    ///
+    /// The text may include the token `{provider}`, which is substituted with the name of each
+    /// provider, and may span multiple lines. Each probe macro is documented separately, with the
+    /// text of any `/** ... */` comment immediately preceding the probe in the provider file.
+    pub fn module_docs(mut self, docs: &str) -> Self {
+        self.config.module_docs = Some(docs.to_string());
+        self
+    }
+
+    /// Set whether the generated code must be usable in a `#![no_std]` crate.
+    ///
+    /// When enabled, probes may only take integer arguments and [`UniqueId`]s, since strings and
+    /// serializable types are copied into an allocated buffer before firing the probe. Any other
+    /// argument type results in a compile error. The macOS implementation still refers to `std`
+    /// for the C types of probe arguments, and so does not support this option.
+    pub fn no_std(mut self, no_std: bool) -> Self {
+        self.config.no_std = no_std;
+        self
+    }
+
+    /// Set whether the provider's modules and probe macros may be used by other crates.
+    ///
+    /// When enabled, the generated modules are public, and each probe macro is exported, so that
+    /// a library may re-export a provider with a single `pub use`. See the [crate
+    /// documentation](crate#re-exporting-probes) for details.
+    pub fn export(mut self, export: bool) -> Self {
+        self.config.export = export;
+        self
+    }
+
+    /// Set whether probes are passed a sequence number.
+    ///
+    /// When enabled, each probe takes an additional first argument, a `uint64_t` which is
+    /// incremented each time any probe in the program fires. DTrace scripts may use this to order
+    /// probes fired from different threads, without relying on timestamps. The closure passed to
+    /// each probe macro still returns only the probe's declared arguments.
+    pub fn with_sequence(mut self, with_sequence: bool) -> Self {
+        self.config.with_sequence = with_sequence;
+        self
+    }
+
+    /// Set whether each probe is passed the ID of the thread firing it.
+    ///
+    /// If enabled, each probe takes an additional leading argument, the `u64` ID of the firing
+    /// thread, as used by the OS, e.g., `gettid()` on Linux. DTrace already provides this as `tid`,
+    /// but passing it as an argument makes it available to consumers which only see the arguments,
+    /// e.g., for correlating probes with logs. If sequence numbers are enabled, the thread ID
+    /// follows the sequence number. The ID is only read if the probe fires.
+    pub fn with_tid(mut self, with_tid: bool) -> Self {
+        self.config.with_tid = with_tid;
+        self
+    }
+
+    /// Set the path to a header previously generated by `dtrace -h` for the provider file.
+    ///
+    /// On macOS, the symbols which the linker uses to construct each probe are read from a header
+    /// generated by running `dtrace -h`. If this is set, that header is read from the given file
+    /// instead, so that the crate may be built on a host without DTrace. The header must have been
+    /// generated from the same provider file. A relative path is relative to the package root. On
+    /// other systems, this has no effect.
+    pub fn dtrace_header<P: AsRef<Path>>(mut self, file: P) -> Self {
+        self.config.dtrace_header = Some(file.as_ref().to_string_lossy().into_owned());
+        self
+    }
+
+    /// Set whether to compute probe symbols without running DTrace.
+    ///
+    /// On macOS, running `dtrace -h` to learn the symbols which the linker uses to construct each
+    /// probe accounts for much of the time taken to build the providers. If this is enabled, the
+    /// symbols are instead computed directly from the provider file. This is only possible for
+    /// providers with the default stability attributes, and whose provider and probe names don't
+    /// contain `__`; `dtrace` is still run for any others. A header set with
+    /// [`Builder::dtrace_header`] takes precedence. On other systems, this has no effect.
+    pub fn compute_symbols(mut self, compute_symbols: bool) -> Self {
+        self.config.compute_symbols = compute_symbols;
+        self
+    }
+
+    /// Set the architecture for which probes are generated, as in `CARGO_CFG_TARGET_ARCH`.
+    ///
+    /// This determines the registers in which probe arguments are passed. [`Builder::build`] uses
+    /// the architecture Cargo is building for, unless this is set. [`Builder::generate`] uses the
+    /// architecture for which this crate was compiled, unless this is set.
+    pub fn target_arch(mut self, arch: &str) -> Self {
+        self.config.target_arch = Some(arch.to_string());
+        self
+    }
+
+    /// Pin the leading arguments of the named probe to the given registers, in order.
+    ///
+    /// This overrides the default ABI registers for those arguments, and is intended only for
+    /// matching scripts which read specific registers directly. See the [crate
+    /// documentation](crate#pinning-argument-registers) for details.
+    pub fn register_pins(mut self, probe: &str, registers: &[&str]) -> Self {
+        self.config.register_pins.insert(
+            probe.to_string(),
+            registers.iter().map(|reg| reg.to_string()).collect(),
         );
-        fs::write(out_file, tokens.to_string().as_bytes())?;
+        self
+    }
+
+    /// Set whether to also write a JSON manifest describing each provider.
+    ///
+    /// If enabled, a file `<provider>.probes.json` is written to `OUT_DIR` for each provider,
+    /// listing the provider's probes and their argument types. The path to each manifest is made
+    /// available to the crate at compile-time in the environment variable
+    /// `USDT_MANIFEST_<provider>`, e.g., via `env!("USDT_MANIFEST_my_provider")`.
+    pub fn manifest(mut self, manifest: bool) -> Self {
+        self.manifest = manifest;
+        self
+    }
+
+    /// Generate the Rust code from the D provider file, writing the result to the output file.
+    ///
+    /// Probes are generated for the architecture Cargo is building for, as given by
+    /// `CARGO_CFG_TARGET_ARCH`, so that cross-compiling passes probe arguments in the target's
+    /// registers rather than those of the host.
+    ///
+    /// Cargo is instructed to rerun the build script when the provider file or any file it
+    /// includes changes, and when `USDT_SECTION_NAME` changes if no section name is set. Note that
+    /// once a build script prints any `rerun-if` directive, Cargo no longer reruns it whenever any
+    /// file in the package changes, so a build script doing other work should print directives for
+    /// its own inputs.
+    pub fn build(mut self) -> Result<(), Error> {
+        if self.config.target_arch.is_none() {
+            self.config.target_arch = env::var("CARGO_CFG_TARGET_ARCH").ok();
+        }
+        if self.config.section_name.is_none() {
+            println!("cargo:rerun-if-env-changed=USDT_SECTION_NAME");
+            self.config.section_name = env::var("USDT_SECTION_NAME").ok();
+        }
+        let generated = self.generate()?;
+        for file in generated.sources.iter() {
+            println!("cargo:rerun-if-changed={}", file.display());
+        }
+        let out_dir = Path::new(&env::var("OUT_DIR")?).to_path_buf();
+        fs::write(
+            out_dir.join(&generated.out_file),
+            generated.source.as_bytes(),
+        )?;
+        for (provider, manifest) in generated.manifests.iter() {
+            let manifest_file = out_dir.join(format!("{}.probes.json", provider));
+            fs::write(&manifest_file, manifest)?;
+            println!(
+                "cargo:rustc-env=USDT_MANIFEST_{}={}",
+                provider,
+                manifest_file.display()
+            );
+        }
         Ok(())
     }
+
+    /// Check that the D provider file compiles into valid Rust code, without writing anything.
+    ///
+    /// This runs the same steps as [`Builder::build`], with the same configuration, and checks the
+    /// result as [`check_provider_source`] does. It neither consults nor prints anything for Cargo,
+    /// so it may be used to check provider files quickly, e.g., in CI or in tests. On macOS,
+    /// `dtrace -h` is still run, as for [`Builder::generate`].
+    pub fn validate(&self) -> Result<(), Error> {
+        let (source, _) = self.source_text()?;
+        usdt_impl::check_generated_code(usdt_impl::compile_provider_source(&source, &self.config)?)
+    }
+
+    /// Generate the Rust code from the D provider file, returning it rather than writing it.
+    ///
+    /// This does not consult the environment set by Cargo, nor write any files, so it may be used
+    /// outside of a build script, e.g., by another build system which writes the results itself.
+    /// On macOS, `dtrace -h` is still run to determine the probe symbols, unless they are read from
+    /// a header with [`Builder::dtrace_header`] or computed with [`Builder::compute_symbols`].
+    pub fn generate(&self) -> Result<Generated, Error> {
+        let (source, sources) = self.source_text()?;
+        let tokens = usdt_impl::compile_provider_source(&source, &self.config)?;
+        let mut manifests = BTreeMap::new();
+        if self.manifest {
+            for provider in usdt_impl::parse_providers(&source)? {
+                let manifest = provider.to_manifest()?;
+                manifests.insert(provider.name, manifest);
+            }
+        }
+        Ok(Generated {
+            out_file: PathBuf::from(
+                self.out_file
+                    .file_name()
+                    .expect("Could not extract filename"),
+            ),
+            source: tokens.to_string(),
+            manifests,
+            sources,
+        })
+    }
+
+    // Return the text of the D provider definition, and the files it was read from.
+    fn source_text(&self) -> Result<(String, Vec<PathBuf>), Error> {
+        match &self.source {
+            Source::File(path) => usdt_impl::read_provider_file(path),
+            Source::Text(text) => Ok((text.clone(), Vec::new())),
+        }
+    }
+}
+
+/// The output generated for a D provider definition file by [`Builder::generate`].
+#[derive(Debug, Clone)]
+pub struct Generated {
+    /// The name of the file to which the Rust code is written by [`Builder::build`].
+    pub out_file: PathBuf,
+    /// The generated Rust code.
+    pub source: String,
+    /// The JSON manifest of each provider, keyed by the provider's name, if enabled with
+    /// [`Builder::manifest`].
+    pub manifests: BTreeMap<String, String>,
+    /// The D files read to generate the code: the provider file, followed by any files it
+    /// includes. This is empty for a builder constructed with [`Builder::from_source`].
+    pub sources: Vec<PathBuf>,
+}
+
+// The D source of a builder's providers.
+#[derive(Debug)]
+enum Source {
+    File(PathBuf),
+    Text(String),
+}
+
+// Replace any characters in a file stem other than ASCII letters and digits with `_`, so that the
+// generated files neither escape `OUT_DIR` nor contain extra dots.
+fn sanitize_file_stem(stem: &str) -> String {
+    stem.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Build every D provider definition file in a directory, in a build.rs script.
+///
+/// Each file in `dir` with the `".d"` extension is built as if by `Builder::new(file).build()`, so
+/// that the provider file `foo.d` generates the Rust file `foo.rs` in `OUT_DIR`, which may then be
+/// included as usual. Cargo is instructed to rerun the build script when any of the provider files
+/// or the files they include change, or when files are added to or removed from the directory.
+///
+/// Note that _every_ `.d` file in `dir` is built as a provider file, including any which are only
+/// meant to be `#include`d by the others. A shared file containing only translators or pragmas
+/// generates a Rust file with no probes, but one declaring providers generates their probes both
+/// on its own and in each including file. Shared files should instead be kept outside of `dir`,
+/// e.g., in a subdirectory, which is not searched.
+///
+/// An error is returned if two provider files would generate the same output file, such as
+/// `foo-bar.d` and `foo_bar.d`. File names are compared case-insensitively, since `foo.d` and
+/// `Foo.d` would otherwise clobber one another on a case-insensitive filesystem.
+pub fn build_providers_in_dir<P: AsRef<Path>>(dir: P) -> Result<(), Error> {
+    let dir = dir.as_ref();
+    let mut files = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    files.retain(|path| path.is_file() && path.extension() == Some(OsStr::new("d")));
+    files.sort();
+
+    // Check for collisions before generating anything.
+    let mut out_files = BTreeMap::new();
+    for file in files.iter() {
+        let builder = Builder::new(file);
+        let name = builder
+            .out_file
+            .file_name()
+            .expect("Could not extract filename")
+            .to_string_lossy()
+            .to_lowercase();
+        if let Some(previous) = out_files.insert(name, file) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "Provider files \"{}\" and \"{}\" generate the same output file",
+                    previous.display(),
+                    file.display(),
+                ),
+            )
+            .into());
+        }
+    }
+
+    println!("cargo:rerun-if-changed={}", dir.display());
+    for file in files.iter() {
+        Builder::new(file).build()?;
+    }
+    Ok(())
+}
+
+/// Build the D provider definitions in `source`, in a build.rs script.
+///
+/// This is shorthand for `Builder::from_source(name, source).build()`, and generates the Rust file
+/// `<name>.rs` in `OUT_DIR`, which may then be included as usual. For example:
+///
+/// ```ignore
+/// usdt::build_providers_from_source("shared", include_str!("../common/shared.d")).unwrap();
+/// ```
+pub fn build_providers_from_source(name: &str, source: &str) -> Result<(), Error> {
+    Builder::from_source(name, source).build()
+}
+
+/// Check that the D provider definitions in `file` compile into valid Rust code.
+///
+/// This is shorthand for `Builder::new(file).validate()`, and writes no files.
+pub fn validate_providers<P: AsRef<Path>>(file: P) -> Result<(), Error> {
+    Builder::new(file).validate()
 }
 
 /// Register an application's probes with DTrace.
@@ -353,3 +931,118 @@ impl Builder {
 pub fn register_probes() -> Result<(), Error> {
     usdt_impl::register_probes().map_err(Error::from)
 }
+
+/// Fire a probe with a single string argument, formatted from the given format string and
+/// arguments, as with `format!`.
+///
+/// The message is only formatted if the probe is enabled, so this is an inexpensive way to emit
+/// log-like messages via DTrace. The probe must take exactly one `char *` (or `&str`) argument.
+///
+/// ```ignore
+/// #[usdt::provider]
+/// mod my_provider {
+///     fn log(_: &str) {}
+/// }
+///
+/// let count = 2;
+/// usdt::log_probe!(my_provider::log, "processed {} requests", count);
+/// ```
+#[macro_export]
+macro_rules! log_probe {
+    ($($probe:ident)::+, $($format_args:tt)+) => {
+        $($probe)::+!(|| ::std::format!($($format_args)+))
+    };
+}
+
+/// Fire a probe only if it is enabled and the given predicate is true.
+///
+/// The predicate is only evaluated once the probe is known to be enabled, so it may be
+/// relatively expensive to compute without slowing down the program while the probe is disabled.
+/// The probe's arguments are passed as usual, with a closure, which is only called if the probe
+/// fires. This is equivalent to calling the probe macro with `if` and the predicate before its
+/// arguments, as in `my_provider::my_probe!(if predicate, || args)`.
+///
+/// ```ignore
+/// #[usdt::provider]
+/// mod my_provider {
+///     fn slow_request(_: u64, _: u64) {}
+/// }
+///
+/// usdt::probe_if!(my_provider::slow_request, elapsed > threshold, || (id, elapsed));
+/// ```
+#[macro_export]
+macro_rules! probe_if {
+    ($($probe:ident)::+, $predicate:expr) => {
+        $($probe)::+!(if $predicate, || ())
+    };
+    ($($probe:ident)::+, $predicate:expr, $args_lambda:expr) => {
+        $($probe)::+!(if $predicate, $args_lambda)
+    };
+}
+
+/// Evaluate to the address of the instruction at which this macro is expanded, as a `u64`.
+///
+/// This may be passed to a `uint64_t` probe argument so that the site which fired the probe can
+/// be symbolized in DTrace, e.g., with `usym(arg0)` or `uaddr(arg0)`, without collecting a full
+/// stack trace. The address is read directly from the program counter, rather than from the
+/// return address on the stack, and so is correct even if the enclosing function is
+/// tail-called, or itself tail-calls the probe.
+///
+/// The closure passed to a probe is a separate function, which is usually, but not necessarily,
+/// inlined into its caller. To be certain the address lies within the calling function, capture
+/// it before firing the probe.
+///
+/// ```ignore
+/// #[usdt::provider]
+/// mod my_provider {
+///     fn site(_: u64) {}
+/// }
+///
+/// let addr = usdt::caller_addr!();
+/// my_provider::site!(|| addr);
+/// ```
+///
+/// Like the probe macros, this expands to `asm!`, so the crate using it must enable
+/// `#![feature(asm)]` at its root. On architectures other than x86_64 and aarch64, or without the
+/// `asm` feature, this evaluates to zero.
+#[cfg(all(feature = "asm", target_arch = "x86_64"))]
+#[macro_export]
+macro_rules! caller_addr {
+    () => {{
+        let addr: u64;
+        #[allow(unused_unsafe)]
+        unsafe {
+            asm!(
+                "lea {}, [rip]",
+                out(reg) addr,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+        addr
+    }};
+}
+
+#[cfg(all(feature = "asm", target_arch = "aarch64"))]
+#[macro_export]
+macro_rules! caller_addr {
+    () => {{
+        let addr: u64;
+        #[allow(unused_unsafe)]
+        unsafe {
+            asm!(
+                "adr {}, .",
+                out(reg) addr,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+        addr
+    }};
+}
+
+#[cfg(not(all(feature = "asm", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+#[macro_export]
+macro_rules! caller_addr {
+    () => {
+        0u64
+    };
+}
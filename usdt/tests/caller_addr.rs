@@ -0,0 +1,49 @@
+//! Test that `caller_addr!` evaluates to an address within the function in which it's expanded.
+
+// Copyright 2021 Oxide Computer Company
+
+#![cfg(all(feature = "asm", any(target_arch = "x86_64", target_arch = "aarch64")))]
+#![cfg_attr(feature = "asm", feature(asm))]
+
+// The generous bound on the size of each function below, in bytes.
+const MAX_FN_SIZE: u64 = 4096;
+
+#[inline(never)]
+fn site() -> u64 {
+    usdt::caller_addr!()
+}
+
+// Tail-calls `site`, which would make a return-address-based capture point here, not into `site`.
+#[inline(never)]
+fn tail_calls_site() -> u64 {
+    site()
+}
+
+#[inline(never)]
+fn two_sites() -> u64 {
+    let first = usdt::caller_addr!();
+    let second = usdt::caller_addr!();
+    assert!(second > first);
+    first
+}
+
+fn assert_within(addr: u64, function: fn() -> u64) {
+    let start = function as usize as u64;
+    assert!(
+        addr >= start && addr < start + MAX_FN_SIZE,
+        "address {:#x} is not within the function at {:#x}",
+        addr,
+        start
+    );
+}
+
+#[test]
+fn test_caller_addr_within_function() {
+    assert_within(site(), site);
+    assert_within(tail_calls_site(), site);
+}
+
+#[test]
+fn test_caller_addr_distinct_sites() {
+    assert_within(two_sites(), two_sites);
+}
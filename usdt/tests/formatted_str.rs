@@ -0,0 +1,40 @@
+//! Test that `DebugStr` and `DisplayStr` only format their values when the probe is enabled.
+
+// Copyright 2021 Oxide Computer Company
+
+#![cfg(not(feature = "mock-probes"))]
+#![cfg_attr(feature = "asm", feature(asm))]
+
+use std::cell::Cell;
+use std::fmt;
+use usdt::{DebugStr, DisplayStr};
+
+// Probes with a semaphore are disabled until a tracer attaches, on every backend.
+#[usdt::provider(semaphore = true)]
+mod formatting {
+    fn value(_: &str) {}
+}
+
+struct Counted<'a>(&'a Cell<usize>);
+
+impl fmt::Debug for Counted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.set(self.0.get() + 1);
+        write!(f, "Counted")
+    }
+}
+
+impl fmt::Display for Counted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.set(self.0.get() + 1);
+        write!(f, "counted")
+    }
+}
+
+#[test]
+fn test_formatted_str_disabled_does_not_format() {
+    let count = Cell::new(0);
+    formatting::value!(|| DebugStr(Counted(&count)));
+    formatting::value!(|| DisplayStr(Counted(&count)));
+    assert_eq!(count.get(), 0);
+}
@@ -0,0 +1,31 @@
+//! Test that `log_probe!` only formats its message when the probe is enabled.
+
+// Copyright 2021 Oxide Computer Company
+
+#![cfg(not(feature = "mock-probes"))]
+#![cfg_attr(feature = "asm", feature(asm))]
+
+use std::cell::Cell;
+use std::fmt;
+
+// Probes with a semaphore are disabled until a tracer attaches, on every backend.
+#[usdt::provider(semaphore = true)]
+mod logging {
+    fn log(_: &str) {}
+}
+
+struct Counted<'a>(&'a Cell<usize>);
+
+impl fmt::Display for Counted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.set(self.0.get() + 1);
+        write!(f, "counted")
+    }
+}
+
+#[test]
+fn test_log_probe_disabled_does_not_format() {
+    let count = Cell::new(0);
+    usdt::log_probe!(logging::log, "x={}, y={}", 1, Counted(&count));
+    assert_eq!(count.get(), 0);
+}
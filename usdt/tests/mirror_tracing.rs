@@ -0,0 +1,86 @@
+//! Test that probes configured with `mirror_tracing` emit `tracing` events with their arguments.
+
+// Copyright 2021 Oxide Computer Company
+
+#![cfg(all(feature = "tracing", not(feature = "mock-probes")))]
+#![cfg_attr(feature = "asm", feature(asm))]
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use usdt::tracing::field::{Field, Visit};
+use usdt::tracing::span::{Attributes, Id, Record};
+use usdt::tracing::{Event, Metadata, Subscriber};
+
+// Probes must fire for their events to be emitted, even where DTrace is available.
+#[usdt::provider(mirror_tracing = "info", always_fire = true)]
+mod mirrored {
+    fn work(_: u8, _: &str) {}
+}
+
+// The target and fields of a captured event, with each field formatted as a string.
+type CapturedEvent = (String, BTreeMap<String, String>);
+
+// A subscriber which captures every event, and ignores spans.
+#[derive(Clone, Default)]
+struct Capture {
+    events: Arc<Mutex<Vec<CapturedEvent>>>,
+}
+
+struct FieldVisitor<'a>(&'a mut BTreeMap<String, String>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value));
+    }
+}
+
+impl Subscriber for Capture {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, _: &Attributes<'_>) -> Id {
+        Id::from_u64(1)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = BTreeMap::new();
+        event.record(&mut FieldVisitor(&mut fields));
+        let target = event.metadata().target().to_string();
+        self.events.lock().unwrap().push((target, fields));
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+#[test]
+fn test_mirror_tracing_event_fields() {
+    let capture = Capture::default();
+    let name = String::from("name");
+    usdt::tracing::subscriber::with_default(capture.clone(), || {
+        mirrored::work!(|| (7, &name));
+    });
+    let events = capture.events.lock().unwrap();
+    assert_eq!(events.len(), 1);
+    let (target, fields) = &events[0];
+    assert_eq!(target, "mirrored::work");
+    assert_eq!(fields["message"], "work");
+    assert_eq!(fields["arg0"], "7");
+    assert_eq!(fields["arg1"], "name");
+}
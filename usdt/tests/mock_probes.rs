@@ -0,0 +1,418 @@
+//! Test capturing probes with the `mock-probes` feature.
+
+// Copyright 2021 Oxide Computer Company
+
+#![cfg(feature = "mock-probes")]
+
+use usdt::mock::{take_fired_probes, FiredProbe};
+
+#[derive(Clone, serde::Serialize)]
+struct Arg {
+    x: u8,
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Idle,
+    Busy,
+}
+
+impl usdt::IntoProbeArg<u8> for State {
+    fn to_probe_arg(&self) -> u8 {
+        match self {
+            State::Idle => 0,
+            State::Busy => 1,
+        }
+    }
+}
+
+#[derive(usdt::ProbeArg)]
+#[repr(u32)]
+enum Phase {
+    Start = 10,
+    Stop = 20,
+}
+
+#[derive(usdt::ProbeArgs)]
+#[repr(C)]
+struct Req {
+    id: u64,
+    len: u32,
+}
+
+#[derive(usdt::ProbeArgs)]
+#[repr(C)]
+struct Keyed {
+    id: u32,
+    key: [u8; 8],
+}
+
+#[usdt::provider]
+mod mocked {
+    use crate::Arg;
+    fn begin(_: u8, _: &str, _: i64) {}
+    fn json(_: &Arg) {}
+    fn noargs() {}
+    fn ipv4(_: u32) {}
+    fn ipv6(_: &std::net::Ipv6Addr) {}
+    fn counts(_: u8, _: i32, _: u64) {}
+    fn phase(_: u32) {}
+    fn log(_: &str) {}
+    fn transfer(request_id: u64, bytes: u64) {}
+    fn unique(_: &usdt::UniqueId, _: u8) {}
+    fn request(_: u64) {}
+    fn key(_: char) {}
+}
+
+#[usdt::provider(with_sequence = true)]
+mod sequenced {
+    fn tick() {}
+    fn tock(_: u8, _: &str) {}
+}
+
+#[usdt::provider(with_tid = true)]
+mod threaded {
+    fn tick() {}
+    fn tock(_: u8) {}
+}
+
+#[usdt::provider(with_sequence = true, with_tid = true)]
+mod sequenced_threaded {
+    fn tock(_: u8, _: &str) {}
+}
+
+#[usdt::provider(return_status = true)]
+mod status {
+    fn ping(_: u8) {}
+}
+
+fn fired(probe: &str, args: &[&str]) -> FiredProbe {
+    FiredProbe {
+        provider: String::from("mocked"),
+        probe: String::from(probe),
+        args: args.iter().map(|arg| arg.to_string()).collect(),
+    }
+}
+
+#[test]
+fn test_mock_probes_capture_arguments_in_order() {
+    let name = String::from("name");
+    mocked::begin!(|| (1, &name, -1));
+    mocked::json!(|| Arg { x: 2 });
+    mocked::noargs!();
+    assert_eq!(
+        take_fired_probes(),
+        vec![
+            fired("begin", &["1", "name", "-1"]),
+            fired("json", &["{\"ok\":{\"x\":2}}"]),
+            fired("noargs", &[]),
+        ]
+    );
+    assert!(take_fired_probes().is_empty());
+}
+
+#[test]
+fn test_mock_probes_custom_integer_argument() {
+    let state = State::Idle;
+    mocked::begin!(|| (state, "idle", 0));
+    mocked::begin!(|| (&State::Busy, "busy", 0));
+    assert_eq!(
+        take_fired_probes(),
+        vec![
+            fired("begin", &["0", "idle", "0"]),
+            fired("begin", &["1", "busy", "0"]),
+        ]
+    );
+}
+
+#[test]
+fn test_mock_probes_ip_addresses() {
+    let v4 = std::net::Ipv4Addr::new(10, 0, 0, 1);
+    let v6 = std::net::Ipv6Addr::LOCALHOST;
+    mocked::ipv4!(|| v4);
+    mocked::ipv6!(|| &v6);
+    assert_eq!(
+        take_fired_probes(),
+        vec![
+            fired("ipv4", &["167772161"]),
+            fired("ipv6", &["{\"ok\":\"::1\"}"]),
+        ]
+    );
+
+    // IPv6 addresses may also be passed as the address of their octets.
+    let octets = usdt::Ipv6Octets::from(v6);
+    mocked::request!(|| &octets);
+    let address = &octets as *const usdt::Ipv6Octets as usize;
+    assert_eq!(
+        take_fired_probes(),
+        vec![fired("request", &[&address.to_string()])]
+    );
+}
+
+#[test]
+fn test_mock_probes_are_per_thread() {
+    mocked::noargs!();
+    std::thread::spawn(|| {
+        mocked::begin!(|| (0, "other thread", 0));
+        assert_eq!(take_fired_probes().len(), 1);
+    })
+    .join()
+    .unwrap();
+    assert_eq!(take_fired_probes(), vec![fired("noargs", &[])]);
+}
+
+#[test]
+fn test_mock_probes_raw_arguments() {
+    mocked::counts!(raw & [300, u64::MAX, 7]);
+    mocked::counts!(|| (44, -1, 7));
+    let probes = take_fired_probes();
+    assert_eq!(probes.len(), 2);
+    assert_eq!(probes[0], fired("counts", &["44", "-1", "7"]));
+    assert_eq!(probes[0], probes[1]);
+
+    mocked::ipv4!(raw & [7]);
+    assert_eq!(take_fired_probes(), vec![fired("ipv4", &["7"])]);
+}
+
+#[test]
+fn test_mock_probes_untyped_literals() {
+    // Literals take the type of the argument, rather than falling back to `i32`.
+    mocked::counts!(|| (255, -2147483648, 18446744073709551615));
+    mocked::transfer!(request_id = 18446744073709551615, bytes = 0);
+    assert_eq!(
+        take_fired_probes(),
+        vec![
+            fired("counts", &["255", "-2147483648", "18446744073709551615"]),
+            fired("transfer", &["18446744073709551615", "0"]),
+        ]
+    );
+}
+
+#[test]
+fn test_mock_probes_signal_safe() {
+    mocked::counts!(signal_safe || (1, -2, 3));
+    assert_eq!(
+        take_fired_probes(),
+        vec![fired("counts", &["1", "-2", "3"])]
+    );
+}
+
+#[test]
+fn test_mock_probes_c_string() {
+    let message = std::ffi::CString::new("from C").unwrap();
+    mocked::log!(|| message.as_c_str());
+    assert_eq!(take_fired_probes(), vec![fired("log", &["from C"])]);
+}
+
+#[test]
+fn test_mock_probes_log_probe() {
+    let x = 3;
+    usdt::log_probe!(mocked::log, "x={}, y={:?}", x, "y");
+    assert_eq!(take_fired_probes(), vec![fired("log", &["x=3, y=\"y\""])]);
+}
+
+#[usdt::provider(max_string_len = 4)]
+mod truncated {
+    fn log(_: &str) {}
+}
+
+#[test]
+fn test_mock_probes_truncated_str() {
+    // "é" is two bytes, and is dropped rather than split.
+    truncated::log!(|| "caf\u{e9}s");
+    truncated::log!(|| "abc");
+    let cstr = std::ffi::CString::new("abcdef").unwrap();
+    truncated::log!(|| cstr.as_c_str());
+    let args = take_fired_probes()
+        .into_iter()
+        .map(|probe| probe.args[0].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(args, ["caf", "abc", "abcd"]);
+}
+
+#[test]
+fn test_mock_probes_formatted_str() {
+    let req = Req { id: 1, len: 2 };
+    mocked::log!(|| usdt::DebugStr((req.id, req.len)));
+    mocked::log!(|| usdt::DisplayStr(std::net::Ipv4Addr::LOCALHOST));
+    assert_eq!(
+        take_fired_probes(),
+        vec![fired("log", &["(1, 2)"]), fired("log", &["127.0.0.1"])]
+    );
+}
+
+#[test]
+fn test_mock_probes_named_arguments() {
+    let bytes = 2;
+    mocked::transfer!(bytes = bytes, request_id = 1);
+    mocked::transfer!(request_id = 3, bytes = bytes * 2,);
+    assert_eq!(
+        take_fired_probes(),
+        vec![
+            fired("transfer", &["1", "2"]),
+            fired("transfer", &["3", "4"])
+        ]
+    );
+}
+
+#[test]
+fn test_mock_probes_unique_id() {
+    let id = usdt::UniqueId::new();
+    let expected = id.as_u64().to_string();
+    mocked::unique!(|| (&id, 1));
+    mocked::unique!(|| (id.clone(), 2));
+    assert_eq!(
+        take_fired_probes(),
+        vec![
+            fired("unique", &[expected.as_str(), "1"]),
+            fired("unique", &[expected.as_str(), "2"])
+        ]
+    );
+}
+
+#[test]
+fn test_mock_probes_derived_enum_argument() {
+    let phase = Phase::Stop;
+    mocked::phase!(|| Phase::Start);
+    mocked::phase!(|| &phase);
+    assert_eq!(
+        take_fired_probes(),
+        vec![fired("phase", &["10"]), fired("phase", &["20"])]
+    );
+}
+
+#[test]
+fn test_mock_probes_sequence_numbers() {
+    sequenced::tick!();
+    sequenced::tock!(|| (1, "one"));
+    sequenced::tick!(|| ());
+    let probes = take_fired_probes();
+    assert_eq!(probes.len(), 3);
+    assert_eq!(probes[1].args[1..], ["1", "one"]);
+    let sequence = probes
+        .iter()
+        .map(|probe| probe.args[0].parse::<u64>().unwrap())
+        .collect::<Vec<_>>();
+    assert!(sequence.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+fn test_mock_probes_return_status() {
+    // Mocked probes are always enabled, so they always report having fired.
+    assert!(status::ping!(|| 1));
+    assert!(status::ping!(raw & [2]));
+    let fired = take_fired_probes();
+    assert_eq!(fired.len(), 2);
+    assert_eq!(fired[1].args, vec!["2"]);
+}
+
+#[test]
+fn test_mock_probes_struct_address() {
+    use usdt::ProbeArgs;
+    assert_eq!(
+        Req::D_STRUCT,
+        "struct Req {\n\tuint64_t id;\n\tuint32_t len;\n};\n"
+    );
+
+    // The probe receives the address of the struct.
+    let req = Req { id: 1, len: 2 };
+    mocked::request!(|| &req);
+    let address = &req as *const Req as usize;
+    assert_eq!(
+        take_fired_probes(),
+        vec![fired("request", &[&address.to_string()])]
+    );
+    assert_eq!((req.id, req.len), (1, 2));
+}
+
+#[test]
+fn test_mock_probes_struct_array() {
+    use usdt::ProbeArgs;
+    assert_eq!(
+        Keyed::D_STRUCT,
+        "struct Keyed {\n\tuint32_t id;\n\tuint8_t key[8];\n};\n"
+    );
+
+    // The probe receives the address of the struct, and so of the array within it, which is
+    // borrowed for as long as the probe fires.
+    let keyed = Keyed {
+        id: 1,
+        key: *b"abcdefgh",
+    };
+    mocked::request!(|| &keyed);
+    let address = &keyed as *const Keyed as usize;
+    assert_eq!(
+        take_fired_probes(),
+        vec![fired("request", &[&address.to_string()])]
+    );
+    assert_eq!(
+        &keyed.key as *const u8 as usize,
+        address + std::mem::size_of::<u32>()
+    );
+    assert_eq!(keyed.id, 1);
+}
+
+#[test]
+fn test_mock_probes_thread_id() {
+    let tid = usdt::thread_id();
+    threaded::tick!();
+    threaded::tock!(|| 1);
+    sequenced_threaded::tock!(|| (2, "two"));
+    let probes = take_fired_probes();
+    assert_eq!(probes[0].args, [tid.to_string()]);
+    assert_eq!(probes[1].args, [tid.to_string(), String::from("1")]);
+    assert_eq!(
+        probes[2].args[1..],
+        [tid.to_string(), String::from("2"), String::from("two")]
+    );
+
+    // The ID is that of the thread firing the probe.
+    let (other, probes) = std::thread::spawn(|| {
+        threaded::tick!();
+        (usdt::thread_id(), take_fired_probes())
+    })
+    .join()
+    .unwrap();
+    assert_ne!(other, tid);
+    assert_eq!(probes[0].args, [other.to_string()]);
+}
+
+#[test]
+fn test_mock_probes_enabled_probes() {
+    // Mocked probes are always enabled.
+    assert_eq!(truncated::enabled_probes(), ["log"]);
+    assert_eq!(mocked::enabled_probes().len(), mocked::PROBES.len());
+}
+
+#[test]
+fn test_mock_probes_char() {
+    // "é" is two bytes in UTF-8, but the probe receives its code point.
+    mocked::key!(|| '\u{e9}');
+    mocked::ipv4!(|| 'a');
+    assert_eq!(
+        take_fired_probes(),
+        vec![fired("key", &["233"]), fired("ipv4", &["97"])]
+    );
+}
+
+#[test]
+fn test_mock_probes_probe_if() {
+    let mut evaluated = 0;
+    let mut check = |result| {
+        evaluated += 1;
+        result
+    };
+    usdt::probe_if!(mocked::request, check(false), || 1);
+    usdt::probe_if!(mocked::request, check(true), || 2);
+    mocked::request!(if check(true), || 3);
+    usdt::probe_if!(mocked::noargs, check(true));
+    assert_eq!(evaluated, 4);
+    assert_eq!(
+        take_fired_probes(),
+        vec![
+            fired("request", &["2"]),
+            fired("request", &["3"]),
+            fired("noargs", &[])
+        ]
+    );
+}
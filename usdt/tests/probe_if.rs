@@ -0,0 +1,29 @@
+//! Test that `probe_if!` only evaluates its predicate when the probe is enabled.
+
+// Copyright 2021 Oxide Computer Company
+
+#![cfg(not(feature = "mock-probes"))]
+#![cfg_attr(feature = "asm", feature(asm))]
+
+use std::cell::Cell;
+
+// Probes with a semaphore are disabled until a tracer attaches, on every backend.
+#[usdt::provider(semaphore = true)]
+mod conditional {
+    fn value(_: u64) {}
+    fn empty() {}
+}
+
+fn counted(count: &Cell<usize>) -> bool {
+    count.set(count.get() + 1);
+    true
+}
+
+#[test]
+fn test_probe_if_disabled_does_not_evaluate_predicate() {
+    let count = Cell::new(0);
+    usdt::probe_if!(conditional::value, counted(&count), || 1);
+    usdt::probe_if!(conditional::empty, counted(&count));
+    conditional::value!(if counted(&count), || 2);
+    assert_eq!(count.get(), 0);
+}
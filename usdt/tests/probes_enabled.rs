@@ -0,0 +1,43 @@
+//! Test that disabling probes process-wide prevents any probe from firing.
+//!
+//! This is a separate test binary, since the switch affects probes fired by every test.
+
+// Copyright 2021 Oxide Computer Company
+
+#![cfg(feature = "mock-probes")]
+
+use std::cell::Cell;
+use usdt::mock::take_fired_probes;
+
+#[usdt::provider]
+mod switched {
+    fn value(_: u64) {}
+    fn signal(_: u8) {}
+}
+
+#[test]
+fn test_probes_globally_disabled() {
+    assert!(usdt::probes_globally_enabled());
+
+    // Mocked probes are always enabled, as if DTrace had enabled them.
+    let evaluated = Cell::new(0);
+    let value = || {
+        evaluated.set(evaluated.get() + 1);
+        1
+    };
+    usdt::set_probes_enabled(false);
+    assert!(!usdt::probes_globally_enabled());
+    switched::value!(|| value());
+    switched::value!(raw & [2]);
+    switched::signal!(signal_safe || 3);
+    usdt::probe_if!(switched::value, true, || value());
+    assert!(switched::enabled_probes().is_empty());
+    assert_eq!(evaluated.get(), 0);
+    assert!(take_fired_probes().is_empty());
+
+    usdt::set_probes_enabled(true);
+    switched::value!(|| value());
+    assert_eq!(evaluated.get(), 1);
+    assert_eq!(take_fired_probes().len(), 1);
+    assert_eq!(switched::enabled_probes(), ["value", "signal"]);
+}